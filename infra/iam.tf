@@ -34,9 +34,18 @@ resource "aws_iam_role_policy" "lambda_s3_access" {
         Effect = "Allow"
         Action = [
           "s3:GetObject",
-          "s3:PutObject"
+          "s3:PutObject",
+          "s3:GetObjectTagging",
+          "s3:PutObjectTagging"
         ]
         Resource = "${aws_s3_bucket.email_storage.arn}/*"
+      },
+      {
+        # Lets S3 answer a missing key with 404 NoSuchKey instead of 403, so
+        # first-time lookups (rate-limit windows, aliases, threads) succeed
+        Effect   = "Allow"
+        Action   = "s3:ListBucket"
+        Resource = aws_s3_bucket.email_storage.arn
       }
     ]
   })
@@ -20,11 +20,39 @@ resource "aws_lambda_function" "email_processor" {
 
   environment {
     variables = {
-      EMAIL_BUCKET      = aws_s3_bucket.email_storage.bucket
-      INCOMING_PREFIX   = var.email_general_prefix
-      FORWARD_TO_EMAIL  = var.forward_to_email
-      MAX_EMAIL_SIZE_MB = var.max_email_size_mb
-      RUST_LOG          = var.log_level
+      EMAIL_BUCKET              = aws_s3_bucket.email_storage.bucket
+      INCOMING_PREFIX           = var.email_general_prefix
+      FORWARD_TO_EMAIL          = var.forward_to_email
+      MAX_EMAIL_SIZE_MB         = var.max_email_size_mb
+      PROCESSED_PREFIX          = var.email_processed_prefix
+      FAILED_PREFIX             = var.email_failed_prefix
+      DEAD_LETTER_PREFIX        = var.email_dead_letter_prefix
+      FORWARDER_EMAIL           = "forwarder@${var.domain_name}"
+      BOUNCE_PREFIX             = var.email_bounce_prefix
+      SRS_SECRET                = var.srs_secret
+      RELAY_SECRET              = var.relay_secret
+      RELAY_FROM_EMAIL          = var.relay_from_email
+      ALIAS_PREFIX              = var.email_alias_prefix
+      KNOWN_RECIPIENTS          = join(",", var.known_recipients)
+      UNKNOWN_RECIPIENT_POLICY  = var.unknown_recipient_policy
+      UNKNOWN_RECIPIENT_PREFIX  = var.email_unknown_recipient_prefix
+      ATTACHMENT_PREFIX         = var.email_attachment_prefix
+      ATTACHMENT_POLICY         = var.attachment_policy
+      CLAMD_ADDRESS             = var.clamd_address
+      CLAMD_SCAN                = var.clamd_scan
+      INDEX_PREFIX              = var.email_index_prefix
+      THREAD_PREFIX             = var.email_thread_prefix
+      ARC_SELECTOR              = var.arc_selector
+      ARC_PRIVATE_KEY           = var.arc_private_key
+      ROUTES                    = jsonencode(var.routes)
+      CLASSIFIER_RULES          = length(var.classifier_rules) > 0 ? jsonencode(var.classifier_rules) : ""
+      SENDER_RULES              = jsonencode(var.sender_rules)
+      QUARANTINE_PREFIX         = var.email_quarantine_prefix
+      RATE_LIMIT_PER_SENDER     = var.rate_limit_per_sender == null ? "" : tostring(var.rate_limit_per_sender)
+      RATE_LIMIT_PER_DOMAIN     = var.rate_limit_per_domain == null ? "" : tostring(var.rate_limit_per_domain)
+      RATE_LIMIT_WINDOW_MINUTES = var.rate_limit_window_minutes
+      AUTO_RESPONDER            = var.auto_responder == null ? "" : jsonencode({ for k, v in var.auto_responder : k => v if v != null })
+      RUST_LOG                  = var.log_level
     }
   }
 
@@ -39,4 +67,29 @@ resource "aws_lambda_function" "email_processor" {
   }
 }
 
+# Send "N messages held" notices once a rate-limited burst has ended, without
+# waiting for the sender's next message
+resource "aws_cloudwatch_event_rule" "held_notices" {
+  count = var.rate_limit_per_sender == null && var.rate_limit_per_domain == null ? 0 : 1
 
+  name                = "${var.project_name}-held-notices"
+  description         = "Send held-mail notices for rate-limited bursts that have ended"
+  schedule_expression = var.held_notice_schedule
+}
+
+resource "aws_cloudwatch_event_target" "held_notices" {
+  count = length(aws_cloudwatch_event_rule.held_notices)
+
+  rule = aws_cloudwatch_event_rule.held_notices[0].name
+  arn  = aws_lambda_function.email_processor.arn
+}
+
+resource "aws_lambda_permission" "held_notices_invoke" {
+  count = length(aws_cloudwatch_event_rule.held_notices)
+
+  statement_id  = "AllowExecutionFromEventBridge"
+  action        = "lambda:InvokeFunction"
+  function_name = aws_lambda_function.email_processor.function_name
+  principal     = "events.amazonaws.com"
+  source_arn    = aws_cloudwatch_event_rule.held_notices[0].arn
+}
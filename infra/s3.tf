@@ -57,6 +57,77 @@ resource "aws_s3_bucket_lifecycle_configuration" "email_storage" {
     }
   }
 
+  rule {
+    id     = "processed_emails"
+    status = "Enabled"
+
+    filter {
+      prefix = "${var.email_processed_prefix}/"
+    }
+
+    expiration {
+      days = 90
+    }
+
+    noncurrent_version_expiration {
+      noncurrent_days = 30
+    }
+  }
+
+  # Failed messages are kept longer so they can be triaged and re-forwarded
+  rule {
+    id     = "failed_emails"
+    status = "Enabled"
+
+    filter {
+      prefix = "${var.email_failed_prefix}/"
+    }
+
+    expiration {
+      days = 180
+    }
+
+    noncurrent_version_expiration {
+      noncurrent_days = 30
+    }
+  }
+
+  # Mail to unknown recipients under the drop policy is not kept past a week
+  rule {
+    id     = "dropped_unknown_recipients"
+    status = "Enabled"
+
+    filter {
+      and {
+        prefix = "${var.email_general_prefix}/"
+        tags = {
+          skip-reason = "unknown-recipient-dropped"
+        }
+      }
+    }
+
+    expiration {
+      days = 7
+    }
+
+    noncurrent_version_expiration {
+      noncurrent_days = 1
+    }
+  }
+
+  rule {
+    id     = "unknown_recipient_counts"
+    status = "Enabled"
+
+    filter {
+      prefix = "${var.email_unknown_recipient_prefix}/"
+    }
+
+    expiration {
+      days = 90
+    }
+  }
+
   rule {
     id     = "reports_cleanup"
     status = "Enabled"
@@ -42,6 +42,17 @@ resource "aws_route53_record" "dkim" {
   records = ["${aws_ses_domain_dkim.main.dkim_tokens[count.index]}.dkim.amazonses.com"]
 }
 
+# Public key for ARC seals added by the email processor
+resource "aws_route53_record" "arc" {
+  count   = var.arc_selector != "" && var.arc_public_key != "" ? 1 : 0
+  zone_id = data.aws_route53_zone.main.zone_id
+  name    = "${var.arc_selector}._domainkey"
+  type    = "TXT"
+  ttl     = 600
+  # TXT strings are limited to 255 characters; a 2048-bit key needs two
+  records = [join("\"\"", regexall(".{1,255}", "v=DKIM1; k=rsa; p=${var.arc_public_key}"))]
+}
+
 # DMARC record for email authentication policy
 resource "aws_route53_record" "dmarc" {
   zone_id = data.aws_route53_zone.main.zone_id
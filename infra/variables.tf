@@ -34,6 +34,165 @@ variable "email_reports_prefix" {
   default     = "reports"
 }
 
+variable "email_processed_prefix" {
+  description = "Bucket prefix to copy processed (forwarded or skipped) email to; empty disables the copy"
+  type        = string
+  default     = "processed"
+}
+
+variable "email_failed_prefix" {
+  description = "Bucket prefix to copy email that failed to forward to; empty disables the copy"
+  type        = string
+  default     = "failed"
+}
+
+variable "email_dead_letter_prefix" {
+  description = "Bucket prefix for failure records of messages that could not be forwarded"
+  type        = string
+  default     = "dead-letter"
+}
+
+variable "email_bounce_prefix" {
+  description = "Bucket prefix for records of bounces to SRS return addresses"
+  type        = string
+  default     = "bounces"
+}
+
+variable "srs_secret" {
+  description = "Key for signing SRS return addresses on forwarded mail (empty disables SRS)"
+  type        = string
+  default     = ""
+  sensitive   = true
+}
+
+variable "relay_secret" {
+  description = "Key for reply+ relay addresses and per-conversation aliases; replies from forward_to_email then go out from the domain (empty disables the relay)"
+  type        = string
+  default     = ""
+  sensitive   = true
+}
+
+variable "email_alias_prefix" {
+  description = "Bucket prefix for per-conversation reply aliases"
+  type        = string
+  default     = "aliases"
+}
+
+variable "relay_from_email" {
+  description = "Sender address of relayed replies (empty uses the forwarder address)"
+  type        = string
+  default     = ""
+}
+
+variable "email_attachment_prefix" {
+  description = "Bucket prefix for attachments extracted from accepted mail, one prefix per message (empty disables extraction)"
+  type        = string
+  default     = "attachments"
+}
+
+variable "email_index_prefix" {
+  description = "Bucket prefix for the JSON Lines index of processed mail, partitioned by day (empty disables the index)"
+  type        = string
+  default     = "index"
+}
+
+variable "email_thread_prefix" {
+  description = "Bucket prefix for the conversation thread index built from Message-ID, In-Reply-To and References (empty disables threading)"
+  type        = string
+  default     = "threads"
+}
+
+variable "attachment_policy" {
+  description = "Executables, scripts, macro documents and archives holding them: forward, strip (replace with a notice) or quarantine the message"
+  type        = string
+  default     = "strip"
+
+  validation {
+    condition     = contains(["forward", "strip", "quarantine"], var.attachment_policy)
+    error_message = "attachment_policy must be forward, strip or quarantine."
+  }
+}
+
+variable "clamd_address" {
+  description = "clamd to scan mail with over INSTREAM, as tcp://host:3310 or unix:///path (empty disables scanning; the Lambda must be able to reach it)"
+  type        = string
+  default     = ""
+}
+
+variable "clamd_scan" {
+  description = "What to send to clamd: message (the raw message) or attachments (each decoded attachment)"
+  type        = string
+  default     = "message"
+
+  validation {
+    condition     = contains(["message", "attachments"], var.clamd_scan)
+    error_message = "clamd_scan must be message or attachments."
+  }
+}
+
+variable "known_recipients" {
+  description = "Local parts or addresses that receive mail besides the route addresses (empty treats every recipient as known)"
+  type        = list(string)
+  default     = []
+}
+
+variable "unknown_recipient_policy" {
+  description = "Mail to no known recipient: catch-all (forward on the catch-all route), drop, or store-only"
+  type        = string
+  default     = "catch-all"
+
+  validation {
+    condition     = contains(["catch-all", "drop", "store-only"], var.unknown_recipient_policy)
+    error_message = "unknown_recipient_policy must be catch-all, drop or store-only."
+  }
+}
+
+variable "email_unknown_recipient_prefix" {
+  description = "Bucket prefix for daily hit counts of unknown recipients"
+  type        = string
+  default     = "unknown-recipients"
+}
+
+variable "arc_selector" {
+  description = "DKIM selector for ARC seals on forwarded mail (empty disables ARC). Mail that already carries an ARC chain, e.g. from a mailing list, is forwarded unsealed: existing chains are not validated or extended"
+  type        = string
+  default     = ""
+}
+
+variable "arc_private_key" {
+  description = "PEM RSA private key for ARC seals"
+  type        = string
+  default     = ""
+  sensitive   = true
+}
+
+variable "arc_public_key" {
+  description = "Base64 public key published at <arc_selector>._domainkey for ARC verification"
+  type        = string
+  default     = ""
+}
+
+variable "routes" {
+  description = "Per-address routes for forwarded mail, e.g. { name = \"booking\", addresses = [\"booking\", \"gigs\"], subject_tag = \"[booking]\" }; addresses may name a subaddress (\"info+gigsalad\") or any subaddress (\"info+*\"), and subject tags may use {{subaddress}}"
+  type = list(object({
+    name        = string
+    addresses   = list(string)
+    subject_tag = optional(string)
+  }))
+  default = []
+}
+
+variable "classifier_rules" {
+  description = "Keyword/regex rules that assign a category to inbound mail (empty disables classification)"
+  type = list(object({
+    category    = string
+    keywords    = optional(list(string), [])
+    patterns    = optional(list(string), [])
+    subject_tag = optional(string)
+  }))
+  default = []
+}
+
 variable "forward_to_email" {
   description = "Gmail address to forward emails to"
   type        = string
@@ -74,3 +233,57 @@ variable "info_alarm_email" {
   type        = string
   default     = "alerts-info@jimmillerdrums.com"
 }
+
+variable "sender_rules" {
+  description = "Sender allow/block rules, e.g. { action = \"drop\", field = \"domain\", pattern = \"*.seo-experts.example\" }"
+  type = list(object({
+    action  = string
+    field   = string
+    pattern = string
+  }))
+  default = []
+}
+
+variable "email_quarantine_prefix" {
+  description = "Bucket prefix messages quarantined by policy are copied to"
+  type        = string
+  default     = "quarantine"
+}
+
+variable "rate_limit_per_sender" {
+  description = "Messages forwarded per sender address per window before the rest are held (null disables)"
+  type        = number
+  default     = null
+}
+
+variable "rate_limit_per_domain" {
+  description = "Messages forwarded per sender domain per window before the rest are held (null disables)"
+  type        = number
+  default     = null
+}
+
+variable "rate_limit_window_minutes" {
+  description = "Length of the sliding rate limit window"
+  type        = number
+  default     = 60
+}
+
+variable "held_notice_schedule" {
+  description = "EventBridge schedule on which notices are sent for rate-limited bursts that have ended (only created when a rate limit is set)"
+  type        = string
+  default     = "rate(15 minutes)"
+}
+
+variable "auto_responder" {
+  description = "Vacation auto-reply for selected routes; subject, body and html may use {{sender_name}}, {{subject}}, {{since}} and {{until}}; without a body the built-in template is sent (null disables)"
+  type = object({
+    routes        = optional(list(string), [])
+    since         = optional(string)
+    until         = optional(string)
+    subject       = optional(string)
+    body          = optional(string)
+    html          = optional(string)
+    interval_days = optional(number)
+  })
+  default = null
+}
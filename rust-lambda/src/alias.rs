@@ -0,0 +1,305 @@
+use crate::aws::{AppContext, AwsError};
+use crate::config::Config;
+use crate::domain::S3Key;
+use crate::email::extract_reply_to_info;
+use crate::relay::RELAY_PREFIX;
+use crate::store::StoreError;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use mailparse::{parse_headers, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{info, warn};
+
+/// Hex characters in an alias id
+const ALIAS_ID_LEN: usize = 16;
+/// Correspondent message ids kept per conversation for `References`
+const MAX_REFERENCES: usize = 20;
+
+/// A masked address standing in for one correspondent in one conversation,
+/// kept under `alias_prefix`. Forwarded messages carry it as their
+/// `Reply-To`; replies to it are relayed from `our_address`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationAlias {
+    /// The alias address, e.g. `reply+0123456789abcdef@jimmillerdrums.com`
+    pub alias: String,
+    pub correspondent: String,
+    /// Our address the correspondent wrote to; relayed replies come from it
+    pub our_address: String,
+    /// Message-ID that started the conversation, when known
+    pub thread: Option<String>,
+    /// The correspondent's message ids, oldest first
+    #[serde(default)]
+    pub references: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    #[serde(default)]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ConversationAlias {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    /// `In-Reply-To` and `References` for a reply in this conversation, so it
+    /// threads with the messages the correspondent actually sent
+    pub fn thread_headers(&self) -> Vec<(String, String)> {
+        let Some(last) = self.references.last() else {
+            return Vec::new();
+        };
+        vec![
+            ("In-Reply-To".to_string(), last.clone()),
+            ("References".to_string(), self.references.join(" ")),
+        ]
+    }
+}
+
+/// Id of the alias for `correspondent` writing to `our_address` in `thread`
+pub fn alias_id(
+    secret: &str,
+    correspondent: &str,
+    our_address: &str,
+    thread: Option<&str>,
+) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(b"alias:");
+    mac.update(correspondent.to_lowercase().as_bytes());
+    mac.update(b"\n");
+    mac.update(our_address.to_lowercase().as_bytes());
+    mac.update(b"\n");
+    mac.update(thread.unwrap_or_default().as_bytes());
+    mac.finalize().into_bytes()[..ALIAS_ID_LEN / 2]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Alias id of a `reply+<id>@` address; `None` for anything else, including
+/// the signed `reply+<hash>=<domain>=<local>@` form
+pub fn alias_id_from_address(address: &str) -> Option<String> {
+    let address = address.trim();
+    let local_part = address
+        .rsplit_once('@')
+        .map(|(local, _)| local)
+        .unwrap_or(address);
+    let id = local_part
+        .get(..RELAY_PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(RELAY_PREFIX))
+        .map(|_| &local_part[RELAY_PREFIX.len()..])?;
+    (id.len() == ALIAS_ID_LEN && id.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| id.to_lowercase())
+}
+
+/// Message that started the thread `raw_email` belongs to: the first
+/// `References` entry, else `In-Reply-To`, else its own `Message-ID`
+fn thread_root(raw_email: &[u8]) -> Option<String> {
+    let (headers, _) = parse_headers(raw_email).ok()?;
+    headers
+        .get_first_value("References")
+        .and_then(|value| value.split_whitespace().next().map(str::to_string))
+        .or_else(|| headers.get_first_value("In-Reply-To"))
+        .or_else(|| headers.get_first_value("Message-ID"))
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+fn alias_key(config: &Config, id: &str) -> Result<S3Key, StoreError> {
+    S3Key::try_from(format!("{}/{}.json", config.alias_prefix, id))
+        .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+pub async fn load_alias(
+    context: &AppContext,
+    config: &Config,
+    id: &str,
+) -> Result<Option<ConversationAlias>, AwsError> {
+    let key = alias_key(config, id)?;
+    match context.store.get_object(&config.email_bucket, &key).await {
+        Ok(body) => Ok(Some(serde_json::from_slice(&body).map_err(|e| {
+            StoreError::Backend(format!("Invalid alias record {}: {}", key, e))
+        })?)),
+        Err(StoreError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn save_alias(
+    context: &AppContext,
+    config: &Config,
+    alias: &ConversationAlias,
+) -> Result<(), AwsError> {
+    let id = alias_id_from_address(&alias.alias)
+        .ok_or_else(|| StoreError::Backend(format!("Not an alias: {}", alias.alias)))?;
+    let key = alias_key(config, &id)?;
+    let body = serde_json::to_vec_pretty(alias)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode alias: {}", e)))?;
+    Ok(context
+        .store
+        .put_object(&config.email_bucket, &key, body)
+        .await?)
+}
+
+/// The alias to put in the `Reply-To` of a forwarded message, creating it on
+/// the first message of a conversation and recording the message's id for
+/// threading. `None` when the conversation's alias was revoked, or the
+/// sender is the forwarding mailbox itself.
+pub async fn conversation_alias(
+    context: &AppContext,
+    config: &Config,
+    secret: &str,
+    raw_email: &[u8],
+    our_address: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<ConversationAlias>, AwsError> {
+    let (correspondent, _) = extract_reply_to_info(raw_email)?;
+    let correspondent = correspondent.trim().to_lowercase();
+    if !correspondent.contains('@') || correspondent.eq_ignore_ascii_case(&config.forward_to_email)
+    {
+        return Ok(None);
+    }
+
+    let thread = thread_root(raw_email);
+    let id = alias_id(secret, &correspondent, our_address, thread.as_deref());
+    let mut alias = match load_alias(context, config, &id).await? {
+        Some(alias) if alias.is_revoked() => return Ok(None),
+        Some(alias) => alias,
+        None => {
+            info!("New conversation alias {} for {}", id, correspondent);
+            ConversationAlias {
+                alias: format!("{}{}@{}", RELAY_PREFIX, id, config.forwarder_domain()),
+                correspondent,
+                our_address: our_address.to_lowercase(),
+                thread,
+                references: Vec::new(),
+                created_at: now,
+                last_used_at: now,
+                revoked_at: None,
+            }
+        }
+    };
+
+    let message_id = parse_headers(raw_email)
+        .ok()
+        .and_then(|(headers, _)| headers.get_first_value("Message-ID"))
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty());
+    if let Some(message_id) = message_id {
+        if !alias.references.contains(&message_id) {
+            alias.references.push(message_id);
+        }
+        // Keep the thread root and the most recent messages
+        if alias.references.len() > MAX_REFERENCES {
+            alias
+                .references
+                .drain(1..alias.references.len() - MAX_REFERENCES + 1);
+        }
+    }
+    alias.last_used_at = now;
+    save_alias(context, config, &alias).await?;
+
+    Ok(Some(alias))
+}
+
+/// Every stored alias, most recently used first
+pub async fn list_aliases(
+    context: &AppContext,
+    config: &Config,
+) -> Result<Vec<ConversationAlias>, AwsError> {
+    let prefix = format!("{}/", config.alias_prefix);
+    let mut aliases = Vec::new();
+    for object in context
+        .store
+        .list_objects(&config.email_bucket, &prefix)
+        .await?
+    {
+        let body = context
+            .store
+            .get_object(&config.email_bucket, &object.key)
+            .await?;
+        match serde_json::from_slice::<ConversationAlias>(&body) {
+            Ok(alias) => aliases.push(alias),
+            Err(e) => warn!("Skipping invalid alias record {}: {}", object.key, e),
+        }
+    }
+    aliases.sort_by_key(|alias| std::cmp::Reverse(alias.last_used_at));
+    Ok(aliases)
+}
+
+/// Stop relaying replies through an alias (given as an address or an id).
+/// Later mail in the conversation is forwarded with its own `Reply-To`.
+pub async fn revoke_alias(
+    context: &AppContext,
+    config: &Config,
+    alias: &str,
+    now: DateTime<Utc>,
+) -> Result<ConversationAlias, AwsError> {
+    let id = alias_id_from_address(alias)
+        .or_else(|| alias_id_from_address(&format!("{}{}", RELAY_PREFIX, alias)))
+        .ok_or_else(|| StoreError::NotFound(alias.to_string()))?;
+    let mut record = load_alias(context, config, &id)
+        .await?
+        .ok_or_else(|| StoreError::NotFound(alias.to_string()))?;
+
+    if record.revoked_at.is_none() {
+        record.revoked_at = Some(now);
+        save_alias(context, config, &record).await?;
+        info!(
+            "Revoked alias {} for {}",
+            record.alias, record.correspondent
+        );
+    }
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alias_ids() {
+        let id = alias_id(
+            "secret",
+            "Fan@Example.com",
+            "booking@jimmillerdrums.com",
+            None,
+        );
+        assert_eq!(id.len(), 16);
+        assert_eq!(
+            id,
+            alias_id(
+                "secret",
+                "fan@example.com",
+                "BOOKING@jimmillerdrums.com",
+                None
+            )
+        );
+        assert_ne!(
+            id,
+            alias_id(
+                "secret",
+                "fan@example.com",
+                "booking@jimmillerdrums.com",
+                Some("<a@b>")
+            )
+        );
+
+        let address = format!("reply+{}@jimmillerdrums.com", id.to_uppercase());
+        assert_eq!(alias_id_from_address(&address), Some(id));
+        assert_eq!(
+            alias_id_from_address("reply+1a2b3c4d=example.com=fan@jimmillerdrums.com"),
+            None
+        );
+        assert_eq!(alias_id_from_address("info@jimmillerdrums.com"), None);
+    }
+
+    #[test]
+    fn test_thread_root() {
+        let reply = b"Message-ID: <3@example.com>\r\nIn-Reply-To: <2@example.com>\r\nReferences: <1@example.com> <2@example.com>\r\n\r\nHi";
+        assert_eq!(thread_root(reply).as_deref(), Some("<1@example.com>"));
+        let first = b"Message-ID: <1@example.com>\r\n\r\nHi";
+        assert_eq!(thread_root(first).as_deref(), Some("<1@example.com>"));
+        assert_eq!(thread_root(b"Subject: Hi\r\n\r\nHi"), None);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::auth::{authserv_id, AUTHENTICATION_RESULTS_HEADER};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use mailparse::{parse_headers, MailHeader};
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Headers covered by `ARC-Message-Signature`, when present. Message-ID is
+/// left out because SES assigns a new one after we sign.
+const SIGNED_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "reply-to",
+    "subject",
+    "date",
+    "mime-version",
+    "content-type",
+];
+
+#[derive(Error, Debug)]
+pub enum ArcError {
+    #[error("Invalid ARC signing key: {0}")]
+    InvalidKey(String),
+    #[error("Failed to parse message for sealing: {0}")]
+    ParseError(#[from] mailparse::MailParseError),
+    #[error("Message already carries an ARC chain, which is not validated or extended")]
+    ExistingChain,
+    #[error("Message has no header/body boundary")]
+    InvalidStructure,
+    #[error("Signing failed")]
+    SigningFailed,
+}
+
+/// Adds an ARC set (RFC 8617) to forwarded messages, signed as
+/// `d=<domain>; s=<selector>` with an RSA key
+#[derive(Clone)]
+pub struct ArcSigner {
+    domain: String,
+    selector: String,
+    key: Arc<RsaKeyPair>,
+}
+
+impl fmt::Debug for ArcSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArcSigner")
+            .field("domain", &self.domain)
+            .field("selector", &self.selector)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ArcSigner {
+    /// Load an RSA key given as PEM (PKCS#8 or PKCS#1) or as base64 DER
+    pub fn new(domain: &str, selector: &str, private_key: &str) -> Result<Self, ArcError> {
+        let encoded: String = private_key
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = BASE64
+            .decode(encoded)
+            .map_err(|e| ArcError::InvalidKey(e.to_string()))?;
+        let key = RsaKeyPair::from_pkcs8(&der)
+            .or_else(|_| RsaKeyPair::from_der(&der))
+            .map_err(|e| ArcError::InvalidKey(e.to_string()))?;
+
+        Ok(Self {
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            key: Arc::new(key),
+        })
+    }
+
+    /// Public key as published in the `<selector>._domainkey` TXT record
+    pub fn public_key(&self) -> &[u8] {
+        self.key.public().as_ref()
+    }
+
+    /// Prepend `ARC-Seal`, `ARC-Message-Signature` and
+    /// `ARC-Authentication-Results` (instance 1). The results are copied from
+    /// the message's `Authentication-Results` for `authserv_id`. A message
+    /// that already carries a chain is refused: extending it needs the prior
+    /// sets validated to set `cv=`, which would take DNS lookups.
+    pub fn seal(
+        &self,
+        message: &[u8],
+        authserv_id_value: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u8>, ArcError> {
+        let (headers, body_start) = parse_headers(message)?;
+        if headers
+            .iter()
+            .any(|h| h.get_key_ref().eq_ignore_ascii_case("ARC-Seal"))
+        {
+            return Err(ArcError::ExistingChain);
+        }
+        if body_start > message.len() {
+            return Err(ArcError::InvalidStructure);
+        }
+
+        let results = headers
+            .iter()
+            .filter(|h| {
+                h.get_key_ref()
+                    .eq_ignore_ascii_case(AUTHENTICATION_RESULTS_HEADER)
+            })
+            .map(|h| h.get_value())
+            .find(|value| authserv_id(value).eq_ignore_ascii_case(authserv_id_value))
+            .and_then(|value| {
+                value
+                    .split_once(';')
+                    .map(|(_, results)| results.trim().to_string())
+            })
+            .unwrap_or_else(|| "none".to_string());
+        let aar = format!("i=1; {}; {}", authserv_id_value, results);
+
+        let timestamp = now.timestamp();
+        let signed: Vec<&MailHeader> = signed_headers(&headers);
+        let names: Vec<String> = signed.iter().map(|h| h.get_key().to_lowercase()).collect();
+        let body_hash = BASE64.encode(Sha256::digest(relaxed_body(&message[body_start..])));
+
+        let ams_unsigned = format!(
+            "i=1; a=rsa-sha256; c=relaxed/relaxed; d={}; s={}; t={}; h={}; bh={}; b=",
+            self.domain,
+            self.selector,
+            timestamp,
+            names.join(":"),
+            body_hash
+        );
+        let mut ams_input = String::new();
+        for header in &signed {
+            ams_input.push_str(&relaxed_header(
+                &header.get_key(),
+                &String::from_utf8_lossy(header.get_value_raw()),
+            ));
+        }
+        ams_input.push_str(relaxed_header("ARC-Message-Signature", &ams_unsigned).trim_end());
+        let ams = format!("{}{}", ams_unsigned, self.sign(ams_input.as_bytes())?);
+
+        let seal_unsigned = format!(
+            "i=1; a=rsa-sha256; t={}; cv=none; d={}; s={}; b=",
+            timestamp, self.domain, self.selector
+        );
+        let mut seal_input = relaxed_header("ARC-Authentication-Results", &aar);
+        seal_input.push_str(&relaxed_header("ARC-Message-Signature", &ams));
+        seal_input.push_str(relaxed_header("ARC-Seal", &seal_unsigned).trim_end());
+        let seal = format!("{}{}", seal_unsigned, self.sign(seal_input.as_bytes())?);
+
+        let mut sealed = format!(
+            "ARC-Seal: {}\r\nARC-Message-Signature: {}\r\nARC-Authentication-Results: {}\r\n",
+            seal, ams, aar
+        )
+        .into_bytes();
+        sealed.extend_from_slice(message);
+        Ok(sealed)
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<String, ArcError> {
+        let mut signature = vec![0; self.key.public().modulus_len()];
+        self.key
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                data,
+                &mut signature,
+            )
+            .map_err(|_| ArcError::SigningFailed)?;
+        Ok(BASE64.encode(signature))
+    }
+}
+
+/// Headers to sign in [`SIGNED_HEADERS`] order, taking the last instance of
+/// each name as DKIM verifiers do
+fn signed_headers<'a>(headers: &'a [MailHeader<'a>]) -> Vec<&'a MailHeader<'a>> {
+    SIGNED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            headers
+                .iter()
+                .rev()
+                .find(|h| h.get_key_ref().eq_ignore_ascii_case(name))
+        })
+        .collect()
+}
+
+/// DKIM "relaxed" header canonicalization (RFC 6376 section 3.4.2)
+fn relaxed_header(name: &str, value: &str) -> String {
+    let unfolded = value.replace(['\r', '\n'], "");
+    let value = unfolded.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!("{}:{}\r\n", name.trim().to_lowercase(), value)
+}
+
+/// DKIM "relaxed" body canonicalization (RFC 6376 section 3.4.4), on bytes
+/// so 8-bit bodies hash as sent
+fn relaxed_body(body: &[u8]) -> Vec<u8> {
+    let mut lines: Vec<Vec<u8>> = body
+        .split(|&b| b == b'\n')
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let mut canonical = Vec::with_capacity(line.len());
+            let mut in_whitespace = false;
+            for &b in line {
+                if b == b' ' || b == b'\t' {
+                    in_whitespace = true;
+                } else {
+                    if in_whitespace {
+                        canonical.push(b' ');
+                        in_whitespace = false;
+                    }
+                    canonical.push(b);
+                }
+            }
+            canonical
+        })
+        .collect();
+    while lines.last().is_some_and(Vec::is_empty) {
+        lines.pop();
+    }
+
+    let mut canonical = Vec::with_capacity(body.len());
+    for line in lines {
+        canonical.extend_from_slice(&line);
+        canonical.extend_from_slice(b"\r\n");
+    }
+    canonical
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use mailparse::MailHeaderMap;
+    use ring::signature::{UnparsedPublicKey, RSA_PKCS1_2048_8192_SHA256};
+
+    const TEST_KEY: &str = include_str!("../tests/fixtures/arc_test_key.pem");
+
+    fn signer() -> ArcSigner {
+        ArcSigner::new("jimmillerdrums.com", "arc1", TEST_KEY).unwrap()
+    }
+
+    fn tag<'a>(value: &'a str, name: &str) -> &'a str {
+        value
+            .split(';')
+            .filter_map(|tag| tag.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_relaxed_canonicalization() {
+        assert_eq!(
+            relaxed_header("Subject ", " Hello \r\n\t world  "),
+            "subject:Hello world\r\n"
+        );
+        assert_eq!(relaxed_body(b"a  b \r\n\r\n\r\n"), b"a b\r\n");
+        assert_eq!(relaxed_body(b""), b"");
+    }
+
+    #[test]
+    fn test_seal_signatures_verify() {
+        let message = b"Authentication-Results: jimmillerdrums.com; spf=pass smtp.mailfrom=fan@example.com\r\nFrom: fan@example.com\r\nTo: me@gmail.com\r\nSubject: Hi\r\n\r\nBody\r\n";
+        let now = Utc.with_ymd_and_hms(2026, 3, 14, 9, 30, 0).unwrap();
+
+        let signer = signer();
+        let sealed = signer.seal(message, "jimmillerdrums.com", now).unwrap();
+        assert!(sealed.ends_with(message));
+
+        let (headers, _) = parse_headers(&sealed).unwrap();
+        let aar = headers
+            .get_first_value("ARC-Authentication-Results")
+            .unwrap();
+        assert_eq!(
+            aar,
+            "i=1; jimmillerdrums.com; spf=pass smtp.mailfrom=fan@example.com"
+        );
+
+        let public_key =
+            UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, signer.public_key().to_vec());
+
+        let ams = headers.get_first_value("ARC-Message-Signature").unwrap();
+        assert_eq!(tag(&ams, "h"), "from:to:subject");
+        let mut ams_input =
+            String::from("from:fan@example.com\r\nto:me@gmail.com\r\nsubject:Hi\r\n");
+        let ams_unsigned = &ams[..ams.rfind("b=").unwrap() + 2];
+        ams_input.push_str(relaxed_header("ARC-Message-Signature", ams_unsigned).trim_end());
+        let signature = BASE64.decode(tag(&ams, "b")).unwrap();
+        assert!(public_key.verify(ams_input.as_bytes(), &signature).is_ok());
+
+        let seal = headers.get_first_value("ARC-Seal").unwrap();
+        assert_eq!(tag(&seal, "cv"), "none");
+        let mut seal_input = relaxed_header("ARC-Authentication-Results", &aar);
+        seal_input.push_str(&relaxed_header("ARC-Message-Signature", &ams));
+        let seal_unsigned = &seal[..seal.rfind("b=").unwrap() + 2];
+        seal_input.push_str(relaxed_header("ARC-Seal", seal_unsigned).trim_end());
+        let signature = BASE64.decode(tag(&seal, "b")).unwrap();
+        assert!(public_key.verify(seal_input.as_bytes(), &signature).is_ok());
+    }
+
+    /// Seal of the message in `test_seal_signatures_verify`, canonicalized
+    /// by hand and signed with `openssl dgst -sha256 -sign` using the test
+    /// key. RSA PKCS#1 v1.5 signatures are deterministic, so the output must
+    /// match byte for byte.
+    #[test]
+    fn test_seal_matches_independent_vector() {
+        let message = b"Authentication-Results: jimmillerdrums.com; spf=pass smtp.mailfrom=fan@example.com\r\nFrom: fan@example.com\r\nTo: me@gmail.com\r\nSubject: Hi\r\n\r\nBody\r\n";
+        let now = Utc.with_ymd_and_hms(2026, 3, 14, 9, 30, 0).unwrap();
+
+        let sealed = signer().seal(message, "jimmillerdrums.com", now).unwrap();
+        let headers = String::from_utf8(sealed[..sealed.len() - message.len()].to_vec()).unwrap();
+        let expected = "ARC-Seal: i=1; a=rsa-sha256; t=1773480600; cv=none; d=jimmillerdrums.com; s=arc1; \
+b=M/id6w1tHfFJe8kTs4k9vtg/PMi0zg5Pg0icfzch06wMYck84B2xiThLDmfYfbM6ac+c2HyBv4gslYEROoYGeC/ZwU58LsIlmABC24KGYiu22KDR/re/Gmz+o+t8I+r/HIbsShnz3/o86ym8IfjHJn7cU4fR9NhoAifZOkXqqbaYblq5eepeSU+51kwbureNEG831cyK+o2kOWSbeGbtBgfnQ6KUuCMMiGcUgaicg8PZ+PWpRzQaftVv9w/ykngdhy2mveXzdXt3oYGgcn2heMgVbur3AkU8G8Kqt+3pC93gDemxmFFHz7HnE5nPzFDOAgpNpecj4rfEs2oIafPSFA==\r\n\
+ARC-Message-Signature: i=1; a=rsa-sha256; c=relaxed/relaxed; d=jimmillerdrums.com; s=arc1; t=1773480600; h=from:to:subject; \
+bh=x/N8/ivRfGMxF56p8/vkrTaHlPadGijHRWz0MB878Wk=; \
+b=ApP/Z4mNagXwAGVBxAvsvNAi7/YciaDnYXSiKRvNUz6pHYVdGNxJt4V2q5Bpg0gV4n4OYTJAqfyeGuikGevCXNn8NDjNRtu2q+TC4YY3VOqSdcRrOyr4yivIpYV6wTqb9Q90B0c+3sgJK7zq9tT71dXD+8WoAnjWEOBEBWXZiFfhcJYDDOi4JqGv8655YKgawts6F19K2XHhxaNzeprmTdcmFdrNa9Bex1aOtSMsyaXHRsDBscz3eo65iVM1FHKMXTMhrodSuYD2SJuyu0tVf0FGuEYUlfh0G3nNW1wA4O6f/w97WOo5m2hrxtXNeK3CdJPC++RJwwrNOzLU2vKZGQ==\r\n\
+ARC-Authentication-Results: i=1; jimmillerdrums.com; spf=pass smtp.mailfrom=fan@example.com\r\n";
+        assert_eq!(headers, expected);
+    }
+
+    #[test]
+    fn test_existing_chain_is_not_resealed() {
+        let message = b"ARC-Seal: i=1; a=rsa-sha256; cv=none; d=example.com; s=s; b=abc\r\nFrom: fan@example.com\r\n\r\nBody";
+        let result = signer().seal(message, "jimmillerdrums.com", Utc::now());
+        assert!(matches!(result, Err(ArcError::ExistingChain)));
+    }
+
+    #[test]
+    fn test_invalid_key_is_rejected() {
+        assert!(ArcSigner::new("jimmillerdrums.com", "arc1", "not a key").is_err());
+    }
+}
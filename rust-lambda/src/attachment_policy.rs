@@ -0,0 +1,479 @@
+use crate::attachments::{extract_attachments, Attachment};
+use crate::email::EmailError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fmt;
+use std::str::FromStr;
+
+/// Lists the attachments removed from a forwarded message
+pub const STRIPPED_ATTACHMENTS_HEADER: &str = "X-Processor-Stripped-Attachments";
+/// Skip reason for messages quarantined for their attachments
+pub const DANGEROUS_ATTACHMENT_REASON: &str = "dangerous-attachment";
+
+/// Extensions that run code when opened, or open documents that can
+const BLOCKED_EXTENSIONS: &[&str] = &[
+    // Windows executables and installers
+    "exe", "scr", "com", "pif", "cpl", "dll", "msi", "msp", "msc", "lnk", "reg", "hta", "appx",
+    // Scripts
+    "bat", "cmd", "js", "jse", "vbs", "vbe", "wsf", "wsh", "ps1", "psm1", "jar", "sh",
+    // Macro-enabled Office documents
+    "docm", "dotm", "xlsm", "xltm", "xlam", "pptm", "potm", "ppsm", "ppam", "sldm",
+];
+
+const OLE_MAGIC: &[u8] = &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Stream name of the VBA project in a legacy Office file, in UTF-16LE
+const OLE_VBA_PROJECT: &[u8] = b"_\0V\0B\0A\0_\0P\0R\0O\0J\0E\0C\0T\0";
+/// Zip entries listed when looking inside an archive
+const MAX_ZIP_ENTRIES: usize = 10_000;
+
+/// What to do with a message carrying a dangerous attachment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttachmentPolicy {
+    /// Do not inspect attachments
+    #[default]
+    Forward,
+    /// Replace each dangerous attachment with a short notice part
+    Strip,
+    /// Copy the message to the quarantine prefix instead of forwarding it
+    Quarantine,
+}
+
+impl FromStr for AttachmentPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "forward" | "off" => Ok(AttachmentPolicy::Forward),
+            "strip" => Ok(AttachmentPolicy::Strip),
+            "quarantine" => Ok(AttachmentPolicy::Quarantine),
+            other => Err(format!(
+                "expected forward, strip or quarantine, got '{}'",
+                other
+            )),
+        }
+    }
+}
+
+/// Why an attachment was judged dangerous
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Danger {
+    /// The filename ends in a blocked extension
+    BlockedExtension(String),
+    /// The content is a native executable, whatever its name
+    Executable,
+    /// The content is an Office document carrying VBA macros
+    Macros,
+    /// A zip archive lists an entry with a blocked extension
+    ArchiveEntry(String),
+}
+
+impl fmt::Display for Danger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Danger::BlockedExtension(extension) => write!(f, "blocked file type .{}", extension),
+            Danger::Executable => write!(f, "executable content"),
+            Danger::Macros => write!(f, "document with macros"),
+            Danger::ArchiveEntry(name) => write!(f, "archive contains {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DangerousAttachment {
+    pub attachment: Attachment,
+    pub danger: Danger,
+}
+
+/// Result of applying the policy to a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachmentScreening {
+    /// Nothing dangerous found, or the policy is off
+    Clean,
+    /// The message with each dangerous attachment replaced by a notice
+    Stripped {
+        message: Vec<u8>,
+        removed: Vec<DangerousAttachment>,
+    },
+    Quarantine(Vec<DangerousAttachment>),
+}
+
+/// Judge one attachment by its filename, then by its content. Zip archives
+/// (including Office files) are judged by the names they list; other
+/// archive formats and encrypted entries are not opened.
+pub fn attachment_danger(attachment: &Attachment) -> Option<Danger> {
+    if let Some(extension) = attachment.filename.as_deref().and_then(blocked_extension) {
+        return Some(Danger::BlockedExtension(extension));
+    }
+
+    let body = attachment.body.as_slice();
+    let is_executable = body.starts_with(b"MZ")
+        || body.starts_with(b"\x7fELF")
+        || [
+            [0xfe, 0xed, 0xfa, 0xce],
+            [0xfe, 0xed, 0xfa, 0xcf],
+            [0xce, 0xfa, 0xed, 0xfe],
+            [0xcf, 0xfa, 0xed, 0xfe],
+            [0xca, 0xfe, 0xba, 0xbe],
+        ]
+        .iter()
+        .any(|magic| body.starts_with(magic));
+    if is_executable {
+        return Some(Danger::Executable);
+    }
+
+    if body.starts_with(OLE_MAGIC) {
+        return contains(body, OLE_VBA_PROJECT).then_some(Danger::Macros);
+    }
+
+    if body.starts_with(ZIP_MAGIC) {
+        for name in zip_entry_names(body) {
+            let file_name = name.rsplit('/').next().unwrap_or(&name);
+            if file_name.eq_ignore_ascii_case("vbaProject.bin") {
+                return Some(Danger::Macros);
+            }
+            if blocked_extension(file_name).is_some() {
+                return Some(Danger::ArchiveEntry(name));
+            }
+        }
+    }
+
+    None
+}
+
+/// Every dangerous attachment of a message
+pub fn find_dangerous_attachments(
+    raw_email: &[u8],
+) -> Result<Vec<DangerousAttachment>, EmailError> {
+    Ok(extract_attachments(raw_email)?
+        .into_iter()
+        .filter_map(|attachment| {
+            attachment_danger(&attachment).map(|danger| DangerousAttachment { attachment, danger })
+        })
+        .collect())
+}
+
+/// Apply `policy` to a message. A message that is itself the dangerous
+/// attachment has nothing left to forward once stripped, so it is
+/// quarantined under either policy.
+pub fn screen_attachments(
+    policy: AttachmentPolicy,
+    raw_email: &[u8],
+) -> Result<AttachmentScreening, EmailError> {
+    if policy == AttachmentPolicy::Forward {
+        return Ok(AttachmentScreening::Clean);
+    }
+
+    let dangerous = find_dangerous_attachments(raw_email)?;
+    if dangerous.is_empty() {
+        return Ok(AttachmentScreening::Clean);
+    }
+    let whole_message = dangerous
+        .iter()
+        .any(|found| found.attachment.span.start == 0);
+    if policy == AttachmentPolicy::Quarantine || whole_message {
+        return Ok(AttachmentScreening::Quarantine(dangerous));
+    }
+
+    Ok(AttachmentScreening::Stripped {
+        message: strip_attachments(raw_email, &dangerous),
+        removed: dangerous,
+    })
+}
+
+/// Replace each attachment's part with a `text/plain` notice saying what was
+/// removed and why. The rest of the message is left byte for byte.
+pub fn strip_attachments(raw_email: &[u8], dangerous: &[DangerousAttachment]) -> Vec<u8> {
+    let mut spans: Vec<&DangerousAttachment> = dangerous.iter().collect();
+    spans.sort_by_key(|found| found.attachment.span.start);
+
+    let mut stripped = Vec::with_capacity(raw_email.len());
+    let mut position = 0;
+    for found in spans {
+        let span = &found.attachment.span;
+        if span.start < position || span.end > raw_email.len() {
+            continue;
+        }
+        stripped.extend_from_slice(&raw_email[position..span.start]);
+        stripped.extend_from_slice(notice_part(found).as_bytes());
+        position = span.end;
+    }
+    stripped.extend_from_slice(&raw_email[position..]);
+    stripped
+}
+
+/// Value of [`STRIPPED_ATTACHMENTS_HEADER`]: the removed filenames, reduced
+/// to printable ASCII
+pub fn stripped_header_value(removed: &[DangerousAttachment]) -> String {
+    removed
+        .iter()
+        .map(|found| {
+            found
+                .attachment
+                .filename
+                .as_deref()
+                .unwrap_or("unnamed")
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_graphic() && c != ',' || c == ' ' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn notice_part(found: &DangerousAttachment) -> String {
+    let text = format!(
+        "The attachment \"{}\" ({}) was removed before forwarding: {}.\r\n\
+         The original message is kept in the mail archive.\r\n",
+        found.attachment.filename.as_deref().unwrap_or("unnamed"),
+        found.attachment.content_type,
+        found.danger
+    );
+    let encoded = STANDARD.encode(text.as_bytes());
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect();
+    format!(
+        "Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Disposition: inline\r\n\
+         Content-Transfer-Encoding: base64\r\n\
+         \r\n\
+         {}",
+        lines.join("\r\n")
+    )
+}
+
+fn blocked_extension(filename: &str) -> Option<String> {
+    // Trailing dots and spaces are ignored when Windows opens a file
+    let filename = filename.trim_end_matches(['.', ' ']);
+    let (_, extension) = filename.rsplit_once('.')?;
+    let extension = extension.to_lowercase();
+    BLOCKED_EXTENSIONS
+        .contains(&extension.as_str())
+        .then_some(extension)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Entry names from a zip file's central directory; empty when it cannot be
+/// found. Entries are not decompressed.
+fn zip_entry_names(data: &[u8]) -> Vec<String> {
+    let read_u16 = |at: usize| {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+    };
+    let read_u32 = |at: usize| {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+
+    // The end-of-central-directory record closes the file, before a comment of
+    // at most 64 KiB
+    const EOCD_LEN: usize = 22;
+    let Some(last) = data.len().checked_sub(EOCD_LEN) else {
+        return Vec::new();
+    };
+    let first = last.saturating_sub(u16::MAX as usize);
+    let Some(eocd) = (first..=last)
+        .rev()
+        .find(|&at| data[at..].starts_with(b"PK\x05\x06"))
+    else {
+        return Vec::new();
+    };
+    let Some(mut position) = read_u32(eocd + 16) else {
+        return Vec::new();
+    };
+
+    let mut names = Vec::new();
+    while names.len() < MAX_ZIP_ENTRIES
+        && data
+            .get(position..)
+            .is_some_and(|rest| rest.starts_with(b"PK\x01\x02"))
+    {
+        let (Some(name_len), Some(extra_len), Some(comment_len)) = (
+            read_u16(position + 28),
+            read_u16(position + 30),
+            read_u16(position + 32),
+        ) else {
+            break;
+        };
+        let Some(name) = data.get(position + 46..position + 46 + name_len) else {
+            break;
+        };
+        names.push(String::from_utf8_lossy(name).to_string());
+        position += 46 + name_len + extra_len + comment_len;
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal zip listing `names`, with empty stored entries
+    fn zip(names: &[&str]) -> Vec<u8> {
+        let mut local = Vec::new();
+        let mut central = Vec::new();
+        for name in names {
+            let offset = local.len() as u32;
+            local.extend_from_slice(b"PK\x03\x04");
+            local.extend_from_slice(&[0; 22]);
+            local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            local.extend_from_slice(&[0; 2]);
+            local.extend_from_slice(name.as_bytes());
+
+            central.extend_from_slice(b"PK\x01\x02");
+            central.extend_from_slice(&[0; 24]);
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let mut data = local;
+        let central_offset = data.len() as u32;
+        data.extend_from_slice(&central);
+        data.extend_from_slice(b"PK\x05\x06");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        data.extend_from_slice(&central_offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    fn attachment(filename: &str, body: &[u8]) -> Attachment {
+        Attachment {
+            index: 1,
+            filename: Some(filename.to_string()),
+            content_type: "application/octet-stream".to_string(),
+            body: body.to_vec(),
+            span: 0..0,
+        }
+    }
+
+    #[test]
+    fn test_attachment_danger() {
+        assert_eq!(
+            attachment_danger(&attachment("invoice.pdf.EXE", b"")),
+            Some(Danger::BlockedExtension("exe".to_string()))
+        );
+        assert_eq!(
+            attachment_danger(&attachment("rider.docm. ", b"")),
+            Some(Danger::BlockedExtension("docm".to_string()))
+        );
+        assert_eq!(
+            attachment_danger(&attachment("invoice.pdf", b"MZ\x90\x00")),
+            Some(Danger::Executable)
+        );
+
+        let mut legacy = OLE_MAGIC.to_vec();
+        legacy.extend_from_slice(OLE_VBA_PROJECT);
+        assert_eq!(
+            attachment_danger(&attachment("contract.doc", &legacy)),
+            Some(Danger::Macros)
+        );
+        assert_eq!(
+            attachment_danger(&attachment("contract.doc", OLE_MAGIC)),
+            None
+        );
+
+        let renamed = zip(&["[Content_Types].xml", "word/vbaProject.bin"]);
+        assert_eq!(
+            attachment_danger(&attachment("contract.docx", &renamed)),
+            Some(Danger::Macros)
+        );
+        let archive = zip(&["photos/", "photos/setup.scr"]);
+        assert_eq!(
+            attachment_danger(&attachment("photos.zip", &archive)),
+            Some(Danger::ArchiveEntry("photos/setup.scr".to_string()))
+        );
+
+        let plot = zip(&["stage-plot.pdf"]);
+        assert_eq!(attachment_danger(&attachment("plot.zip", &plot)), None);
+        assert_eq!(
+            attachment_danger(&attachment("rider.pdf", b"%PDF-1.4")),
+            None
+        );
+    }
+
+    const MESSAGE: &[u8] = b"From: fan@example.com\r\n\
+Subject: Photos\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Photos attached\r\n\
+--b\r\n\
+Content-Type: application/octet-stream\r\n\
+Content-Disposition: attachment; filename=\"photos.exe\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+TVqQAAMAAAAEAAAA\r\n\
+--b\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename=\"rider.pdf\"\r\n\
+\r\n\
+%PDF-1.4\r\n\
+--b--\r\n";
+
+    #[test]
+    fn test_strip_replaces_part_with_notice() {
+        let AttachmentScreening::Stripped { message, removed } =
+            screen_attachments(AttachmentPolicy::Strip, MESSAGE).unwrap()
+        else {
+            panic!("expected the attachment to be stripped");
+        };
+        assert_eq!(removed.len(), 1);
+        assert_eq!(stripped_header_value(&removed), "photos.exe");
+
+        let text = String::from_utf8_lossy(&message);
+        assert!(!text.contains("TVqQ"));
+        assert!(text.contains("Photos attached"));
+        assert!(text.contains("filename=\"rider.pdf\""));
+
+        let parsed = mailparse::parse_mail(&message).unwrap();
+        assert_eq!(parsed.subparts.len(), 3);
+        let notice = parsed.subparts[1].get_body().unwrap();
+        assert!(notice.contains("\"photos.exe\""));
+        assert!(notice.contains("blocked file type .exe"));
+        assert!(find_dangerous_attachments(&message).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_quarantine_and_whole_message_attachments() {
+        assert!(matches!(
+            screen_attachments(AttachmentPolicy::Quarantine, MESSAGE).unwrap(),
+            AttachmentScreening::Quarantine(found) if found.len() == 1
+        ));
+        assert_eq!(
+            screen_attachments(AttachmentPolicy::Forward, MESSAGE).unwrap(),
+            AttachmentScreening::Clean
+        );
+
+        let bare = b"Content-Type: application/octet-stream; name=\"run.js\"\r\n\r\nalert(1)";
+        assert!(matches!(
+            screen_attachments(AttachmentPolicy::Strip, bare).unwrap(),
+            AttachmentScreening::Quarantine(_)
+        ));
+    }
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!("Strip".parse(), Ok(AttachmentPolicy::Strip));
+        assert_eq!("quarantine".parse(), Ok(AttachmentPolicy::Quarantine));
+        assert!("delete".parse::<AttachmentPolicy>().is_err());
+    }
+}
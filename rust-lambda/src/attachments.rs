@@ -0,0 +1,331 @@
+use crate::aws::{AppContext, AwsError};
+use crate::config::Config;
+use crate::domain::{MessageId, S3Key};
+use crate::email::EmailError;
+use crate::store::{ObjectMetadata, StoreError};
+use chrono::{DateTime, Utc};
+use mailparse::{parse_header, parse_mail, DispositionType, ParsedMail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use tracing::info;
+
+/// Object metadata holding the original filename, percent-encoded as UTF-8
+pub const FILENAME_METADATA: &str = "filename";
+/// Object metadata holding the hex SHA-256 of the attachment
+pub const SHA256_METADATA: &str = "sha256";
+/// Object metadata naming the message the attachment came from
+pub const MESSAGE_ID_METADATA: &str = "message-id";
+
+const MANIFEST_NAME: &str = "manifest.json";
+const MAX_KEY_NAME_LEN: usize = 100;
+
+/// A decoded attachment of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// Position among the message's attachments, from 1
+    pub index: usize,
+    /// Original filename, RFC 2231 and RFC 2047 decoded, without any path
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub body: Vec<u8>,
+    /// Byte range of the whole part, headers included, in the raw message
+    pub span: Range<usize>,
+}
+
+impl Attachment {
+    pub fn sha256(&self) -> String {
+        Sha256::digest(&self.body)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Object name under the message's prefix: the index keeps names unique,
+    /// the filename is reduced to characters that are safe in a key
+    pub fn key_name(&self) -> String {
+        let name: String = self
+            .filename
+            .as_deref()
+            .unwrap_or("attachment")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let name = match name.char_indices().nth(MAX_KEY_NAME_LEN) {
+            Some((end, _)) => &name[..end],
+            None => &name,
+        };
+        format!("{}-{}", self.index, name)
+    }
+}
+
+/// One archived attachment in a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentRecord {
+    pub key: String,
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// What was archived for a message, written next to its attachments as
+/// `manifest.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentManifest {
+    pub message_id: String,
+    pub extracted_at: DateTime<Utc>,
+    pub attachments: Vec<AttachmentRecord>,
+}
+
+/// Decode every attachment of a message, in MIME tree order. A part is an
+/// attachment when its disposition says so, or when it has a filename and is
+/// not a text or HTML body.
+pub fn extract_attachments(raw_email: &[u8]) -> Result<Vec<Attachment>, EmailError> {
+    let parsed = parse_mail(raw_email)?;
+    let mut attachments = Vec::new();
+    collect_attachments(raw_email, &parsed, &mut attachments)?;
+    Ok(attachments)
+}
+
+fn collect_attachments(
+    raw_email: &[u8],
+    part: &ParsedMail<'_>,
+    attachments: &mut Vec<Attachment>,
+) -> Result<(), EmailError> {
+    if !part.subparts.is_empty() {
+        for subpart in &part.subparts {
+            collect_attachments(raw_email, subpart, attachments)?;
+        }
+        return Ok(());
+    }
+
+    let content_type = part.ctype.mimetype.to_lowercase();
+    let disposition = part.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .and_then(|name| decode_filename(name));
+
+    let is_attachment = match disposition.disposition {
+        DispositionType::Attachment => true,
+        _ => {
+            filename.is_some()
+                && !matches!(content_type.as_str(), "text/plain" | "text/html")
+                && !content_type.starts_with("multipart/")
+        }
+    };
+    if is_attachment {
+        // mailparse borrows every part from the input, so its position is
+        // the distance between the two slices
+        let start = (part.raw_bytes.as_ptr() as usize).saturating_sub(raw_email.as_ptr() as usize);
+        attachments.push(Attachment {
+            index: attachments.len() + 1,
+            filename,
+            content_type,
+            body: part.get_body_raw()?,
+            span: start..start + part.raw_bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Filename from a `filename`/`name` parameter. mailparse has already undone
+/// RFC 2231 encoding and continuations; many clients still send RFC 2047
+/// encoded words instead, and some send a path.
+fn decode_filename(value: &str) -> Option<String> {
+    let decoded = if value.contains("=?") {
+        parse_header(format!("X: {}", value).as_bytes())
+            .map(|(header, _)| header.get_value())
+            .unwrap_or_else(|_| value.to_string())
+    } else {
+        value.to_string()
+    };
+    decoded
+        .rsplit(['/', '\\'])
+        .next()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// S3 metadata only carries ASCII, so anything else is percent-encoded
+fn metadata_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_graphic() && byte != b'%' || byte == b' ' {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+fn attachment_key(prefix: &str, message_id: &MessageId, name: &str) -> Result<S3Key, StoreError> {
+    S3Key::try_from(format!("{}/{}/{}", prefix, message_id, name))
+        .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+/// Store each attachment of a message under `<prefix>/<message id>/` with its
+/// content type, filename and SHA-256 as object metadata, then the manifest.
+/// Keys depend only on the message, so archiving again overwrites in place.
+/// `None` when the message has no attachments.
+pub async fn archive_attachments(
+    context: &AppContext,
+    config: &Config,
+    prefix: &str,
+    message_id: &MessageId,
+    raw_email: &[u8],
+    now: DateTime<Utc>,
+) -> Result<Option<AttachmentManifest>, AwsError> {
+    let attachments = extract_attachments(raw_email)?;
+    if attachments.is_empty() {
+        return Ok(None);
+    }
+
+    let mut records = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let key = attachment_key(prefix, message_id, &attachment.key_name())?;
+        let sha256 = attachment.sha256();
+
+        let mut metadata = ObjectMetadata::new();
+        metadata.insert(SHA256_METADATA.to_string(), sha256.clone());
+        metadata.insert(MESSAGE_ID_METADATA.to_string(), message_id.to_string());
+        if let Some(filename) = &attachment.filename {
+            metadata.insert(FILENAME_METADATA.to_string(), metadata_value(filename));
+        }
+
+        let size = attachment.body.len() as u64;
+        context
+            .store
+            .put_object_with_metadata(
+                &config.email_bucket,
+                &key,
+                attachment.body,
+                &attachment.content_type,
+                &metadata,
+            )
+            .await?;
+
+        records.push(AttachmentRecord {
+            key: key.to_string(),
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size,
+            sha256,
+        });
+    }
+
+    let manifest = AttachmentManifest {
+        message_id: message_id.to_string(),
+        extracted_at: now,
+        attachments: records,
+    };
+    let body = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode manifest: {}", e)))?;
+    context
+        .store
+        .put_object_with_metadata(
+            &config.email_bucket,
+            &attachment_key(prefix, message_id, MANIFEST_NAME)?,
+            body,
+            "application/json",
+            &ObjectMetadata::new(),
+        )
+        .await?;
+
+    info!(
+        "Archived {} attachments of {}",
+        manifest.attachments.len(),
+        message_id
+    );
+    Ok(Some(manifest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTRACT: &[u8] = b"From: planner@example.com\r\n\
+Subject: Contract\r\n\
+Content-Type: multipart/mixed; boundary=\"b1\"\r\n\
+\r\n\
+--b1\r\n\
+Content-Type: multipart/alternative; boundary=\"b2\"\r\n\
+\r\n\
+--b2\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Contract attached\r\n\
+--b2\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>Contract attached</p>\r\n\
+--b2--\r\n\
+--b1\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment;\r\n\
+\x20filename*0*=UTF-8''Vertrag%20M%C3%BC;\r\n\
+\x20filename*1*=ller.pdf\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQ=\r\n\
+--b1\r\n\
+Content-Type: image/png; name=\"=?UTF-8?B?c3RhZ2UgcGxvdC5wbmc=?=\"\r\n\
+Content-Disposition: inline\r\n\
+\r\n\
+PNG\r\n\
+--b1\r\n\
+Content-Type: text/plain; name=\"notes.txt\"\r\n\
+Content-Disposition: inline\r\n\
+\r\n\
+Inline text\r\n\
+--b1--\r\n";
+
+    #[test]
+    fn test_extract_attachments() {
+        let attachments = extract_attachments(CONTRACT).unwrap();
+        assert_eq!(attachments.len(), 2);
+
+        assert_eq!(attachments[0].index, 1);
+        assert_eq!(
+            attachments[0].filename.as_deref(),
+            Some("Vertrag Müller.pdf")
+        );
+        assert_eq!(attachments[0].content_type, "application/pdf");
+        assert_eq!(attachments[0].body, b"%PDF-1.4");
+        assert_eq!(attachments[0].key_name(), "1-Vertrag_M_ller.pdf");
+        let part = &CONTRACT[attachments[0].span.clone()];
+        assert!(part.starts_with(b"Content-Type: application/pdf\r\n"));
+        assert!(part.ends_with(b"JVBERi0xLjQ="));
+
+        assert_eq!(attachments[1].filename.as_deref(), Some("stage plot.png"));
+        assert_eq!(attachments[1].content_type, "image/png");
+
+        assert!(extract_attachments(b"Subject: Hi\r\n\r\nNo attachments")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_filename_and_metadata_encoding() {
+        assert_eq!(
+            decode_filename("C:\\Users\\jim\\rider.pdf").as_deref(),
+            Some("rider.pdf")
+        );
+        assert_eq!(decode_filename("  "), None);
+        assert_eq!(
+            metadata_value("Vertrag Müller 100%.pdf"),
+            "Vertrag M%C3%BCller 100%25.pdf"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::domain::{SesReceipt, SesVerdict};
+use crate::email::extract_email_address;
+use mailparse::{parse_headers, MailHeaderMap};
+
+pub const AUTHENTICATION_RESULTS_HEADER: &str = "Authentication-Results";
+
+/// `Authentication-Results` value (RFC 8601) reporting the SES receipt
+/// verdicts under `authserv_id`, e.g.
+/// `jimmillerdrums.com; spf=pass smtp.mailfrom=fan@example.com; dkim=pass; dmarc=pass header.from=example.com`.
+/// `None` when SES reported no authentication verdicts.
+pub fn authentication_results(
+    authserv_id: &str,
+    receipt: &SesReceipt,
+    envelope_source: Option<&str>,
+    raw_email: &[u8],
+) -> Option<String> {
+    let mut results = Vec::new();
+
+    if let Some(spf) = result_keyword(&receipt.spf_verdict) {
+        match envelope_source.map(str::trim).filter(|s| s.contains('@')) {
+            Some(source) => results.push(format!("spf={} smtp.mailfrom={}", spf, source)),
+            None => results.push(format!("spf={}", spf)),
+        }
+    }
+
+    if let Some(dkim) = result_keyword(&receipt.dkim_verdict) {
+        results.push(format!("dkim={}", dkim));
+    }
+
+    if let Some(dmarc) = result_keyword(&receipt.dmarc_verdict) {
+        match header_from_domain(raw_email) {
+            Some(domain) => results.push(format!("dmarc={} header.from={}", dmarc, domain)),
+            None => results.push(format!("dmarc={}", dmarc)),
+        }
+    }
+
+    if results.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}; {}", authserv_id, results.join("; ")))
+}
+
+/// Authentication service identifier of an `Authentication-Results` value
+/// (the part before the first `;`, without an optional version)
+pub fn authserv_id(value: &str) -> &str {
+    value
+        .split(';')
+        .next()
+        .and_then(|id| id.split_whitespace().next())
+        .unwrap_or_default()
+}
+
+/// RFC 8601 result keyword for an SES verdict. `DISABLED` and unknown
+/// statuses are left out rather than guessed at.
+fn result_keyword(verdict: &Option<SesVerdict>) -> Option<&'static str> {
+    match verdict.as_ref()?.status.to_uppercase().as_str() {
+        "PASS" => Some("pass"),
+        "FAIL" => Some("fail"),
+        "GRAY" => Some("none"),
+        "PROCESSING_FAILED" => Some("temperror"),
+        _ => None,
+    }
+}
+
+fn header_from_domain(raw_email: &[u8]) -> Option<String> {
+    let (headers, _) = parse_headers(raw_email).ok()?;
+    let from = extract_email_address(&headers.get_first_value("From")?).ok()?;
+    from.rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(status: &str) -> Option<SesVerdict> {
+        Some(SesVerdict {
+            status: status.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_authentication_results_from_verdicts() {
+        let receipt = SesReceipt {
+            spf_verdict: verdict("PASS"),
+            dkim_verdict: verdict("GRAY"),
+            dmarc_verdict: verdict("FAIL"),
+            ..Default::default()
+        };
+        let email = b"From: Fan <fan@Example.com>\r\nSubject: Hi\r\n\r\nBody";
+
+        assert_eq!(
+            authentication_results(
+                "jimmillerdrums.com",
+                &receipt,
+                Some("bounce@mailer.example.com"),
+                email
+            )
+            .unwrap(),
+            "jimmillerdrums.com; spf=pass smtp.mailfrom=bounce@mailer.example.com; dkim=none; dmarc=fail header.from=example.com"
+        );
+    }
+
+    #[test]
+    fn test_disabled_verdicts_are_left_out() {
+        let receipt = SesReceipt {
+            spf_verdict: verdict("DISABLED"),
+            spam_verdict: verdict("PASS"),
+            ..Default::default()
+        };
+        assert_eq!(
+            authentication_results("jimmillerdrums.com", &receipt, None, b"\r\n\r\n"),
+            None
+        );
+
+        let receipt = SesReceipt {
+            dkim_verdict: verdict("PROCESSING_FAILED"),
+            ..Default::default()
+        };
+        assert_eq!(
+            authentication_results("jimmillerdrums.com", &receipt, Some("<>"), b"\r\n\r\n")
+                .unwrap(),
+            "jimmillerdrums.com; dkim=temperror"
+        );
+    }
+
+    #[test]
+    fn test_authserv_id() {
+        assert_eq!(authserv_id("amazonses.com; spf=pass"), "amazonses.com");
+        assert_eq!(authserv_id(" example.org 1; none"), "example.org");
+    }
+}
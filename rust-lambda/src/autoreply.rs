@@ -0,0 +1,195 @@
+use crate::email::EmailError;
+use mailparse::{parse_mail, MailHeaderMap};
+use std::str::FromStr;
+
+/// Header added to automated messages forwarded under [`AutomatedMailPolicy::Tag`]
+pub const AUTOMATED_HEADER: &str = "X-Processor-Automated";
+
+/// Why a message looks machine-generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutomatedKind {
+    /// `Auto-Submitted` other than `no` (RFC 3834)
+    AutoSubmitted,
+    /// `Precedence: bulk`, `junk` or `auto_reply`
+    Precedence,
+    /// `X-Autoreply` set by many vacation responders
+    XAutoreply,
+    /// Empty envelope sender (`<>`), used by bounces
+    NullSender,
+    /// `multipart/report` delivery status notification
+    DeliveryReport,
+}
+
+impl AutomatedKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AutomatedKind::AutoSubmitted => "auto-submitted",
+            AutomatedKind::Precedence => "precedence",
+            AutomatedKind::XAutoreply => "x-autoreply",
+            AutomatedKind::NullSender => "null-sender",
+            AutomatedKind::DeliveryReport => "delivery-report",
+        }
+    }
+
+    /// Bounces and DSNs, as opposed to out-of-office style auto-replies
+    pub fn is_bounce(&self) -> bool {
+        matches!(
+            self,
+            AutomatedKind::NullSender | AutomatedKind::DeliveryReport
+        )
+    }
+}
+
+/// What to do with an automated message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AutomatedMailPolicy {
+    /// Forward like any other message
+    Forward,
+    /// Forward with an [`AUTOMATED_HEADER`] naming the detected kind
+    #[default]
+    Tag,
+    /// Keep the stored copy but do not forward
+    StoreOnly,
+}
+
+impl FromStr for AutomatedMailPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "forward" => Ok(AutomatedMailPolicy::Forward),
+            "tag" => Ok(AutomatedMailPolicy::Tag),
+            "store" | "store-only" => Ok(AutomatedMailPolicy::StoreOnly),
+            other => Err(format!(
+                "expected forward, tag or store-only, got '{}'",
+                other
+            )),
+        }
+    }
+}
+
+/// Detect auto-replies and bounces. `envelope_source` is the SES `mail.source`
+/// when known; otherwise the stored `Return-Path` stands in for it.
+pub fn detect_automated(
+    raw_email: &[u8],
+    envelope_source: Option<&str>,
+) -> Result<Option<AutomatedKind>, EmailError> {
+    let parsed = parse_mail(raw_email)?;
+    let headers = &parsed.headers;
+
+    let envelope_source = envelope_source
+        .map(str::to_string)
+        .or_else(|| headers.get_first_value("Return-Path"));
+    if let Some(source) = envelope_source {
+        if matches!(source.trim(), "" | "<>") {
+            return Ok(Some(AutomatedKind::NullSender));
+        }
+    }
+
+    if parsed
+        .ctype
+        .mimetype
+        .eq_ignore_ascii_case("multipart/report")
+    {
+        return Ok(Some(AutomatedKind::DeliveryReport));
+    }
+
+    if let Some(value) = headers.get_first_value("Auto-Submitted") {
+        if !first_token(&value).eq_ignore_ascii_case("no") {
+            return Ok(Some(AutomatedKind::AutoSubmitted));
+        }
+    }
+
+    if let Some(value) = headers.get_first_value("Precedence") {
+        let precedence = first_token(&value).to_lowercase();
+        if matches!(precedence.as_str(), "bulk" | "junk" | "auto_reply") {
+            return Ok(Some(AutomatedKind::Precedence));
+        }
+    }
+
+    if let Some(value) = headers.get_first_value("X-Autoreply") {
+        if !first_token(&value).eq_ignore_ascii_case("no") {
+            return Ok(Some(AutomatedKind::XAutoreply));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Header value without parameters or comments, e.g. `auto-replied` from
+/// `auto-replied; owner-email="x@y"`
+fn first_token(value: &str) -> &str {
+    value.split([';', '(']).next().unwrap_or_default().trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(email: &[u8]) -> Option<AutomatedKind> {
+        detect_automated(email, Some("sender@example.com")).unwrap()
+    }
+
+    #[test]
+    fn test_regular_message_is_not_automated() {
+        let email = b"From: fan@example.com\r\nSubject: Lessons?\r\n\r\nBody";
+        assert_eq!(detect(email), None);
+    }
+
+    #[test]
+    fn test_auto_submitted() {
+        let email = b"Auto-Submitted: auto-replied; owner-email=\"a@b.c\"\r\nFrom: a@b.c\r\n\r\nOut of office";
+        assert_eq!(detect(email), Some(AutomatedKind::AutoSubmitted));
+
+        let email = b"Auto-Submitted: no\r\nFrom: a@b.c\r\n\r\nHuman";
+        assert_eq!(detect(email), None);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let email = b"Precedence: Bulk\r\nFrom: a@b.c\r\n\r\nNewsletter";
+        assert_eq!(detect(email), Some(AutomatedKind::Precedence));
+
+        let email = b"Precedence: list\r\nFrom: a@b.c\r\n\r\nDiscussion";
+        assert_eq!(detect(email), None);
+    }
+
+    #[test]
+    fn test_x_autoreply() {
+        let email = b"X-Autoreply: yes\r\nFrom: a@b.c\r\n\r\nAway";
+        assert_eq!(detect(email), Some(AutomatedKind::XAutoreply));
+    }
+
+    #[test]
+    fn test_null_sender_from_envelope_or_return_path() {
+        let email = b"From: MAILER-DAEMON@example.com\r\n\r\nBounce";
+        assert_eq!(
+            detect_automated(email, Some("<>")).unwrap(),
+            Some(AutomatedKind::NullSender)
+        );
+
+        let email = b"Return-Path: <>\r\nFrom: MAILER-DAEMON@example.com\r\n\r\nBounce";
+        assert_eq!(
+            detect_automated(email, None).unwrap(),
+            Some(AutomatedKind::NullSender)
+        );
+    }
+
+    #[test]
+    fn test_delivery_status_notification() {
+        let email = b"From: postmaster@example.com\r\nContent-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nUndeliverable\r\n--b--";
+        let kind = detect(email).unwrap();
+        assert_eq!(kind, AutomatedKind::DeliveryReport);
+        assert!(kind.is_bounce());
+    }
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!(
+            "store-only".parse::<AutomatedMailPolicy>(),
+            Ok(AutomatedMailPolicy::StoreOnly)
+        );
+        assert_eq!(" Tag ".parse(), Ok(AutomatedMailPolicy::Tag));
+        assert!("drop".parse::<AutomatedMailPolicy>().is_err());
+    }
+}
@@ -1,11 +1,16 @@
-use crate::domain::{EmailAddress, EmailBody, MessageId, S3Key, Subject};
+use crate::domain::{EmailAddress, MessageId, S3Key};
 use crate::email::EmailError;
+use crate::store::MailStore;
+use crate::template::RenderedMessage;
 use aws_sdk_s3::Client as S3Client;
 use aws_sdk_sesv2::primitives::Blob;
-use aws_sdk_sesv2::types::{Body, Content, Destination, EmailContent, Message, RawMessage};
+use aws_sdk_sesv2::types::{
+    Body, Content, Destination, EmailContent, Message, MessageHeader, RawMessage,
+};
 use aws_sdk_sesv2::Client as SesClient;
+use std::sync::Arc;
 use thiserror::Error;
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Error, Debug)]
 pub enum AwsError {
@@ -19,17 +24,56 @@ pub enum AwsError {
     MimeError(#[from] crate::mime::MimeError),
     #[error("Domain error: {0}")]
     DomainError(#[from] crate::domain::DomainError),
+    #[error("Storage error: {0}")]
+    StoreError(#[from] crate::store::StoreError),
+}
+
+impl AwsError {
+    /// Short, stable name for the kind of failure (used in dead-letter records)
+    pub fn class(&self) -> &'static str {
+        match self {
+            AwsError::S3Error(_) => "s3",
+            AwsError::SesError(_) => "ses",
+            AwsError::EmailError(_) => "email_parse",
+            AwsError::MimeError(_) => "mime",
+            AwsError::DomainError(_) => "domain",
+            AwsError::StoreError(crate::store::StoreError::NotFound(_)) => "not_found",
+            AwsError::StoreError(_) => "storage",
+        }
+    }
+
+    /// Whether retrying the same message could succeed. Malformed messages,
+    /// missing objects and SES rejections fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AwsError::S3Error(_) => true,
+            AwsError::StoreError(crate::store::StoreError::Backend(_)) => true,
+            AwsError::SesError(message) => {
+                let message = message.to_lowercase();
+                [
+                    "throttl",
+                    "toomanyrequests",
+                    "serviceunavailable",
+                    "timeout",
+                    "dispatch",
+                ]
+                .iter()
+                .any(|transient| message.contains(transient))
+            }
+            _ => false,
+        }
+    }
 }
 
 pub struct AppContext {
-    pub s3_client: S3Client,
+    pub store: Arc<dyn MailStore>,
     pub ses_client: SesClient,
 }
 
 impl AppContext {
     pub fn new(config: &aws_config::SdkConfig) -> Self {
         Self {
-            s3_client: S3Client::new(config),
+            store: Arc::new(S3Client::new(config)),
             ses_client: SesClient::new(config),
         }
     }
@@ -62,34 +106,64 @@ pub async fn retrieve_email_from_s3(
     Ok(bytes)
 }
 
+/// Send a message SES assembles itself: plain text, or `multipart/alternative`
+/// when the message has an HTML part
 pub async fn send_email_via_ses(
     client: &SesClient,
     from: &str,
     to: &EmailAddress,
     reply_to: &EmailAddress,
-    subject: &Subject,
-    body: &EmailBody,
+    message: &RenderedMessage,
 ) -> Result<String, AwsError> {
     info!("Sending email via SES to {}", to);
 
     let destination = Destination::builder().to_addresses(to.as_str()).build();
 
     let subject_content = Content::builder()
-        .data(subject.as_str())
+        .data(message.subject.as_str())
         .build()
         .map_err(|e| AwsError::SesError(e.to_string()))?;
 
-    let body_content = Content::builder()
-        .data(body.as_str())
+    let text_content = Content::builder()
+        .data(message.text.as_str())
         .build()
         .map_err(|e| AwsError::SesError(e.to_string()))?;
 
-    let message = Message::builder()
+    let html_content = message
+        .html
+        .as_ref()
+        .map(|html| {
+            Content::builder()
+                .data(html)
+                .build()
+                .map_err(|e| AwsError::SesError(e.to_string()))
+        })
+        .transpose()?;
+
+    let headers = message
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            MessageHeader::builder()
+                .name(name)
+                .value(value)
+                .build()
+                .map_err(|e| AwsError::SesError(e.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let simple = Message::builder()
         .subject(subject_content)
-        .body(Body::builder().text(body_content).build())
+        .body(
+            Body::builder()
+                .text(text_content)
+                .set_html(html_content)
+                .build(),
+        )
+        .set_headers((!headers.is_empty()).then_some(headers))
         .build();
 
-    let email_content = EmailContent::builder().simple(message).build();
+    let email_content = EmailContent::builder().simple(simple).build();
 
     let response = client
         .send_email()
@@ -106,11 +180,13 @@ pub async fn send_email_via_ses(
     Ok(message_id.to_string())
 }
 
-/// Send raw MIME email via SES (supports multipart, attachments, etc.)
+/// Send raw MIME email via SES (supports multipart, attachments, etc.).
+/// Bounces and complaints go to `feedback_address` when given.
 pub async fn send_raw_email_via_ses(
     client: &SesClient,
     raw_email: &[u8],
     from_email: &str,
+    feedback_address: Option<&str>,
 ) -> Result<String, AwsError> {
     info!("Sending raw email via SES ({} bytes)", raw_email.len());
 
@@ -124,6 +200,7 @@ pub async fn send_raw_email_via_ses(
     let response = client
         .send_email()
         .from_email_address(from_email)
+        .set_feedback_forwarding_email_address(feedback_address.map(str::to_string))
         .content(email_content)
         .send()
         .await
@@ -169,30 +246,116 @@ pub async fn forward_email(
 ) -> Result<String, AwsError> {
     let s3_key = S3Key::try_from(format!("{}/{}", request.incoming_path, request.message_id))?;
 
-    let email_bytes = retrieve_email_from_s3(&context.s3_client, &request.bucket, &s3_key).await?;
+    info!(
+        "Retrieving email from storage: {}/{}",
+        request.bucket, s3_key
+    );
+    let email_bytes = context.store.get_object(&request.bucket, &s3_key).await?;
+
+    forward_raw_email(
+        context,
+        &request.message_id,
+        &email_bytes,
+        &request.forward_to,
+        config,
+        &ForwardOptions::default(),
+    )
+    .await
+}
+
+/// Per-message additions to a forwarded message
+#[derive(Debug, Clone, Default)]
+pub struct ForwardOptions {
+    /// Headers appended after the trace header
+    pub extra_headers: Vec<(String, String)>,
+    /// Subject prefix of the message's route
+    pub subject_tag: Option<String>,
+    /// Conversation alias that replaces the sender's address in `Reply-To`
+    pub reply_alias: Option<String>,
+}
+
+/// Rewrite the sender headers of a raw message and send it on to `forward_to`,
+/// stamping a `Received` header for `message_id` and applying `options`
+pub async fn forward_raw_email(
+    context: &AppContext,
+    message_id: &MessageId,
+    email_bytes: &[u8],
+    forward_to: &EmailAddress,
+    config: &crate::config::Config,
+    options: &ForwardOptions,
+) -> Result<String, AwsError> {
+    validate_email_size(email_bytes, config.max_email_size_mb)?;
 
-    validate_email_size(&email_bytes, config.max_email_size_mb)?;
+    let (mut reply_to_email, sender_name) = crate::email::extract_reply_to_info(email_bytes)?;
 
-    let (reply_to_email, sender_name) = crate::email::extract_reply_to_info(&email_bytes)?;
+    // Replies go back through the domain so the forwarding mailbox stays private
+    if let Some(alias) = &options.reply_alias {
+        reply_to_email = format!("\"{}\" <{}>", sender_name, alias);
+    }
 
     let from_display_address = format!(
-        "\"{}\" (via jimmillerdrums.com) <forwarder@jimmillerdrums.com>",
-        sender_name
+        "\"{}\" (via {}) <{}>",
+        sender_name,
+        config.forwarder_domain(),
+        config.forwarder_email
     );
 
-    let modified_email = crate::mime::modify_email_headers(
-        &email_bytes,
-        &from_display_address,
-        request.forward_to.as_str(),
-        &reply_to_email,
-    )?;
+    let hops = crate::loops::forward_hops(email_bytes, &config.forwarder_email)?;
+
+    let mut headers = vec![(
+        crate::loops::TRACE_HEADER.to_string(),
+        crate::loops::trace_header_value(&config.forwarder_email, hops + 1),
+    )];
+    headers.extend_from_slice(&options.extra_headers);
+
+    let rewrite = crate::mime::HeaderRewrite {
+        from: from_display_address,
+        to: forward_to.to_string(),
+        reply_to: reply_to_email,
+        extra_headers: headers,
+        preserve: config.original_headers.clone(),
+        prepend_headers: vec![(
+            "Received".to_string(),
+            crate::trace::received_header_value(
+                config.forwarder_domain(),
+                message_id,
+                chrono::Utc::now(),
+            ),
+        )],
+        authserv_id: Some(config.forwarder_domain().to_string()),
+        subject_tag: options.subject_tag.clone(),
+        drop_headers: Vec::new(),
+    };
+    let mut modified_email = crate::mime::rewrite_email_headers(email_bytes, &rewrite)?;
+
+    if let Some(signer) = &config.arc_signer {
+        match signer.seal(
+            &modified_email,
+            config.forwarder_domain(),
+            chrono::Utc::now(),
+        ) {
+            Ok(sealed) => modified_email = sealed,
+            Err(e) => warn!("Forwarding {} without an ARC seal: {}", message_id, e),
+        }
+    }
 
-    let message_id = send_raw_email_via_ses(
+    // Route bounces back to us with the original sender encoded, so they can be traced
+    let return_path = config.srs_secret.as_deref().and_then(|secret| {
+        crate::srs::original_envelope_sender(email_bytes).and_then(|sender| {
+            crate::srs::encode_return_path(
+                &sender,
+                config.forwarder_domain(),
+                secret,
+                chrono::Utc::now(),
+            )
+        })
+    });
+
+    send_raw_email_via_ses(
         &context.ses_client,
         &modified_email,
-        "forwarder@jimmillerdrums.com",
+        &config.forwarder_email,
+        return_path.as_deref(),
     )
-    .await?;
-
-    Ok(message_id)
+    .await
 }
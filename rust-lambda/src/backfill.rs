@@ -0,0 +1,276 @@
+use crate::aws::{AppContext, AwsError};
+use crate::config::Config;
+use crate::disposition::{ForwardOutcome, ProcessingStatus, STATUS_TAG};
+use crate::domain::{MessageId, S3Key, TimeWindow};
+use crate::relay::RelayOutcome;
+use crate::Handled;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::fmt;
+use tracing::{info, warn};
+
+/// Object SES writes when a receipt rule is first created; never a real message
+const SES_SETUP_NOTIFICATION: &str = "AMAZON_SES_SETUP_NOTIFICATION";
+
+#[derive(Debug, Clone)]
+pub struct BackfillOptions {
+    pub window: TimeWindow,
+    /// Maximum number of messages forwarded at the same time
+    pub concurrency: usize,
+    /// Report what would be forwarded without sending anything
+    pub dry_run: bool,
+}
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        Self {
+            window: TimeWindow::default(),
+            concurrency: 4,
+            dry_run: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardFailure {
+    pub message_id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BackfillReport {
+    pub scanned: usize,
+    pub outside_window: usize,
+    pub ignored: usize,
+    pub already_forwarded: usize,
+    /// Already handled without forwarding, e.g. reports
+    pub already_skipped: usize,
+    pub would_forward: Vec<String>,
+    pub forwarded: Vec<String>,
+    pub suppressed: Vec<String>,
+    /// Relay replies, which cannot be authorized again without their SES
+    /// verdicts; left as they are for a manual decision
+    pub needs_manual: Vec<String>,
+    pub failed: Vec<ForwardFailure>,
+}
+
+impl fmt::Display for BackfillReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scanned:           {}", self.scanned)?;
+        writeln!(f, "Outside window:    {}", self.outside_window)?;
+        writeln!(f, "Ignored:           {}", self.ignored)?;
+        writeln!(f, "Already forwarded: {}", self.already_forwarded)?;
+        writeln!(f, "Already skipped:   {}", self.already_skipped)?;
+        if !self.would_forward.is_empty() {
+            writeln!(f, "Would forward:     {}", self.would_forward.len())?;
+            for message_id in &self.would_forward {
+                writeln!(f, "  {}", message_id)?;
+            }
+        }
+        writeln!(f, "Forwarded:         {}", self.forwarded.len())?;
+        writeln!(f, "Suppressed:        {}", self.suppressed.len())?;
+        if !self.needs_manual.is_empty() {
+            writeln!(f, "Needs manual:      {}", self.needs_manual.len())?;
+            for message_id in &self.needs_manual {
+                writeln!(f, "  {}", message_id)?;
+            }
+        }
+        writeln!(f, "Failed:            {}", self.failed.len())?;
+        for failure in &self.failed {
+            writeln!(f, "  {}: {}", failure.message_id, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+enum BackfillOutcome {
+    AlreadyForwarded,
+    AlreadySkipped,
+    WouldForward,
+    Forwarded,
+    Suppressed,
+    NeedsManual,
+    Failed(String),
+}
+
+/// Re-process messages under `incoming_prefix` that were never handled or
+/// failed, e.g. after an outage where every invocation failed. Each goes
+/// through the same report, bounce, relay and forwarding paths as new mail.
+pub async fn run_backfill(
+    context: &AppContext,
+    config: &Config,
+    options: &BackfillOptions,
+) -> Result<BackfillReport, AwsError> {
+    let prefix = format!("{}/", config.incoming_prefix);
+    let objects = context
+        .store
+        .list_objects(&config.email_bucket, &prefix)
+        .await?;
+
+    let mut report = BackfillReport {
+        scanned: objects.len(),
+        ..Default::default()
+    };
+
+    let mut candidates = Vec::new();
+    for object in objects {
+        if !options.window.contains(object.last_modified) {
+            report.outside_window += 1;
+            continue;
+        }
+        match message_id_from_key(&prefix, &object.key) {
+            Some(message_id) => candidates.push(message_id),
+            None => report.ignored += 1,
+        }
+    }
+
+    info!(
+        "Backfill: {} candidate(s) of {} object(s) under {}",
+        candidates.len(),
+        report.scanned,
+        prefix
+    );
+
+    let outcomes: Vec<(MessageId, BackfillOutcome)> = stream::iter(candidates)
+        .map(|message_id| async move {
+            let outcome = backfill_message(context, config, &message_id, options.dry_run).await;
+            (message_id, outcome)
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    for (message_id, outcome) in outcomes {
+        match outcome {
+            BackfillOutcome::AlreadyForwarded => report.already_forwarded += 1,
+            BackfillOutcome::AlreadySkipped => report.already_skipped += 1,
+            BackfillOutcome::WouldForward => report.would_forward.push(message_id.to_string()),
+            BackfillOutcome::Forwarded => report.forwarded.push(message_id.to_string()),
+            BackfillOutcome::Suppressed => report.suppressed.push(message_id.to_string()),
+            BackfillOutcome::NeedsManual => report.needs_manual.push(message_id.to_string()),
+            BackfillOutcome::Failed(error) => report.failed.push(ForwardFailure {
+                message_id: message_id.to_string(),
+                error,
+            }),
+        }
+    }
+
+    report.would_forward.sort();
+    report.forwarded.sort();
+    report.suppressed.sort();
+    report.needs_manual.sort();
+    report
+        .failed
+        .sort_by(|a, b| a.message_id.cmp(&b.message_id));
+
+    Ok(report)
+}
+
+async fn backfill_message(
+    context: &AppContext,
+    config: &Config,
+    message_id: &MessageId,
+    dry_run: bool,
+) -> BackfillOutcome {
+    let key = match crate::disposition::incoming_key(&config.incoming_prefix, message_id) {
+        Ok(key) => key,
+        Err(e) => return BackfillOutcome::Failed(e.to_string()),
+    };
+
+    // Only mail that was never handled, or failed, is picked up again; a
+    // report or held message was dealt with on arrival
+    match context
+        .store
+        .get_object_tags(&config.email_bucket, &key)
+        .await
+    {
+        Ok(tags) => match tags.get(STATUS_TAG).map(String::as_str) {
+            None => {}
+            Some(status) if status == ProcessingStatus::Failed.as_str() => {}
+            Some(status) if status == ProcessingStatus::Forwarded.as_str() => {
+                return BackfillOutcome::AlreadyForwarded;
+            }
+            Some(_) => return BackfillOutcome::AlreadySkipped,
+        },
+        Err(e) => return BackfillOutcome::Failed(e.to_string()),
+    }
+
+    if dry_run {
+        return BackfillOutcome::WouldForward;
+    }
+
+    let raw_email = match context.store.get_object(&config.email_bucket, &key).await {
+        Ok(raw_email) => raw_email,
+        Err(e) => return BackfillOutcome::Failed(e.to_string()),
+    };
+    let destination = crate::stored_destination(&raw_email);
+    let result =
+        crate::handle_stored_message(context, config, message_id, &destination, None).await;
+
+    match result {
+        Ok(Handled::Forward(delivery)) => match delivery.outcome {
+            ForwardOutcome::Forwarded(forwarded_message_id) => {
+                info!("Backfilled {} as {}", message_id, forwarded_message_id);
+                BackfillOutcome::Forwarded
+            }
+            ForwardOutcome::Suppressed(reason) => {
+                info!("Backfill suppressed {}: {}", message_id, reason);
+                BackfillOutcome::Suppressed
+            }
+        },
+        Ok(Handled::Relay(RelayOutcome::Relayed {
+            message_id: relayed_id,
+            ..
+        })) => {
+            info!("Backfill relayed {} as {}", message_id, relayed_id);
+            BackfillOutcome::Forwarded
+        }
+        Ok(Handled::Unverified) => {
+            warn!("Backfill left relay reply {} for manual action", message_id);
+            BackfillOutcome::NeedsManual
+        }
+        Ok(handled) => {
+            info!("Backfill did not forward {}: {:?}", message_id, handled);
+            BackfillOutcome::Suppressed
+        }
+        Err(e) => {
+            warn!("Backfill of {} failed: {}", message_id, e);
+            BackfillOutcome::Failed(e.to_string())
+        }
+    }
+}
+
+/// Message id for a key directly under `prefix`; nested keys and SES
+/// housekeeping objects are not messages
+pub(crate) fn message_id_from_key(prefix: &str, key: &S3Key) -> Option<MessageId> {
+    let name = key.as_str().strip_prefix(prefix)?;
+    if name.contains('/') || name.starts_with(SES_SETUP_NOTIFICATION) {
+        return None;
+    }
+    MessageId::try_from(name.to_string()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(value: &str) -> S3Key {
+        S3Key::try_from(value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_message_id_from_key() {
+        let message_id = message_id_from_key("incoming/", &key("incoming/abc123")).unwrap();
+        assert_eq!(message_id.as_str(), "abc123");
+    }
+
+    #[test]
+    fn test_message_id_from_key_ignores_non_messages() {
+        assert!(message_id_from_key("incoming/", &key("incoming/")).is_none());
+        assert!(message_id_from_key("incoming/", &key("incoming/nested/abc")).is_none());
+        assert!(
+            message_id_from_key("incoming/", &key("incoming/AMAZON_SES_SETUP_NOTIFICATION"))
+                .is_none()
+        );
+    }
+}
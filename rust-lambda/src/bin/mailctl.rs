@@ -0,0 +1,380 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use email_processor::config::Config;
+use email_processor::{
+    conversation_mbox, export_mail, flush_held_notices, list_aliases, list_dead_letters, list_held,
+    load_thread, query_index, release_held, replay_dead_letters, revoke_alias, run_backfill,
+    send_unknown_recipient_report, unknown_recipient_report, AppContext, BackfillOptions,
+    ExportFormat, ExportOptions, FsStore, IndexQuery, MailStore, TimeWindow,
+};
+use lambda_runtime::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Operator commands for the email processor (uses the same environment as the Lambda)
+#[derive(Parser)]
+#[command(name = "mailctl")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Re-forward stored messages under INCOMING_PREFIX that were never forwarded
+    Backfill {
+        /// Only messages stored at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_bound)]
+        since: Option<DateTime<Utc>>,
+        /// Only messages stored before this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_bound)]
+        until: Option<DateTime<Utc>>,
+        /// Maximum number of messages forwarded at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// List what would be forwarded without sending anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Inspect and replay permanently failed messages
+    #[command(subcommand)]
+    DeadLetter(DeadLetterCommand),
+    /// Inspect and release messages held by the rate limiter
+    #[command(subcommand)]
+    Held(HeldCommand),
+    /// Inspect and revoke per-conversation reply aliases
+    #[command(subcommand)]
+    Aliases(AliasCommand),
+    /// Report mail to addresses not in KNOWN_RECIPIENTS
+    #[command(subcommand)]
+    Unknown(UnknownCommand),
+    /// Search the message index under INDEX_PREFIX
+    #[command(subcommand)]
+    Index(IndexCommand),
+    /// Conversations threaded under THREAD_PREFIX
+    #[command(subcommand)]
+    Thread(ThreadCommand),
+    /// Write stored messages under INCOMING_PREFIX to an mboxrd file or a Maildir
+    Export {
+        /// Only messages stored at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_bound)]
+        since: Option<DateTime<Utc>>,
+        /// Only messages stored before this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_bound)]
+        until: Option<DateTime<Utc>>,
+        /// mbox (mboxrd) or maildir
+        #[arg(long, default_value = "mbox")]
+        format: ExportFormat,
+        /// mbox file or Maildir directory to write
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Read from this directory (holding EMAIL_BUCKET, as left by
+        /// `aws s3 sync`) instead of S3; only EMAIL_BUCKET and
+        /// INCOMING_PREFIX need to be set
+        #[arg(long)]
+        store_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeadLetterCommand {
+    /// List failure records under DEAD_LETTER_PREFIX, oldest first
+    List,
+    /// Forward dead-lettered messages again and remove the records that succeed
+    Replay {
+        /// Only replay these message ids (default: every record)
+        message_ids: Vec<String>,
+        /// List what would be replayed without sending anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HeldCommand {
+    /// List messages under HELD_PREFIX
+    List,
+    /// Send "N messages held from X" notices for bursts that have ended
+    Notify {
+        /// Also report senders that are still over the limit
+        #[arg(long)]
+        all: bool,
+    },
+    /// Forward held messages after all
+    Release {
+        #[arg(required = true)]
+        message_ids: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AliasCommand {
+    /// List aliases under ALIAS_PREFIX, most recently used first
+    List,
+    /// Stop relaying replies through these aliases (addresses or ids)
+    Revoke {
+        #[arg(required = true)]
+        aliases: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum UnknownCommand {
+    /// Print the most-hit unknown addresses, or email them to FORWARD_TO_EMAIL
+    Report {
+        /// Days to cover, ending today
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+        /// Addresses to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+        /// Email the report instead of printing it
+        #[arg(long)]
+        send: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// List indexed messages matching every given filter, oldest first
+    Query {
+        /// Sender address or part of it, e.g. @example.com
+        #[arg(long)]
+        from: Option<String>,
+        /// Only messages processed at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_bound)]
+        since: Option<DateTime<Utc>>,
+        /// Only messages processed before this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_bound)]
+        until: Option<DateTime<Utc>>,
+        /// Text the subject contains
+        #[arg(long)]
+        subject: Option<String>,
+        /// forwarded, skipped, failed, or a skip reason such as rate-limited
+        #[arg(long)]
+        outcome: Option<String>,
+        /// Print the matching records as JSON Lines
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ThreadCommand {
+    /// List the messages of a conversation, oldest first
+    Show {
+        /// Thread id, or the Message-ID of any message in the conversation
+        id: String,
+    },
+    /// Rebuild a conversation from the bucket as an mbox file
+    Export {
+        /// Thread id, or the Message-ID of any message in the conversation
+        id: String,
+        /// File to write (default: standard output)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn parse_bound(value: &str) -> Result<DateTime<Utc>, String> {
+    TimeWindow::parse_bound(value).map_err(|e| e.to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+
+    let sdk_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .load()
+        .await;
+    let context = AppContext::new(&sdk_config);
+
+    // Exporting a synced copy of the bucket works without the Lambda's settings
+    let config = match &cli.command {
+        Command::Export {
+            store_dir: Some(_), ..
+        } => Config::storage_from_env(),
+        _ => Config::from_env(),
+    }
+    .map_err(|e| Error::from(format!("Configuration error: {}", e)))?;
+
+    match cli.command {
+        Command::Backfill {
+            since,
+            until,
+            concurrency,
+            dry_run,
+        } => {
+            let options = BackfillOptions {
+                window: TimeWindow { since, until },
+                concurrency,
+                dry_run,
+            };
+            let report = run_backfill(&context, &config, &options).await?;
+            print!("{}", report);
+        }
+        Command::DeadLetter(DeadLetterCommand::List) => {
+            for record in list_dead_letters(&context, &config).await? {
+                println!(
+                    "{}  {}  attempts={}  {}: {}",
+                    record.failed_at.to_rfc3339(),
+                    record.message_id,
+                    record.attempts,
+                    record.error_class,
+                    record.error
+                );
+            }
+        }
+        Command::DeadLetter(DeadLetterCommand::Replay {
+            message_ids,
+            dry_run,
+        }) => {
+            let report = replay_dead_letters(&context, &config, &message_ids, dry_run).await?;
+            print!("{}", report);
+        }
+        Command::Held(HeldCommand::List) => {
+            for object in list_held(&context, &config).await? {
+                println!(
+                    "{}  {}  {} bytes",
+                    object.last_modified.to_rfc3339(),
+                    object.key,
+                    object.size
+                );
+            }
+        }
+        Command::Held(HeldCommand::Notify { all }) => {
+            for notice in flush_held_notices(&context, &config, all, Utc::now()).await? {
+                println!("{}  held={}", notice.sender, notice.held);
+            }
+        }
+        Command::Held(HeldCommand::Release { message_ids }) => {
+            let report = release_held(&context, &config, &message_ids).await?;
+            print!("{}", report);
+        }
+        Command::Aliases(AliasCommand::List) => {
+            for alias in list_aliases(&context, &config).await? {
+                println!(
+                    "{}  {}  {} -> {}{}",
+                    alias.last_used_at.to_rfc3339(),
+                    alias.alias,
+                    alias.our_address,
+                    alias.correspondent,
+                    if alias.is_revoked() {
+                        "  (revoked)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        Command::Aliases(AliasCommand::Revoke { aliases }) => {
+            for alias in aliases {
+                let record = revoke_alias(&context, &config, &alias, Utc::now()).await?;
+                println!("revoked {}  ({})", record.alias, record.correspondent);
+            }
+        }
+        Command::Unknown(UnknownCommand::Report { days, top, send }) => {
+            let report = unknown_recipient_report(&context, &config, days, top, Utc::now()).await?;
+            if send {
+                send_unknown_recipient_report(&context, &config, &report).await?;
+            } else {
+                print!("{}", report);
+            }
+        }
+        Command::Index(IndexCommand::Query {
+            from,
+            since,
+            until,
+            subject,
+            outcome,
+            json,
+        }) => {
+            if config.index_prefix.is_none() {
+                return Err(Error::from("INDEX_PREFIX is not set"));
+            }
+            let query = IndexQuery {
+                sender: from,
+                window: TimeWindow { since, until },
+                subject,
+                outcome,
+            };
+            for record in query_index(&context, &config, &query, Utc::now()).await? {
+                if json {
+                    println!("{}", serde_json::to_string(&record)?);
+                    continue;
+                }
+                println!(
+                    "{}  {}  {:<9}  {}  {}",
+                    record.date.to_rfc3339(),
+                    record.message_id,
+                    record.reason.as_deref().unwrap_or(&record.outcome),
+                    record.from.as_deref().unwrap_or("-"),
+                    record.subject.as_deref().unwrap_or("")
+                );
+            }
+        }
+        Command::Thread(command) => {
+            let prefix = config
+                .thread_prefix
+                .as_deref()
+                .ok_or_else(|| Error::from("THREAD_PREFIX is not set"))?;
+            let id = match &command {
+                ThreadCommand::Show { id } | ThreadCommand::Export { id, .. } => id,
+            };
+            let thread = load_thread(&context, &config, prefix, id)
+                .await?
+                .ok_or_else(|| Error::from(format!("No thread for {}", id)))?;
+
+            match command {
+                ThreadCommand::Show { .. } => {
+                    println!(
+                        "{}  {}",
+                        thread.thread_id,
+                        thread.subject.as_deref().unwrap_or("")
+                    );
+                    for message in &thread.messages {
+                        println!(
+                            "{}  {}  {}  {}",
+                            message.date.to_rfc3339(),
+                            message.stored_id,
+                            message.from.as_deref().unwrap_or("-"),
+                            message.message_id.as_deref().unwrap_or("-")
+                        );
+                    }
+                }
+                ThreadCommand::Export { output, .. } => {
+                    let mbox = conversation_mbox(&context, &config, &thread).await?;
+                    match output {
+                        Some(path) => std::fs::write(path, mbox)?,
+                        None => std::io::stdout().write_all(&mbox)?,
+                    }
+                }
+            }
+        }
+        Command::Export {
+            since,
+            until,
+            format,
+            output,
+            store_dir,
+        } => {
+            let options = ExportOptions {
+                window: TimeWindow { since, until },
+                format,
+            };
+            let store: &dyn MailStore = match &store_dir {
+                Some(dir) => &FsStore::new(dir),
+                None => context.store.as_ref(),
+            };
+            let report = export_mail(store, &config, &options, &output).await?;
+            print!("{}", report);
+        }
+    }
+
+    Ok(())
+}
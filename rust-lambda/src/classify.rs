@@ -0,0 +1,234 @@
+use crate::email::{parse_email, EmailError};
+use crate::routing::is_valid_route_name;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Header naming the category of a classified message, e.g.
+/// `X-Processor-Classification: booking; confidence=0.75`
+pub const CLASSIFICATION_HEADER: &str = "X-Processor-Classification";
+
+/// Matches in the subject count double: it is short and written on purpose
+const SUBJECT_WEIGHT: f64 = 2.0;
+const BODY_WEIGHT: f64 = 1.0;
+
+#[derive(Error, Debug)]
+pub enum ClassifyError {
+    #[error("Invalid classifier rules: {0}")]
+    InvalidJson(String),
+    #[error("Invalid category name '{0}': use letters, digits, '-', '_' or '.'")]
+    InvalidCategory(String),
+    #[error("Invalid pattern '{0}': {1}")]
+    InvalidPattern(String, String),
+}
+
+/// One category's rule as configured in `CLASSIFIER_RULES`:
+/// `{"category": "booking", "keywords": ["gig", "wedding"], "patterns": ["available on \\w+day"], "subject_tag": "[booking]"}`
+#[derive(Debug, Clone, Deserialize)]
+struct RuleConfig {
+    category: String,
+    /// Whole words or phrases, matched case-insensitively
+    #[serde(default)]
+    keywords: Vec<String>,
+    /// Regular expressions, matched case-insensitively
+    #[serde(default)]
+    patterns: Vec<String>,
+    #[serde(default)]
+    subject_tag: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    category: String,
+    matchers: Vec<Regex>,
+    subject_tag: Option<String>,
+}
+
+impl Rule {
+    /// Weighted count of distinct matchers hitting the subject and body
+    fn score(&self, subject: &str, body: &str) -> f64 {
+        self.matchers
+            .iter()
+            .map(|matcher| {
+                let mut score = 0.0;
+                if matcher.is_match(subject) {
+                    score += SUBJECT_WEIGHT;
+                }
+                if matcher.is_match(body) {
+                    score += BODY_WEIGHT;
+                }
+                score
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub category: String,
+    /// 0.0 to 1.0
+    pub confidence: f64,
+    pub subject_tag: Option<String>,
+}
+
+impl Classification {
+    /// Value of the [`CLASSIFICATION_HEADER`]
+    pub fn header_value(&self) -> String {
+        format!("{}; confidence={:.2}", self.category, self.confidence)
+    }
+}
+
+/// Keyword and regex classifier for inbound mail (bookings, lessons, spam...)
+#[derive(Debug, Clone)]
+pub struct Classifier {
+    rules: Vec<Rule>,
+    min_confidence: f64,
+}
+
+impl Classifier {
+    pub fn from_json(json: &str, min_confidence: f64) -> Result<Self, ClassifyError> {
+        let configs: Vec<RuleConfig> =
+            serde_json::from_str(json).map_err(|e| ClassifyError::InvalidJson(e.to_string()))?;
+
+        let mut rules = Vec::with_capacity(configs.len());
+        for config in configs {
+            if !is_valid_route_name(&config.category) {
+                return Err(ClassifyError::InvalidCategory(config.category));
+            }
+
+            let keywords = config
+                .keywords
+                .iter()
+                .map(|keyword| format!(r"\b{}\b", regex::escape(keyword.trim())));
+            let matchers = keywords
+                .chain(config.patterns.iter().cloned())
+                .map(|pattern| {
+                    RegexBuilder::new(&pattern)
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| ClassifyError::InvalidPattern(pattern.clone(), e.to_string()))
+                })
+                .collect::<Result<_, _>>()?;
+
+            rules.push(Rule {
+                category: config.category,
+                matchers,
+                subject_tag: config.subject_tag,
+            });
+        }
+
+        Ok(Self {
+            rules,
+            min_confidence,
+        })
+    }
+
+    /// Best category for the message, if any reaches the minimum confidence.
+    /// Confidence is the winner's share of all matched evidence, discounted
+    /// when there is little of it: one subject keyword alone gives 0.75, one
+    /// body keyword 0.5.
+    pub fn classify(&self, subject: &str, body: &str) -> Option<Classification> {
+        let scores: Vec<(&Rule, f64)> = self
+            .rules
+            .iter()
+            .map(|rule| (rule, rule.score(subject, body)))
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+
+        let total: f64 = scores.iter().map(|(_, score)| score).sum();
+        // Earlier rules win ties
+        let (rule, best) = scores
+            .iter()
+            .fold(None, |best: Option<&(&Rule, f64)>, candidate| match best {
+                Some(current) if current.1 >= candidate.1 => Some(current),
+                _ => Some(candidate),
+            })?;
+
+        let confidence = (best / total) * (1.0 - 0.5f64.powf(*best));
+        (confidence >= self.min_confidence).then(|| Classification {
+            category: rule.category.clone(),
+            confidence,
+            subject_tag: rule.subject_tag.clone(),
+        })
+    }
+
+    /// Classify a raw message by its subject and text body
+    pub fn classify_email(&self, raw_email: &[u8]) -> Result<Option<Classification>, EmailError> {
+        let parsed = parse_email(raw_email)?;
+        Ok(self.classify(parsed.subject.as_str(), parsed.body.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"[
+        {"category": "booking", "keywords": ["gig", "wedding", "book you"], "subject_tag": "[booking]"},
+        {"category": "lessons", "keywords": ["lesson", "lessons", "teach"], "patterns": ["\\bmy (son|daughter)\\b"]},
+        {"category": "spam", "keywords": ["seo", "backlinks", "guest post"]}
+    ]"#;
+
+    fn classifier() -> Classifier {
+        Classifier::from_json(RULES, 0.5).unwrap()
+    }
+
+    #[test]
+    fn test_booking_inquiry() {
+        let result = classifier()
+            .classify(
+                "Wedding on June 6",
+                "We'd love to book you for our reception.",
+            )
+            .unwrap();
+        assert_eq!(result.category, "booking");
+        assert_eq!(result.subject_tag.as_deref(), Some("[booking]"));
+        // 2 for the subject keyword plus 1 for the body phrase, no competition
+        assert!((result.confidence - 0.875).abs() < 1e-9);
+        assert_eq!(result.header_value(), "booking; confidence=0.88");
+    }
+
+    #[test]
+    fn test_pattern_rule() {
+        let result = classifier()
+            .classify("Question", "Do you teach beginners? My daughter is 9.")
+            .unwrap();
+        assert_eq!(result.category, "lessons");
+    }
+
+    #[test]
+    fn test_keywords_match_whole_words_only() {
+        // "gigabyte" must not count as "gig"
+        assert_eq!(classifier().classify("Hello", "A gigabyte of photos"), None);
+    }
+
+    #[test]
+    fn test_mixed_evidence_lowers_confidence() {
+        let lenient = Classifier::from_json(RULES, 0.4).unwrap();
+        let result = lenient.classify("Gig", "Also, do you do lessons?");
+        // booking 2 vs lessons 1: share 2/3, discounted to 0.5
+        assert_eq!(result.unwrap().category, "booking");
+
+        let strict = Classifier::from_json(RULES, 0.6).unwrap();
+        assert_eq!(strict.classify("Gig", "Also, do you do lessons?"), None);
+    }
+
+    #[test]
+    fn test_classify_email_uses_text_part() {
+        let email = b"From: agency@example.com\r\nSubject: Partnership\r\nContent-Type: multipart/alternative; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nWe offer SEO and backlinks for your site.\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>We offer SEO</p>\r\n--b--";
+        let result = classifier().classify_email(email).unwrap().unwrap();
+        assert_eq!(result.category, "spam");
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        assert!(matches!(
+            Classifier::from_json(r#"[{"category": "gig bookings"}]"#, 0.5),
+            Err(ClassifyError::InvalidCategory(_))
+        ));
+        assert!(matches!(
+            Classifier::from_json(r#"[{"category": "x", "patterns": ["("]}]"#, 0.5),
+            Err(ClassifyError::InvalidPattern(_, _))
+        ));
+    }
+}
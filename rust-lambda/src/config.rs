@@ -1,12 +1,102 @@
+use crate::arc::ArcSigner;
+use crate::attachment_policy::AttachmentPolicy;
+use crate::autoreply::{AutomatedKind, AutomatedMailPolicy};
+use crate::classify::Classifier;
+use crate::mime::OriginalHeader;
+use crate::ratelimit::RateLimit;
+use crate::recipients::UnknownRecipientPolicy;
+use crate::responder::AutoResponder;
+use crate::routing::RoutingTable;
+use crate::scanner::ClamdScanner;
+use crate::senders::SenderRules;
+use chrono::Duration;
 use std::env;
 use thiserror::Error;
 
+const DEFAULT_FORWARDER_EMAIL: &str = "forwarder@jimmillerdrums.com";
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub email_bucket: String,
     pub incoming_prefix: String,
     pub forward_to_email: String,
     pub max_email_size_mb: u32,
+    /// Copy forwarded and skipped messages here after processing (disabled when unset)
+    pub processed_prefix: Option<String>,
+    /// Copy messages that failed to forward here (disabled when unset)
+    pub failed_prefix: Option<String>,
+    /// Where failure records for permanently failing messages are written
+    pub dead_letter_prefix: String,
+    /// Failed attempts after which even a retryable error is dead-lettered
+    pub max_attempts: u32,
+    /// Envelope and header sender of forwarded messages
+    pub forwarder_email: String,
+    /// Refuse to forward a message that has already passed through this many times
+    pub max_forward_hops: u32,
+    /// Handling of out-of-office replies and other auto-submitted mail
+    pub auto_reply_policy: AutomatedMailPolicy,
+    /// Handling of bounces and delivery status notifications
+    pub bounce_policy: AutomatedMailPolicy,
+    /// Key for signing SRS return addresses (forwarded mail keeps the SES default
+    /// return path when unset)
+    pub srs_secret: Option<String>,
+    /// Bounces to return addresses older than this are not trusted
+    pub srs_max_age_days: u32,
+    /// Where records of bounces to our return addresses are written
+    pub bounce_prefix: String,
+    /// Tell the original sender when a forwarded message bounces
+    pub notify_bounced_senders: bool,
+    /// Headers kept as `X-Original-*` on forwarded messages
+    pub original_headers: Vec<OriginalHeader>,
+    /// Seals forwarded messages with an ARC set when a key is configured
+    pub arc_signer: Option<ArcSigner>,
+    /// Per-address routes with their subject tags
+    pub routes: RoutingTable,
+    /// Keyword/regex classifier feeding routing and subject tags (off when unset)
+    pub classifier: Option<Classifier>,
+    /// Sender allow and block lists
+    pub sender_rules: SenderRules,
+    /// Where messages quarantined by policy are copied for review
+    pub quarantine_prefix: String,
+    /// Per-sender limit on forwarded messages (off when unset)
+    pub rate_limit: Option<RateLimit>,
+    /// Where sender windows for the rate limiter are kept
+    pub rate_limit_prefix: String,
+    /// Where messages held by the rate limiter are copied
+    pub held_prefix: String,
+    /// Vacation-style acknowledgements (off when unset)
+    pub auto_responder: Option<AutoResponder>,
+    /// Where the auto-responder records whom it answered and when
+    pub responder_prefix: String,
+    /// Key for `reply+` relay addresses and conversation aliases; forwarded
+    /// mail gets a masked `Reply-To` and replies go out through the domain
+    /// when set
+    pub relay_secret: Option<String>,
+    /// Sender of replies relayed through signed addresses (the forwarder
+    /// address when unset); conversation aliases use the address written to
+    pub relay_from_email: Option<String>,
+    /// Where per-conversation reply aliases are kept
+    pub alias_prefix: String,
+    /// Local parts or full addresses that receive mail, alongside every route
+    /// address (every recipient is known when empty)
+    pub known_recipients: Vec<String>,
+    /// Handling of mail to none of the known recipients
+    pub unknown_recipient_policy: UnknownRecipientPolicy,
+    /// Where daily hit counts for unknown recipients are kept
+    pub unknown_recipient_prefix: String,
+    /// Archive the attachments of accepted mail here, one prefix per message
+    /// (disabled when unset)
+    pub attachment_prefix: Option<String>,
+    /// Handling of executables, scripts and macro documents attached to mail
+    pub attachment_policy: AttachmentPolicy,
+    /// clamd scanner whose positive verdicts quarantine the message (off
+    /// when unset)
+    pub scanner: Option<ClamdScanner>,
+    /// Write a JSON Lines record of every processed message here, one
+    /// partition per day (disabled when unset)
+    pub index_prefix: Option<String>,
+    /// Where the conversation thread index is kept (disabled when unset)
+    pub thread_prefix: Option<String>,
 }
 
 #[derive(Error, Debug)]
@@ -40,22 +130,387 @@ impl Config {
             )));
         }
 
+        let processed_prefix = optional_env("PROCESSED_PREFIX");
+        let failed_prefix = optional_env("FAILED_PREFIX");
+
+        let dead_letter_prefix =
+            optional_env("DEAD_LETTER_PREFIX").unwrap_or_else(|| "dead-letter".to_string());
+
+        let max_attempts = env::var("MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(3);
+
+        if max_attempts == 0 {
+            return Err(ConfigError::InvalidValue(
+                "MAX_ATTEMPTS must be at least 1".to_string(),
+            ));
+        }
+
+        let forwarder_email =
+            optional_env("FORWARDER_EMAIL").unwrap_or_else(|| DEFAULT_FORWARDER_EMAIL.to_string());
+
+        let max_forward_hops = env::var("MAX_FORWARD_HOPS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(3);
+
+        if max_forward_hops == 0 {
+            return Err(ConfigError::InvalidValue(
+                "MAX_FORWARD_HOPS must be at least 1".to_string(),
+            ));
+        }
+
+        let auto_reply_policy = policy_env("AUTO_REPLY_POLICY")?;
+        let bounce_policy = policy_env("BOUNCE_POLICY")?;
+
+        let srs_secret = optional_env("SRS_SECRET");
+
+        let srs_max_age_days = env::var("SRS_MAX_AGE_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(21);
+
+        if srs_max_age_days == 0 {
+            return Err(ConfigError::InvalidValue(
+                "SRS_MAX_AGE_DAYS must be at least 1".to_string(),
+            ));
+        }
+
+        let bounce_prefix = optional_env("BOUNCE_PREFIX").unwrap_or_else(|| "bounces".to_string());
+
+        let notify_bounced_senders = bool_env("NOTIFY_BOUNCED_SENDERS")?;
+
+        let original_headers = match optional_env("PRESERVE_ORIGINAL_HEADERS") {
+            None => OriginalHeader::ALL.to_vec(),
+            Some(v) if v.eq_ignore_ascii_case("none") => Vec::new(),
+            Some(v) => v
+                .split(',')
+                .filter(|name| !name.trim().is_empty())
+                .map(|name| {
+                    name.parse().map_err(|e| {
+                        ConfigError::InvalidValue(format!("PRESERVE_ORIGINAL_HEADERS: {}", e))
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        let arc_signer =
+            match (
+                optional_env("ARC_SELECTOR"),
+                optional_env("ARC_PRIVATE_KEY"),
+            ) {
+                (Some(selector), Some(key)) => {
+                    let domain = forwarder_email
+                        .rsplit_once('@')
+                        .map(|(_, domain)| domain)
+                        .unwrap_or(&forwarder_email);
+                    Some(ArcSigner::new(domain, &selector, &key).map_err(|e| {
+                        ConfigError::InvalidValue(format!("ARC_PRIVATE_KEY: {}", e))
+                    })?)
+                }
+                (None, None) => None,
+                _ => {
+                    return Err(ConfigError::InvalidValue(
+                        "ARC_SELECTOR and ARC_PRIVATE_KEY must be set together".to_string(),
+                    ))
+                }
+            };
+
+        let routes = optional_env("ROUTES")
+            .map(|json| RoutingTable::from_json(&json))
+            .transpose()
+            .map_err(|e| ConfigError::InvalidValue(format!("ROUTES: {}", e)))?
+            .unwrap_or_default();
+
+        let min_confidence = match optional_env("CLASSIFIER_MIN_CONFIDENCE") {
+            Some(v) => v
+                .parse::<f64>()
+                .ok()
+                .filter(|c| (0.0..=1.0).contains(c))
+                .ok_or_else(|| {
+                    ConfigError::InvalidValue(format!(
+                        "CLASSIFIER_MIN_CONFIDENCE must be between 0 and 1, got {}",
+                        v
+                    ))
+                })?,
+            None => 0.5,
+        };
+
+        let classifier = optional_env("CLASSIFIER_RULES")
+            .map(|json| Classifier::from_json(&json, min_confidence))
+            .transpose()
+            .map_err(|e| ConfigError::InvalidValue(format!("CLASSIFIER_RULES: {}", e)))?;
+
+        let sender_rules = optional_env("SENDER_RULES")
+            .map(|json| SenderRules::from_json(&json))
+            .transpose()
+            .map_err(|e| ConfigError::InvalidValue(format!("SENDER_RULES: {}", e)))?
+            .unwrap_or_default();
+
+        let quarantine_prefix =
+            optional_env("QUARANTINE_PREFIX").unwrap_or_else(|| "quarantine".to_string());
+
+        let rate_limit_window = env::var("RATE_LIMIT_WINDOW_MINUTES")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(60);
+
+        if rate_limit_window < 1 {
+            return Err(ConfigError::InvalidValue(
+                "RATE_LIMIT_WINDOW_MINUTES must be at least 1".to_string(),
+            ));
+        }
+
+        let per_address = limit_env("RATE_LIMIT_PER_SENDER")?;
+        let per_domain = limit_env("RATE_LIMIT_PER_DOMAIN")?;
+        let rate_limit = (per_address.is_some() || per_domain.is_some()).then(|| RateLimit {
+            per_address,
+            per_domain,
+            window: Duration::minutes(rate_limit_window),
+        });
+
+        let rate_limit_prefix =
+            optional_env("RATE_LIMIT_PREFIX").unwrap_or_else(|| "rate-limit".to_string());
+        let held_prefix = optional_env("HELD_PREFIX").unwrap_or_else(|| "held".to_string());
+
+        let auto_responder = optional_env("AUTO_RESPONDER")
+            .map(|json| AutoResponder::from_json(&json))
+            .transpose()
+            .map_err(|e| ConfigError::InvalidValue(format!("AUTO_RESPONDER: {}", e)))?;
+
+        let responder_prefix =
+            optional_env("AUTO_RESPONDER_PREFIX").unwrap_or_else(|| "auto-responder".to_string());
+
+        let relay_secret = optional_env("RELAY_SECRET");
+        let relay_from_email = optional_env("RELAY_FROM_EMAIL");
+        let alias_prefix = optional_env("ALIAS_PREFIX").unwrap_or_else(|| "aliases".to_string());
+
+        let known_recipients = optional_env("KNOWN_RECIPIENTS")
+            .map(|v| {
+                v.split(',')
+                    .map(|address| address.trim().to_lowercase())
+                    .filter(|address| !address.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let unknown_recipient_policy = optional_env("UNKNOWN_RECIPIENT_POLICY")
+            .map(|v| {
+                v.parse().map_err(|e| {
+                    ConfigError::InvalidValue(format!("UNKNOWN_RECIPIENT_POLICY: {}", e))
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let unknown_recipient_prefix = optional_env("UNKNOWN_RECIPIENT_PREFIX")
+            .unwrap_or_else(|| "unknown-recipients".to_string());
+
+        let attachment_prefix = optional_env("ATTACHMENT_PREFIX");
+
+        let attachment_policy = optional_env("ATTACHMENT_POLICY")
+            .map(|v| {
+                v.parse()
+                    .map_err(|e| ConfigError::InvalidValue(format!("ATTACHMENT_POLICY: {}", e)))
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let scanner = optional_env("CLAMD_ADDRESS")
+            .map(|address| {
+                let address = address
+                    .parse()
+                    .map_err(|e| ConfigError::InvalidValue(format!("CLAMD_ADDRESS: {}", e)))?;
+                let target = optional_env("CLAMD_SCAN")
+                    .map(|v| {
+                        v.parse()
+                            .map_err(|e| ConfigError::InvalidValue(format!("CLAMD_SCAN: {}", e)))
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                let timeout_seconds = env::var("CLAMD_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(30);
+                Ok(ClamdScanner {
+                    address,
+                    target,
+                    timeout: std::time::Duration::from_secs(timeout_seconds),
+                })
+            })
+            .transpose()?;
+
+        let index_prefix = optional_env("INDEX_PREFIX");
+
+        let thread_prefix = optional_env("THREAD_PREFIX");
+
         Ok(Config {
             email_bucket,
             incoming_prefix,
             forward_to_email,
             max_email_size_mb,
+            processed_prefix,
+            failed_prefix,
+            dead_letter_prefix,
+            max_attempts,
+            forwarder_email,
+            max_forward_hops,
+            auto_reply_policy,
+            bounce_policy,
+            srs_secret,
+            srs_max_age_days,
+            bounce_prefix,
+            notify_bounced_senders,
+            original_headers,
+            arc_signer,
+            routes,
+            classifier,
+            sender_rules,
+            quarantine_prefix,
+            rate_limit,
+            rate_limit_prefix,
+            held_prefix,
+            auto_responder,
+            responder_prefix,
+            relay_secret,
+            relay_from_email,
+            alias_prefix,
+            known_recipients,
+            unknown_recipient_policy,
+            unknown_recipient_prefix,
+            attachment_prefix,
+            attachment_policy,
+            scanner,
+            index_prefix,
+            thread_prefix,
         })
     }
 
+    /// Only what reading stored mail needs (`EMAIL_BUCKET`, `INCOMING_PREFIX`
+    /// and optionally `FORWARDER_EMAIL`), for offline use such as exporting
+    /// a synced copy of the bucket; everything else keeps its default
+    pub fn storage_from_env() -> Result<Self, ConfigError> {
+        let email_bucket = env::var("EMAIL_BUCKET")
+            .map_err(|_| ConfigError::MissingEnvVar("EMAIL_BUCKET".to_string()))?;
+        let incoming_prefix = env::var("INCOMING_PREFIX")
+            .map_err(|_| ConfigError::MissingEnvVar("INCOMING_PREFIX".to_string()))?;
+
+        let mut config = Config::new(email_bucket, incoming_prefix, String::new());
+        if let Some(forwarder_email) = optional_env("FORWARDER_EMAIL") {
+            config.forwarder_email = forwarder_email;
+        }
+        Ok(config)
+    }
+
     pub fn new(email_bucket: String, incoming_prefix: String, forward_to_email: String) -> Self {
         Config {
             email_bucket,
             incoming_prefix,
             forward_to_email,
             max_email_size_mb: 10,
+            processed_prefix: None,
+            failed_prefix: None,
+            dead_letter_prefix: "dead-letter".to_string(),
+            max_attempts: 3,
+            forwarder_email: DEFAULT_FORWARDER_EMAIL.to_string(),
+            max_forward_hops: 3,
+            auto_reply_policy: AutomatedMailPolicy::default(),
+            bounce_policy: AutomatedMailPolicy::default(),
+            srs_secret: None,
+            srs_max_age_days: 21,
+            bounce_prefix: "bounces".to_string(),
+            notify_bounced_senders: false,
+            original_headers: OriginalHeader::ALL.to_vec(),
+            arc_signer: None,
+            routes: RoutingTable::default(),
+            classifier: None,
+            sender_rules: SenderRules::default(),
+            quarantine_prefix: "quarantine".to_string(),
+            rate_limit: None,
+            rate_limit_prefix: "rate-limit".to_string(),
+            held_prefix: "held".to_string(),
+            auto_responder: None,
+            responder_prefix: "auto-responder".to_string(),
+            relay_secret: None,
+            relay_from_email: None,
+            alias_prefix: "aliases".to_string(),
+            known_recipients: Vec::new(),
+            unknown_recipient_policy: UnknownRecipientPolicy::default(),
+            unknown_recipient_prefix: "unknown-recipients".to_string(),
+            attachment_prefix: None,
+            attachment_policy: AttachmentPolicy::default(),
+            scanner: None,
+            index_prefix: None,
+            thread_prefix: None,
         }
     }
+
+    pub fn automated_policy(&self, kind: AutomatedKind) -> AutomatedMailPolicy {
+        if kind.is_bounce() {
+            self.bounce_policy
+        } else {
+            self.auto_reply_policy
+        }
+    }
+
+    /// Domain part of the forwarder address, e.g. `jimmillerdrums.com`
+    pub fn forwarder_domain(&self) -> &str {
+        self.forwarder_email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or(&self.forwarder_email)
+    }
+
+    /// Address relayed replies are sent from
+    pub fn relay_from(&self) -> &str {
+        self.relay_from_email
+            .as_deref()
+            .unwrap_or(&self.forwarder_email)
+    }
+}
+
+fn policy_env(name: &str) -> Result<AutomatedMailPolicy, ConfigError> {
+    optional_env(name)
+        .map(|v| {
+            v.parse()
+                .map_err(|e| ConfigError::InvalidValue(format!("{}: {}", name, e)))
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+fn bool_env(name: &str) -> Result<bool, ConfigError> {
+    optional_env(name)
+        .map(|v| {
+            v.to_lowercase().parse::<bool>().map_err(|_| {
+                ConfigError::InvalidValue(format!("{} must be true or false, got '{}'", name, v))
+            })
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Optional message count limit; must be at least 1 when set
+fn limit_env(name: &str) -> Result<Option<u32>, ConfigError> {
+    optional_env(name)
+        .map(|v| {
+            v.parse::<u32>()
+                .ok()
+                .filter(|limit| *limit >= 1)
+                .ok_or_else(|| {
+                    ConfigError::InvalidValue(format!("{} must be at least 1, got '{}'", name, v))
+                })
+        })
+        .transpose()
+}
+
+/// Read an optional environment variable, treating an empty value as unset
+fn optional_env(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
 }
 
 #[cfg(test)]
@@ -75,6 +530,32 @@ mod tests {
         assert_eq!(config.incoming_prefix, "incoming");
         assert_eq!(config.forward_to_email, "test@example.com");
         assert_eq!(config.max_email_size_mb, 10);
+        assert!(config.processed_prefix.is_none());
+        assert!(config.failed_prefix.is_none());
+        assert_eq!(config.dead_letter_prefix, "dead-letter");
+        assert_eq!(config.max_attempts, 3);
+        assert_eq!(config.forwarder_domain(), "jimmillerdrums.com");
+        assert!(config.srs_secret.is_none());
+        assert_eq!(config.bounce_prefix, "bounces");
+        assert!(!config.notify_bounced_senders);
+        assert_eq!(config.original_headers, OriginalHeader::ALL);
+        assert!(config.sender_rules.is_empty());
+        assert_eq!(config.quarantine_prefix, "quarantine");
+        assert!(config.rate_limit.is_none());
+        assert_eq!(config.held_prefix, "held");
+        assert!(config.relay_secret.is_none());
+        assert_eq!(config.relay_from(), "forwarder@jimmillerdrums.com");
+        assert!(config.known_recipients.is_empty());
+        assert_eq!(
+            config.unknown_recipient_policy,
+            UnknownRecipientPolicy::CatchAll
+        );
+        assert_eq!(config.unknown_recipient_prefix, "unknown-recipients");
+        assert!(config.attachment_prefix.is_none());
+        assert_eq!(config.attachment_policy, AttachmentPolicy::Forward);
+        assert!(config.scanner.is_none());
+        assert!(config.index_prefix.is_none());
+        assert!(config.thread_prefix.is_none());
     }
 
     #[test]
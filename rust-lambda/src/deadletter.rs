@@ -0,0 +1,242 @@
+use crate::aws::{AppContext, AwsError};
+use crate::backfill::ForwardFailure;
+use crate::config::Config;
+use crate::disposition::{incoming_key, Delivery, ForwardOutcome};
+use crate::domain::{MessageId, S3Key};
+use crate::relay::RelayOutcome;
+use crate::store::StoreError;
+use crate::Handled;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tracing::{info, warn};
+
+/// Failure record for a message that will not succeed by retrying
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterRecord {
+    pub message_id: String,
+    pub s3_key: String,
+    pub error_class: String,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetterRecord {
+    pub fn new(message_id: &MessageId, s3_key: &S3Key, error: &AwsError, attempts: u32) -> Self {
+        Self {
+            message_id: message_id.to_string(),
+            s3_key: s3_key.to_string(),
+            error_class: error.class().to_string(),
+            error: error.to_string(),
+            attempts,
+            failed_at: Utc::now(),
+        }
+    }
+}
+
+/// Whether a failed forward should be captured rather than left to Lambda retries
+pub fn is_dead_letter(error: &AwsError, attempts: u32, config: &Config) -> bool {
+    !error.is_retryable() || attempts >= config.max_attempts
+}
+
+fn dead_letter_key(config: &Config, message_id: &str) -> Result<S3Key, StoreError> {
+    S3Key::try_from(format!("{}/{}.json", config.dead_letter_prefix, message_id))
+        .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+/// Write a failure record for `message_id` to the dead-letter prefix
+pub async fn capture_dead_letter(
+    context: &AppContext,
+    config: &Config,
+    message_id: &MessageId,
+    error: &AwsError,
+    attempts: u32,
+) -> Result<(), AwsError> {
+    let s3_key = crate::disposition::incoming_key(&config.incoming_prefix, message_id)?;
+    let record = DeadLetterRecord::new(message_id, &s3_key, error, attempts);
+
+    let key = dead_letter_key(config, &record.message_id)?;
+    let body = serde_json::to_vec_pretty(&record)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode dead letter: {}", e)))?;
+
+    context
+        .store
+        .put_object(&config.email_bucket, &key, body)
+        .await?;
+
+    info!(
+        "Dead-lettered {} after {} attempt(s): {}",
+        record.message_id, record.attempts, record.error_class
+    );
+    Ok(())
+}
+
+/// All dead-letter records, oldest failure first
+pub async fn list_dead_letters(
+    context: &AppContext,
+    config: &Config,
+) -> Result<Vec<DeadLetterRecord>, AwsError> {
+    let prefix = format!("{}/", config.dead_letter_prefix);
+    let objects = context
+        .store
+        .list_objects(&config.email_bucket, &prefix)
+        .await?;
+
+    let mut records = Vec::new();
+    for object in objects {
+        let body = context
+            .store
+            .get_object(&config.email_bucket, &object.key)
+            .await?;
+        match serde_json::from_slice::<DeadLetterRecord>(&body) {
+            Ok(record) => records.push(record),
+            Err(e) => warn!("Skipping unreadable dead letter {}: {}", object.key, e),
+        }
+    }
+
+    records.sort_by_key(|record| record.failed_at);
+    Ok(records)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplayReport {
+    pub replayed: Vec<String>,
+    pub suppressed: Vec<String>,
+    /// Relay replies, which cannot be authorized again without their SES
+    /// verdicts; their records are kept
+    pub not_replayable: Vec<String>,
+    pub would_replay: Vec<String>,
+    pub failed: Vec<ForwardFailure>,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.would_replay.is_empty() {
+            writeln!(f, "Would replay: {}", self.would_replay.len())?;
+            for message_id in &self.would_replay {
+                writeln!(f, "  {}", message_id)?;
+            }
+        }
+        writeln!(f, "Replayed:     {}", self.replayed.len())?;
+        writeln!(f, "Suppressed:   {}", self.suppressed.len())?;
+        if !self.not_replayable.is_empty() {
+            writeln!(f, "Not replayable: {}", self.not_replayable.len())?;
+            for message_id in &self.not_replayable {
+                writeln!(f, "  {}", message_id)?;
+            }
+        }
+        writeln!(f, "Failed:       {}", self.failed.len())?;
+        for failure in &self.failed {
+            writeln!(f, "  {}: {}", failure.message_id, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Re-process dead-lettered messages (all of them, or only `message_ids`)
+/// through the same paths as new mail. Records of messages that now go
+/// through (or are deliberately suppressed) are removed; failures are
+/// re-captured with an incremented attempt count. Relay replies are reported
+/// as not replayable and left alone.
+pub async fn replay_dead_letters(
+    context: &AppContext,
+    config: &Config,
+    message_ids: &[String],
+    dry_run: bool,
+) -> Result<ReplayReport, AwsError> {
+    let mut report = ReplayReport::default();
+
+    for record in list_dead_letters(context, config).await? {
+        if !message_ids.is_empty() && !message_ids.contains(&record.message_id) {
+            continue;
+        }
+        if dry_run {
+            report.would_replay.push(record.message_id);
+            continue;
+        }
+
+        let message_id = MessageId::try_from(record.message_id.clone())?;
+        match replay_message(context, config, &message_id).await {
+            Ok(Handled::Unverified) => report.not_replayable.push(record.message_id),
+            Ok(handled) => {
+                let key = dead_letter_key(config, &record.message_id)?;
+                context
+                    .store
+                    .delete_object(&config.email_bucket, &key)
+                    .await?;
+                match handled {
+                    Handled::Forward(Delivery {
+                        outcome: ForwardOutcome::Forwarded(_),
+                        ..
+                    })
+                    | Handled::Relay(RelayOutcome::Relayed { .. }) => {
+                        report.replayed.push(record.message_id)
+                    }
+                    _ => report.suppressed.push(record.message_id),
+                }
+            }
+            Err(e) => report.failed.push(ForwardFailure {
+                message_id: record.message_id,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Send a stored message down the path its recipients call for
+async fn replay_message(
+    context: &AppContext,
+    config: &Config,
+    message_id: &MessageId,
+) -> Result<Handled, AwsError> {
+    let key = incoming_key(&config.incoming_prefix, message_id)?;
+    let raw_email = context.store.get_object(&config.email_bucket, &key).await?;
+    let destination = crate::stored_destination(&raw_email);
+    crate::handle_stored_message(context, config, message_id, &destination, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::EmailError;
+
+    fn config() -> Config {
+        Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "me@example.com".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_parse_errors_are_dead_lettered_immediately() {
+        let error = AwsError::from(EmailError::MissingHeader("From".to_string()));
+        assert!(is_dead_letter(&error, 1, &config()));
+    }
+
+    #[test]
+    fn test_throttling_is_retried_until_attempts_exhausted() {
+        let error = AwsError::SesError("ThrottlingException: Rate limit exceeded".to_string());
+        assert!(!is_dead_letter(&error, 1, &config()));
+        assert!(is_dead_letter(&error, config().max_attempts, &config()));
+    }
+
+    #[test]
+    fn test_record_round_trips_as_json() {
+        let record = DeadLetterRecord::new(
+            &MessageId::try_from("abc123".to_string()).unwrap(),
+            &S3Key::try_from("incoming/abc123".to_string()).unwrap(),
+            &AwsError::from(StoreError::NotFound("incoming/abc123".to_string())),
+            2,
+        );
+
+        let json = serde_json::to_string(&record).unwrap();
+        let decoded: DeadLetterRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.error_class, "not_found");
+        assert_eq!(decoded.attempts, 2);
+        assert_eq!(decoded.s3_key, "incoming/abc123");
+    }
+}
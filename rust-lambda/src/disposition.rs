@@ -0,0 +1,251 @@
+use crate::config::Config;
+use crate::domain::{MessageId, S3Key};
+use crate::recipients::UNKNOWN_RECIPIENT_DROPPED_REASON;
+use crate::responder::AutoReplyOutcome;
+use crate::senders::SenderMatch;
+use crate::store::{MailStore, ObjectTags, StoreError};
+use tracing::info;
+
+pub const STATUS_TAG: &str = "processing-status";
+pub const FORWARDED_MESSAGE_ID_TAG: &str = "forwarded-message-id";
+pub const ROUTE_TAG: &str = "route";
+pub const FAILED_ATTEMPTS_TAG: &str = "failed-attempts";
+pub const SKIP_REASON_TAG: &str = "skip-reason";
+
+pub const DEFAULT_ROUTE: &str = "default";
+pub const REPORTS_ROUTE: &str = "reports";
+pub const BOUNCES_ROUTE: &str = "bounces";
+pub const RELAY_ROUTE: &str = "relay";
+pub const CATCH_ALL_ROUTE: &str = "catch-all";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingStatus {
+    Forwarded,
+    Skipped,
+    Failed,
+}
+
+impl ProcessingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingStatus::Forwarded => "forwarded",
+            ProcessingStatus::Skipped => "skipped",
+            ProcessingStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Result of running a stored message through the forwarding pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardOutcome {
+    /// Sent on; carries the SES message id of the forwarded copy
+    Forwarded(String),
+    /// Deliberately not forwarded; carries a short reason slug
+    Suppressed(String),
+}
+
+/// A pipeline outcome with the decisions that led to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    /// Name of the route the message took
+    pub route: String,
+    pub outcome: ForwardOutcome,
+    /// Sender allow/block rule that applied, if any
+    pub sender_rule: Option<SenderMatch>,
+    /// Auto-responder result, when the responder was active for the message
+    pub auto_reply: Option<AutoReplyOutcome>,
+}
+
+/// What happened to a stored message, recorded on its S3 object after processing
+#[derive(Debug, Clone)]
+pub struct Disposition {
+    pub status: ProcessingStatus,
+    pub forwarded_message_id: Option<String>,
+    pub route: String,
+    /// Failed forwarding attempts so far, including this one (failures only)
+    pub failed_attempts: Option<u32>,
+    /// Why a skipped message was not forwarded
+    pub reason: Option<String>,
+}
+
+impl Disposition {
+    pub fn forwarded(route: &str, forwarded_message_id: &str) -> Self {
+        Self {
+            status: ProcessingStatus::Forwarded,
+            forwarded_message_id: Some(forwarded_message_id.to_string()),
+            route: route.to_string(),
+            failed_attempts: None,
+            reason: None,
+        }
+    }
+
+    pub fn skipped(route: &str, reason: &str) -> Self {
+        Self {
+            status: ProcessingStatus::Skipped,
+            forwarded_message_id: None,
+            route: route.to_string(),
+            failed_attempts: None,
+            reason: Some(reason.to_string()),
+        }
+    }
+
+    pub fn failed(route: &str, attempts: u32) -> Self {
+        Self {
+            status: ProcessingStatus::Failed,
+            forwarded_message_id: None,
+            route: route.to_string(),
+            failed_attempts: Some(attempts),
+            reason: None,
+        }
+    }
+
+    pub fn tags(&self) -> ObjectTags {
+        let mut tags = ObjectTags::new();
+        tags.insert(STATUS_TAG.to_string(), self.status.as_str().to_string());
+        tags.insert(ROUTE_TAG.to_string(), self.route.clone());
+        if let Some(forwarded_id) = &self.forwarded_message_id {
+            tags.insert(FORWARDED_MESSAGE_ID_TAG.to_string(), forwarded_id.clone());
+        }
+        if let Some(attempts) = self.failed_attempts {
+            tags.insert(FAILED_ATTEMPTS_TAG.to_string(), attempts.to_string());
+        }
+        if let Some(reason) = &self.reason {
+            tags.insert(SKIP_REASON_TAG.to_string(), reason.clone());
+        }
+        tags
+    }
+
+    /// Prefix the object should be copied to, if outcome copies are enabled
+    fn outcome_prefix<'a>(&self, config: &'a Config) -> Option<&'a str> {
+        // Dropped mail is left to expire rather than kept as processed
+        if self.reason.as_deref() == Some(UNKNOWN_RECIPIENT_DROPPED_REASON) {
+            return None;
+        }
+        match self.status {
+            ProcessingStatus::Forwarded | ProcessingStatus::Skipped => {
+                config.processed_prefix.as_deref()
+            }
+            ProcessingStatus::Failed => config.failed_prefix.as_deref(),
+        }
+    }
+}
+
+/// Key of the raw message SES stored for `message_id`
+pub fn incoming_key(incoming_prefix: &str, message_id: &MessageId) -> Result<S3Key, StoreError> {
+    S3Key::try_from(format!("{}/{}", incoming_prefix, message_id))
+        .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+/// Failed attempts already recorded on the incoming object (0 when unknown)
+pub async fn previous_failed_attempts(
+    store: &dyn MailStore,
+    config: &Config,
+    message_id: &MessageId,
+) -> u32 {
+    let Ok(key) = incoming_key(&config.incoming_prefix, message_id) else {
+        return 0;
+    };
+    store
+        .get_object_tags(&config.email_bucket, &key)
+        .await
+        .ok()
+        .and_then(|tags| tags.get(FAILED_ATTEMPTS_TAG)?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Tag the incoming object with its outcome and optionally copy it to the
+/// processed/failed prefix so lifecycle rules and triage can tell them apart
+pub async fn record_disposition(
+    store: &dyn MailStore,
+    config: &Config,
+    message_id: &MessageId,
+    disposition: &Disposition,
+) -> Result<(), StoreError> {
+    let key = incoming_key(&config.incoming_prefix, message_id)?;
+
+    store
+        .put_object_tags(&config.email_bucket, &key, &disposition.tags())
+        .await?;
+    info!(
+        "Tagged {} as {} (route: {})",
+        key,
+        disposition.status.as_str(),
+        disposition.route
+    );
+
+    if let Some(prefix) = disposition.outcome_prefix(config) {
+        let destination = incoming_key(prefix, message_id)?;
+        store
+            .copy_object(&config.email_bucket, &key, &destination)
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn config() -> Config {
+        Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "me@example.com".to_string(),
+        )
+    }
+
+    fn message_id() -> MessageId {
+        MessageId::try_from("abc123".to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_tags_omit_missing_forwarded_id() {
+        let disposition = Disposition::skipped(REPORTS_ROUTE, "report");
+
+        let tags = disposition.tags();
+        assert_eq!(tags.get(STATUS_TAG).unwrap(), "skipped");
+        assert_eq!(tags.get(ROUTE_TAG).unwrap(), "reports");
+        assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "report");
+        assert!(!tags.contains_key(FORWARDED_MESSAGE_ID_TAG));
+    }
+
+    #[tokio::test]
+    async fn test_record_disposition_tags_in_place() {
+        let store = MemoryStore::new();
+        store.insert("bucket", "incoming/abc123", "raw");
+
+        let disposition = Disposition::forwarded(DEFAULT_ROUTE, "ses-out-1");
+        record_disposition(&store, &config(), &message_id(), &disposition)
+            .await
+            .unwrap();
+
+        let tags = store.tags("bucket", "incoming/abc123").unwrap();
+        assert_eq!(tags.get(FORWARDED_MESSAGE_ID_TAG).unwrap(), "ses-out-1");
+        assert!(!store.contains("bucket", "processed/abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_record_disposition_copies_failed_message() {
+        let store = MemoryStore::new();
+        store.insert("bucket", "incoming/abc123", "raw");
+
+        let mut config = config();
+        config.processed_prefix = Some("processed".to_string());
+        config.failed_prefix = Some("failed".to_string());
+
+        let disposition = Disposition::failed(DEFAULT_ROUTE, 2);
+        record_disposition(&store, &config, &message_id(), &disposition)
+            .await
+            .unwrap();
+
+        let tags = store.tags("bucket", "failed/abc123").unwrap();
+        assert_eq!(tags.get(STATUS_TAG).unwrap(), "failed");
+        assert!(!store.contains("bucket", "processed/abc123"));
+        assert_eq!(
+            previous_failed_attempts(&store, &config, &message_id()).await,
+            2
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use thiserror::Error;
@@ -14,6 +15,8 @@ pub enum DomainError {
     InvalidSubject(String),
     #[error("Invalid email body: {0}")]
     InvalidEmailBody(String),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -143,6 +146,32 @@ impl fmt::Display for EmailBody {
     }
 }
 
+/// Half-open time range `[since, until)`; an unset bound is unbounded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeWindow {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        self.since.is_none_or(|since| at >= since) && self.until.is_none_or(|until| at < until)
+    }
+
+    /// Parse an RFC 3339 timestamp or a plain `YYYY-MM-DD` date (midnight UTC)
+    pub fn parse_bound(value: &str) -> Result<DateTime<Utc>, DomainError> {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok(timestamp.with_timezone(&Utc));
+        }
+
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|midnight| midnight.and_utc())
+            .ok_or_else(|| DomainError::InvalidTimestamp(value.to_string()))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SesEvent {
     #[serde(rename = "Records")]
@@ -157,6 +186,9 @@ pub struct SesRecord {
 #[derive(Debug, Deserialize)]
 pub struct SesMessage {
     pub mail: SesMail,
+    /// Receipt verdicts; absent in hand-built events
+    #[serde(default)]
+    pub receipt: Option<SesReceipt>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -167,10 +199,75 @@ pub struct SesMail {
     pub destination: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SesReceipt {
+    pub spam_verdict: Option<SesVerdict>,
+    pub virus_verdict: Option<SesVerdict>,
+    pub spf_verdict: Option<SesVerdict>,
+    pub dkim_verdict: Option<SesVerdict>,
+    pub dmarc_verdict: Option<SesVerdict>,
+    /// Sender domain's DMARC policy (`none`, `quarantine` or `reject`)
+    pub dmarc_policy: Option<String>,
+}
+
+/// SES verdict, e.g. `PASS`, `FAIL`, `GRAY` or `PROCESSING_FAILED`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SesVerdict {
+    pub status: String,
+}
+
+/// EventBridge scheduled invocation
+#[derive(Debug, Deserialize)]
+pub struct ScheduledEvent {
+    #[serde(rename = "detail-type")]
+    pub detail_type: String,
+}
+
+/// What the Lambda function is invoked with: mail from SES, or the
+/// schedule that sends held-mail notices
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Invocation {
+    Ses(SesEvent),
+    Scheduled(ScheduledEvent),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_invocation_from_ses_or_schedule() {
+        let ses: Invocation = serde_json::from_value(serde_json::json!({
+            "Records": [{
+                "ses": {
+                    "mail": {
+                        "messageId": "abc123",
+                        "source": "fan@example.com",
+                        "destination": ["info@jimmillerdrums.com"]
+                    }
+                }
+            }]
+        }))
+        .unwrap();
+        assert!(matches!(ses, Invocation::Ses(event) if event.records.len() == 1));
+
+        let scheduled: Invocation = serde_json::from_value(serde_json::json!({
+            "version": "0",
+            "id": "53dc4d37-cffa-4f76-80c9-8b7d4a4d2eaa",
+            "detail-type": "Scheduled Event",
+            "source": "aws.events",
+            "time": "2026-03-14T09:30:00Z",
+            "resources": ["arn:aws:events:us-east-1:123456789012:rule/held-notices"],
+            "detail": {}
+        }))
+        .unwrap();
+        assert!(
+            matches!(scheduled, Invocation::Scheduled(event) if event.detail_type == "Scheduled Event")
+        );
+    }
+
     #[test]
     fn test_email_address_valid() {
         let email = EmailAddress::try_from("test@example.com".to_string());
@@ -194,4 +291,17 @@ mod tests {
         let msg_id = MessageId::try_from("".to_string());
         assert!(msg_id.is_err());
     }
+
+    #[test]
+    fn test_time_window_bounds() {
+        let window = TimeWindow {
+            since: Some(TimeWindow::parse_bound("2026-01-14").unwrap()),
+            until: Some(TimeWindow::parse_bound("2026-01-14T17:15:00Z").unwrap()),
+        };
+
+        assert!(window.contains(TimeWindow::parse_bound("2026-01-14T14:02:00Z").unwrap()));
+        assert!(!window.contains(TimeWindow::parse_bound("2026-01-14T17:15:00Z").unwrap()));
+        assert!(!window.contains(TimeWindow::parse_bound("2026-01-13T23:59:59Z").unwrap()));
+        assert!(TimeWindow::parse_bound("yesterday").is_err());
+    }
 }
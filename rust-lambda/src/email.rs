@@ -37,7 +37,12 @@ pub fn parse_email(raw_email: &[u8]) -> Result<ParsedEmail, EmailError> {
 
     let from = extract_email_address(&from_header)?;
 
-    let body_text = parsed
+    // Prefer the first text/plain part of a multipart message
+    let text_part = parsed
+        .parts()
+        .find(|part| part.ctype.mimetype.eq_ignore_ascii_case("text/plain"))
+        .unwrap_or(&parsed);
+    let body_text = text_part
         .get_body()
         .unwrap_or_else(|_| String::from_utf8_lossy(parsed.raw_bytes).to_string());
 
@@ -48,10 +53,12 @@ pub fn parse_email(raw_email: &[u8]) -> Result<ParsedEmail, EmailError> {
     })
 }
 
-fn extract_email_address(from_header: &str) -> Result<String, EmailError> {
-    if let Some(start) = from_header.find('<') {
-        if let Some(end) = from_header.find('>') {
-            return Ok(from_header[start + 1..end].to_string());
+pub fn extract_email_address(from_header: &str) -> Result<String, EmailError> {
+    // The address is the last angle-bracketed part; a display name may
+    // contain brackets of its own
+    if let Some(start) = from_header.rfind('<') {
+        if let Some(end) = from_header[start..].find('>') {
+            return Ok(from_header[start + 1..start + end].to_string());
         }
     }
 
@@ -129,6 +136,14 @@ mod tests {
         assert_eq!(result.unwrap(), "john@example.com");
     }
 
+    #[test]
+    fn test_extract_email_with_brackets_in_display_name() {
+        let result = extract_email_address("\"a>b\" <x@example.com>");
+        assert_eq!(result.unwrap(), "x@example.com");
+        let result = extract_email_address("\"a<b\" <x@example.com>");
+        assert_eq!(result.unwrap(), "x@example.com");
+    }
+
     #[test]
     fn test_extract_sender_name() {
         let name = extract_sender_name("\"John Doe\" <john@example.com>");
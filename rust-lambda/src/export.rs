@@ -0,0 +1,192 @@
+use crate::backfill::message_id_from_key;
+use crate::config::Config;
+use crate::domain::TimeWindow;
+use crate::mbox::mboxrd_entry;
+use crate::store::{MailStore, StoreError};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Failed to write export: {0}")]
+    Io(#[from] io::Error),
+    #[error("Storage error: {0}")]
+    Store(#[from] StoreError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// One mboxrd file
+    #[default]
+    Mbox,
+    /// A Maildir tree (`cur`, `new`, `tmp`) with one file per message
+    Maildir,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "mbox" | "mboxrd" => Ok(ExportFormat::Mbox),
+            "maildir" => Ok(ExportFormat::Maildir),
+            other => Err(format!("expected mbox or maildir, got '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    /// Messages stored in this range; unbounded exports everything
+    pub window: TimeWindow,
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub scanned: usize,
+    pub outside_window: usize,
+    pub ignored: usize,
+    pub exported: usize,
+    /// Bytes of raw mail exported
+    pub bytes: u64,
+}
+
+impl fmt::Display for ExportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scanned:        {}", self.scanned)?;
+        writeln!(f, "Outside window: {}", self.outside_window)?;
+        writeln!(f, "Ignored:        {}", self.ignored)?;
+        writeln!(f, "Exported:       {}", self.exported)?;
+        writeln!(f, "Bytes:          {}", self.bytes)?;
+        Ok(())
+    }
+}
+
+/// Where exported messages go, written one at a time
+enum ExportSink {
+    Mbox(BufWriter<File>),
+    Maildir {
+        tmp: PathBuf,
+        cur: PathBuf,
+        host: String,
+    },
+}
+
+impl ExportSink {
+    fn create(format: ExportFormat, output: &Path, host: &str) -> io::Result<Self> {
+        match format {
+            ExportFormat::Mbox => {
+                if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                Ok(ExportSink::Mbox(BufWriter::new(File::create(output)?)))
+            }
+            ExportFormat::Maildir => {
+                for dir in ["tmp", "new", "cur"] {
+                    fs::create_dir_all(output.join(dir))?;
+                }
+                Ok(ExportSink::Maildir {
+                    tmp: output.join("tmp"),
+                    cur: output.join("cur"),
+                    host: host.to_string(),
+                })
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        message_id: &str,
+        raw_email: &[u8],
+        stored_at: DateTime<Utc>,
+    ) -> io::Result<()> {
+        match self {
+            ExportSink::Mbox(writer) => writer.write_all(&mboxrd_entry(raw_email, stored_at)),
+            ExportSink::Maildir { tmp, cur, host } => {
+                // `<time>.<unique>.<host>`; the SES message id is already
+                // unique. Written to tmp and moved into cur as the Maildir
+                // convention asks, marked as not yet seen.
+                let name = format!("{}.{}.{}", stored_at.timestamp(), message_id, host);
+                let staged = tmp.join(&name);
+                fs::write(&staged, raw_email)?;
+                fs::rename(&staged, cur.join(format!("{}:2,", name)))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ExportSink::Mbox(mut writer) => writer.flush(),
+            ExportSink::Maildir { .. } => Ok(()),
+        }
+    }
+}
+
+/// Write the messages stored under `incoming_prefix` in the window to
+/// `output`, oldest first, reading one message at a time. An mbox file is
+/// replaced; a Maildir tree is created or added to. Works against any
+/// store, so a bucket synced to disk can be exported with `FsStore`.
+pub async fn export_mail(
+    store: &dyn MailStore,
+    config: &Config,
+    options: &ExportOptions,
+    output: &Path,
+) -> Result<ExportReport, ExportError> {
+    let prefix = format!("{}/", config.incoming_prefix);
+    let mut objects = store.list_objects(&config.email_bucket, &prefix).await?;
+    objects.sort_by(|a, b| {
+        a.last_modified
+            .cmp(&b.last_modified)
+            .then_with(|| a.key.as_str().cmp(b.key.as_str()))
+    });
+
+    let mut report = ExportReport {
+        scanned: objects.len(),
+        ..Default::default()
+    };
+    let mut sink = ExportSink::create(options.format, output, config.forwarder_domain())?;
+
+    for object in objects {
+        if !options.window.contains(object.last_modified) {
+            report.outside_window += 1;
+            continue;
+        }
+        let Some(message_id) = message_id_from_key(&prefix, &object.key) else {
+            report.ignored += 1;
+            continue;
+        };
+
+        let raw_email = store.get_object(&config.email_bucket, &object.key).await?;
+        sink.write(message_id.as_str(), &raw_email, object.last_modified)?;
+        report.exported += 1;
+        report.bytes += raw_email.len() as u64;
+    }
+    sink.finish()?;
+
+    info!(
+        "Exported {} of {} message(s) to {}",
+        report.exported,
+        report.scanned,
+        output.display()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("mboxrd".parse(), Ok(ExportFormat::Mbox));
+        assert_eq!("Maildir".parse(), Ok(ExportFormat::Maildir));
+        assert!("pst".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::attachments::extract_attachments;
+use crate::aws::{AppContext, AwsError};
+use crate::config::Config;
+use crate::disposition::Disposition;
+use crate::domain::{MessageId, S3Key, SesMessage, TimeWindow};
+use crate::email::extract_email_address;
+use crate::routing::stored_recipients;
+use crate::store::{ObjectMetadata, StoreError};
+use crate::trace::verdicts_header_value;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use mailparse::{parse_headers, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::info;
+
+const PARTITION_KEY: &str = "date=";
+
+/// One processed message in the index, written as a JSON Lines object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexRecord {
+    pub message_id: String,
+    /// When the message was processed
+    pub date: DateTime<Utc>,
+    /// Address in the From header, or the envelope sender without one
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    pub subject: Option<String>,
+    /// Size of the stored message in bytes
+    pub size: u64,
+    /// Attachment filenames, or content types for unnamed attachments
+    pub attachments: Vec<String>,
+    /// SES receipt verdicts as `spam=pass; virus=pass; ...`
+    pub verdicts: Option<String>,
+    pub route: String,
+    /// `forwarded`, `skipped` or `failed`
+    pub outcome: String,
+    /// Why a skipped message was not forwarded
+    pub reason: Option<String>,
+    pub forwarded_message_id: Option<String>,
+}
+
+impl IndexRecord {
+    /// Describe a stored message and what processing did with it. Unparseable
+    /// messages are still indexed, with whatever could be read.
+    pub fn new(
+        message_id: &MessageId,
+        raw_email: &[u8],
+        ses: Option<&SesMessage>,
+        disposition: &Disposition,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let headers = parse_headers(raw_email).ok().map(|(headers, _)| headers);
+        let header = |name: &str| {
+            headers
+                .as_ref()
+                .and_then(|headers| headers.get_first_value(name))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let from = header("From")
+            .and_then(|from| extract_email_address(&from).ok())
+            .or_else(|| ses.map(|ses| ses.mail.source.clone()));
+        let to = match ses {
+            Some(ses) => ses.mail.destination.clone(),
+            None => stored_recipients(raw_email),
+        };
+        let attachments = extract_attachments(raw_email)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|attachment| attachment.filename.unwrap_or(attachment.content_type))
+            .collect();
+
+        Self {
+            message_id: message_id.to_string(),
+            date: now,
+            from,
+            to,
+            subject: header("Subject"),
+            size: raw_email.len() as u64,
+            attachments,
+            verdicts: ses
+                .and_then(|ses| ses.receipt.as_ref())
+                .and_then(verdicts_header_value),
+            route: disposition.route.clone(),
+            outcome: disposition.status.as_str().to_string(),
+            reason: disposition.reason.clone(),
+            forwarded_message_id: disposition.forwarded_message_id.clone(),
+        }
+    }
+}
+
+/// Filters for `query_index`; every one that is set must match
+#[derive(Debug, Clone, Default)]
+pub struct IndexQuery {
+    /// Case-insensitive substring of the sender, e.g. `@example.com`
+    pub sender: Option<String>,
+    pub window: TimeWindow,
+    /// Case-insensitive substring of the subject
+    pub subject: Option<String>,
+    /// Outcome (`forwarded`, `skipped`, `failed`) or skip reason
+    pub outcome: Option<String>,
+}
+
+impl IndexQuery {
+    pub fn matches(&self, record: &IndexRecord) -> bool {
+        let contains = |value: Option<&str>, needle: &Option<String>| {
+            needle.as_ref().is_none_or(|needle| {
+                value.is_some_and(|value| value.to_lowercase().contains(&needle.to_lowercase()))
+            })
+        };
+
+        self.window.contains(record.date)
+            && contains(record.from.as_deref(), &self.sender)
+            && contains(record.subject.as_deref(), &self.subject)
+            && self.outcome.as_ref().is_none_or(|outcome| {
+                record.outcome.eq_ignore_ascii_case(outcome)
+                    || record
+                        .reason
+                        .as_ref()
+                        .is_some_and(|reason| reason.eq_ignore_ascii_case(outcome))
+            })
+    }
+}
+
+fn partition_prefix(prefix: &str, day: NaiveDate) -> String {
+    format!("{}/{}{}/", prefix, PARTITION_KEY, day.format("%Y-%m-%d"))
+}
+
+/// Day of the partition an index key is in
+fn partition_date(key: &str) -> Option<NaiveDate> {
+    let (_, rest) = key.split_once(PARTITION_KEY)?;
+    let (day, _) = rest.split_once('/')?;
+    NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()
+}
+
+/// Write a message's record to `<prefix>/date=<YYYY-MM-DD>/<message id>.jsonl`.
+/// Each message gets its own object because S3 cannot append; processing a
+/// message again on the same day replaces its record.
+pub async fn write_index_record(
+    context: &AppContext,
+    config: &Config,
+    prefix: &str,
+    record: &IndexRecord,
+) -> Result<(), AwsError> {
+    let key = S3Key::try_from(format!(
+        "{}{}.jsonl",
+        partition_prefix(prefix, record.date.date_naive()),
+        record.message_id
+    ))
+    .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+    let mut body = serde_json::to_vec(record)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode index record: {}", e)))?;
+    body.push(b'\n');
+    context
+        .store
+        .put_object_with_metadata(
+            &config.email_bucket,
+            &key,
+            body,
+            "application/x-ndjson",
+            &ObjectMetadata::new(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Read the partitions the query's window covers and filter their records
+/// locally. A message processed more than once is reported with its latest
+/// record. Records are returned oldest first.
+pub async fn query_index(
+    context: &AppContext,
+    config: &Config,
+    query: &IndexQuery,
+    now: DateTime<Utc>,
+) -> Result<Vec<IndexRecord>, AwsError> {
+    let Some(prefix) = config.index_prefix.as_deref() else {
+        return Ok(Vec::new());
+    };
+
+    // A bounded window lists one partition per day; otherwise list them all
+    let mut objects = Vec::new();
+    match query.window.since {
+        Some(since) => {
+            let until = query.window.until.unwrap_or(now);
+            let mut day = since.date_naive();
+            while day <= until.date_naive() {
+                objects.extend(
+                    context
+                        .store
+                        .list_objects(&config.email_bucket, &partition_prefix(prefix, day))
+                        .await?,
+                );
+                day += Duration::days(1);
+            }
+        }
+        None => {
+            let last_day = query.window.until.map(|until| until.date_naive());
+            objects = context
+                .store
+                .list_objects(&config.email_bucket, &format!("{}/", prefix))
+                .await?
+                .into_iter()
+                .filter(
+                    |object| match (partition_date(object.key.as_str()), last_day) {
+                        (Some(day), Some(last_day)) => day <= last_day,
+                        (day, _) => day.is_some(),
+                    },
+                )
+                .collect();
+        }
+    }
+
+    let mut latest: BTreeMap<String, IndexRecord> = BTreeMap::new();
+    for object in objects {
+        let body = context
+            .store
+            .get_object(&config.email_bucket, &object.key)
+            .await?;
+        for line in String::from_utf8_lossy(&body).lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: IndexRecord = match serde_json::from_str(line) {
+                Ok(record) => record,
+                Err(e) => {
+                    info!("Skipping unreadable index line in {}: {}", object.key, e);
+                    continue;
+                }
+            };
+            match latest.get(&record.message_id) {
+                Some(existing) if existing.date >= record.date => {}
+                _ => {
+                    latest.insert(record.message_id.clone(), record);
+                }
+            }
+        }
+    }
+
+    let mut records: Vec<IndexRecord> = latest
+        .into_values()
+        .filter(|record| query.matches(record))
+        .collect();
+    records.sort_by(|a, b| {
+        a.date
+            .cmp(&b.date)
+            .then_with(|| a.message_id.cmp(&b.message_id))
+    });
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{SesMail, SesReceipt, SesVerdict};
+
+    const BOOKING: &[u8] = b"From: \"Pat Planner\" <pat@example.com>\r\n\
+To: booking@jimmillerdrums.com\r\n\
+Subject: Wedding in June\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Details attached\r\n\
+--b\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename=\"contract.pdf\"\r\n\
+\r\n\
+%PDF\r\n\
+--b--\r\n";
+
+    fn at(value: &str) -> DateTime<Utc> {
+        TimeWindow::parse_bound(value).unwrap()
+    }
+
+    fn record() -> IndexRecord {
+        let ses = SesMessage {
+            mail: SesMail {
+                message_id: "abc123".to_string(),
+                source: "bounce@mailer.example.com".to_string(),
+                destination: vec!["booking@jimmillerdrums.com".to_string()],
+            },
+            receipt: Some(SesReceipt {
+                spam_verdict: Some(SesVerdict {
+                    status: "PASS".to_string(),
+                }),
+                virus_verdict: None,
+                spf_verdict: None,
+                dkim_verdict: None,
+                dmarc_verdict: None,
+                dmarc_policy: None,
+            }),
+        };
+        IndexRecord::new(
+            &MessageId::try_from("abc123".to_string()).unwrap(),
+            BOOKING,
+            Some(&ses),
+            &Disposition::forwarded("booking", "forwarded-id"),
+            at("2026-06-01T10:00:00Z"),
+        )
+    }
+
+    #[test]
+    fn test_index_record() {
+        let record = record();
+        assert_eq!(record.from.as_deref(), Some("pat@example.com"));
+        assert_eq!(record.to, vec!["booking@jimmillerdrums.com"]);
+        assert_eq!(record.subject.as_deref(), Some("Wedding in June"));
+        assert_eq!(record.size, BOOKING.len() as u64);
+        assert_eq!(record.attachments, vec!["contract.pdf"]);
+        assert_eq!(record.verdicts.as_deref(), Some("spam=pass"));
+        assert_eq!(record.route, "booking");
+        assert_eq!(record.outcome, "forwarded");
+        assert_eq!(record.forwarded_message_id.as_deref(), Some("forwarded-id"));
+
+        let line = serde_json::to_string(&record).unwrap();
+        assert!(!line.contains('\n'));
+        assert_eq!(serde_json::from_str::<IndexRecord>(&line).unwrap(), record);
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let mut record = record();
+        assert!(IndexQuery::default().matches(&record));
+
+        let query = IndexQuery {
+            sender: Some("@Example.com".to_string()),
+            window: TimeWindow {
+                since: Some(at("2026-06-01")),
+                until: Some(at("2026-06-02")),
+            },
+            subject: Some("wedding".to_string()),
+            outcome: Some("forwarded".to_string()),
+        };
+        assert!(query.matches(&record));
+
+        record.date = at("2026-06-02T00:00:00Z");
+        assert!(!query.matches(&record));
+
+        record = self::record();
+        record.outcome = "skipped".to_string();
+        record.reason = Some("rate-limited".to_string());
+        assert!(!query.matches(&record));
+        let by_reason = IndexQuery {
+            outcome: Some("rate-limited".to_string()),
+            ..Default::default()
+        };
+        assert!(by_reason.matches(&record));
+
+        record.subject = None;
+        let by_subject = IndexQuery {
+            subject: Some("wedding".to_string()),
+            ..Default::default()
+        };
+        assert!(!by_subject.matches(&record));
+    }
+
+    #[test]
+    fn test_partition_date() {
+        assert_eq!(
+            partition_date("index/date=2026-06-01/abc123.jsonl"),
+            NaiveDate::from_ymd_opt(2026, 6, 1)
+        );
+        assert_eq!(partition_date("index/abc123.jsonl"), None);
+    }
+}
@@ -1,19 +1,100 @@
 #![forbid(unsafe_code)]
 
+pub mod alias;
+pub mod arc;
+pub mod attachment_policy;
+pub mod attachments;
+pub mod auth;
+pub mod autoreply;
 pub mod aws;
+pub mod backfill;
+pub mod classify;
 pub mod config;
+pub mod deadletter;
+pub mod disposition;
 pub mod domain;
 pub mod email;
+pub mod export;
+pub mod index;
+pub mod loops;
+pub mod mbox;
 pub mod mime;
+pub mod ratelimit;
+pub mod recipients;
+pub mod relay;
+pub mod responder;
+pub mod routing;
+pub mod scanner;
+pub mod senders;
+pub mod srs;
+pub mod store;
+pub mod template;
+pub mod thread;
+pub mod trace;
 
+pub use alias::*;
+pub use arc::*;
+pub use attachment_policy::*;
+pub use attachments::*;
+pub use auth::*;
+pub use autoreply::*;
 pub use aws::*;
+pub use backfill::*;
+pub use classify::*;
 pub use config::Config;
+pub use deadletter::*;
+pub use disposition::*;
 pub use domain::*;
 pub use email::*;
+pub use export::*;
+pub use index::*;
+pub use loops::*;
+pub use mbox::*;
 pub use mime::*;
+pub use ratelimit::*;
+pub use recipients::*;
+pub use relay::*;
+pub use responder::*;
+pub use routing::*;
+pub use scanner::*;
+pub use senders::*;
+pub use srs::*;
+pub use store::*;
+pub use template::*;
+pub use thread::*;
+pub use trace::*;
 
+use chrono::Utc;
 use serde_json::{json, Value};
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+/// Handle one Lambda invocation: process mail from SES, or on the schedule
+/// send notices for rate-limited bursts that have ended
+pub async fn process_invocation(
+    invocation: Invocation,
+    context: &AppContext,
+    config: &config::Config,
+) -> Result<Value, lambda_runtime::Error> {
+    match invocation {
+        Invocation::Ses(event) => process_ses_event(event, context, config).await,
+        Invocation::Scheduled(event) => {
+            info!("Processing {}", event.detail_type);
+            let notices = flush_held_notices(context, config, false, Utc::now())
+                .await
+                .map_err(|e| {
+                    error!("Error sending held-mail notices: {}", e);
+                    lambda_runtime::Error::from(e.to_string())
+                })?;
+            Ok(json!({
+                "statusCode": 200,
+                "body": json!({
+                    "message": "Held-mail notices sent",
+                    "notices": notices
+                }).to_string()
+            }))
+        }
+    }
+}
 
 pub async fn process_ses_event(
     event: SesEvent,
@@ -37,40 +118,671 @@ pub async fn process_ses_event(
 
     info!("Processing email: {} to {}", message_id, destination);
 
+    let handled =
+        handle_stored_message(context, config, &message_id, destination, Some(&record.ses))
+            .await
+            .map_err(|e| {
+                error!("Error processing email: {}", e);
+                lambda_runtime::Error::from(e.to_string())
+            })?;
+
+    let body = match handled {
+        Handled::Report => json!({
+            "message": "Report email processed but not forwarded",
+            "messageId": message_id
+        }),
+        Handled::Bounce(bounce) => json!({
+            "message": "Bounce recorded",
+            "messageId": message_id,
+            "originalSender": bounce.original_sender,
+            "notified": bounce.notice_message_id.is_some()
+        }),
+        Handled::Relay(RelayOutcome::Relayed {
+            correspondent,
+            message_id: relayed_id,
+        }) => json!({
+            "message": "Reply relayed",
+            "messageId": message_id,
+            "relayedMessageId": relayed_id,
+            "correspondent": correspondent
+        }),
+        Handled::Relay(RelayOutcome::Refused(refusal)) => json!({
+            "message": "Reply not relayed",
+            "messageId": message_id,
+            "reason": refusal.as_str()
+        }),
+        Handled::Unverified => json!({
+            "message": "Reply not relayed without SES verdicts",
+            "messageId": message_id
+        }),
+        Handled::Forward(delivery) => forward_body(&message_id, &delivery),
+    };
+
+    Ok(json!({
+        "statusCode": 200,
+        "body": body.to_string()
+    }))
+}
+
+/// How a stored message was handled, decided by the address it was sent to
+#[derive(Debug)]
+pub(crate) enum Handled {
+    Report,
+    Bounce(BounceRecord),
+    Relay(RelayOutcome),
+    /// A reply to a relay address re-processed without the SES verdicts that
+    /// authorize its sender; left as it is for a manual decision
+    Unverified,
+    Forward(Delivery),
+}
+
+/// Send a message already stored under `incoming_prefix` down the path its
+/// destination calls for: reports are kept, bounces to SRS return addresses
+/// recorded, replies to relay addresses relayed and everything else
+/// forwarded. Shared by SES events and backfill; `ses` is absent when
+/// re-processing, and relay replies are then left untouched since nothing
+/// authorizes their sender.
+pub(crate) async fn handle_stored_message(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    destination: &str,
+    ses: Option<&SesMessage>,
+) -> Result<Handled, AwsError> {
     if is_report_email(destination) {
         info!("Skipping forwarding for report email to: {}", destination);
-        return Ok(json!({
-            "statusCode": 200,
-            "body": json!({
-                "message": "Report email processed but not forwarded",
-                "messageId": message_id
-            }).to_string()
-        }));
+        let disposition = Disposition::skipped(REPORTS_ROUTE, "report");
+        record_outcome(context, config, message_id, ses, &disposition).await;
+        return Ok(Handled::Report);
     }
 
-    let forward_to = EmailAddress::try_from(config.forward_to_email.clone())?;
+    if config.srs_secret.is_some() && is_srs_address(destination) {
+        let bounce = handle_srs_bounce(context, config, message_id, destination).await?;
+        let disposition = Disposition::skipped(BOUNCES_ROUTE, bounce.reason());
+        record_outcome(context, config, message_id, ses, &disposition).await;
+        return Ok(Handled::Bounce(bounce));
+    }
+
+    if config.relay_secret.is_some() && is_relay_address(destination) {
+        let Some(ses) = ses else {
+            info!(
+                "Leaving reply {} without SES verdicts untouched",
+                message_id
+            );
+            return Ok(Handled::Unverified);
+        };
+        let outcome = relay_stored_reply(context, config, message_id, ses, destination).await?;
+        return Ok(Handled::Relay(outcome));
+    }
+
+    let delivery = forward_stored_message(context, config, message_id, ses).await?;
+    Ok(Handled::Forward(delivery))
+}
+
+/// Stand-in for the SES envelope recipient, which is not kept with the
+/// message: the first report, bounce or relay address in `To`/`Cc`, else
+/// the first recipient
+pub(crate) fn stored_destination(raw_email: &[u8]) -> String {
+    let recipients = stored_recipients(raw_email);
+    recipients
+        .iter()
+        .find(|address| {
+            is_report_email(address) || is_srs_address(address) || is_relay_address(address)
+        })
+        .or(recipients.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Response body for a message that went down the forwarding path
+fn forward_body(message_id: &MessageId, delivery: &Delivery) -> Value {
+    let mut body = match &delivery.outcome {
+        ForwardOutcome::Forwarded(forwarded_message_id) => {
+            info!("Email forwarded successfully: {}", forwarded_message_id);
+            json!({
+                "message": "Email forwarded successfully",
+                "forwardedMessageId": forwarded_message_id
+            })
+        }
+        ForwardOutcome::Suppressed(reason) => json!({
+            "message": "Email processed but not forwarded",
+            "messageId": message_id,
+            "reason": reason
+        }),
+    };
+    if let Some(rule) = &delivery.sender_rule {
+        body["senderRule"] = json!(rule);
+    }
+    match &delivery.auto_reply {
+        Some(AutoReplyOutcome::Sent(reply_id)) => body["autoReplyMessageId"] = json!(reply_id),
+        Some(AutoReplyOutcome::Skipped(reason)) => {
+            body["autoReplySkipped"] = json!(reason.as_str())
+        }
+        None => {}
+    }
+    body
+}
+
+/// Forward a message already stored under `incoming_prefix`, record the
+/// outcome on its S3 object and dead-letter it if it will never succeed.
+/// `ses` is the triggering SES notification, absent when re-processing.
+pub(crate) async fn forward_stored_message(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    ses: Option<&SesMessage>,
+) -> Result<Delivery, AwsError> {
+    let result = deliver_stored_message(context, config, message_id, ses).await;
+
+    let disposition = match &result {
+        Ok(Delivery {
+            route,
+            outcome: ForwardOutcome::Forwarded(forwarded_id),
+            ..
+        }) => Disposition::forwarded(route, forwarded_id),
+        Ok(Delivery {
+            route,
+            outcome: ForwardOutcome::Suppressed(reason),
+            ..
+        }) => Disposition::skipped(route, reason),
+        Err(e) => {
+            record_failure(context, config, message_id, ses, DEFAULT_ROUTE, e).await;
+            return result;
+        }
+    };
+    record_outcome(context, config, message_id, ses, &disposition).await;
+
+    result
+}
+
+/// Record another failed attempt on the stored message and dead-letter it
+/// once it will never succeed
+async fn record_failure(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    ses: Option<&SesMessage>,
+    route: &str,
+    error: &AwsError,
+) {
+    let attempts = previous_failed_attempts(context.store.as_ref(), config, message_id).await + 1;
+    let disposition = Disposition::failed(route, attempts);
+    record_outcome(context, config, message_id, ses, &disposition).await;
+
+    if is_dead_letter(error, attempts, config) {
+        if let Err(dlq_error) =
+            capture_dead_letter(context, config, message_id, error, attempts).await
+        {
+            warn!("Failed to dead-letter {}: {}", message_id, dlq_error);
+        }
+    }
+}
+
+/// Load a stored message, apply the forwarding policy and send it on
+async fn deliver_stored_message(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    ses: Option<&SesMessage>,
+) -> Result<Delivery, AwsError> {
+    let key = incoming_key(&config.incoming_prefix, message_id)?;
+    info!(
+        "Retrieving email from storage: {}/{}",
+        config.email_bucket, key
+    );
+    let email_bytes = context.store.get_object(&config.email_bucket, &key).await?;
+
+    // Re-processed messages have no SES event; fall back to the stored recipients
+    let recipients = match ses {
+        Some(ses) => ses.mail.destination.clone(),
+        None => stored_recipients(&email_bytes),
+    };
+    let (mut route, subaddress) = config.routes.resolve_with_subaddress(&recipients);
+
+    if let Some(reason) = detect_loop(
+        &email_bytes,
+        &config.forwarder_email,
+        config.max_forward_hops,
+    )? {
+        warn!("Refusing to forward {}: {:?}", message_id, reason);
+        return Ok(Delivery {
+            route: route.name,
+            outcome: ForwardOutcome::Suppressed(reason.as_str().to_string()),
+            sender_rule: None,
+            auto_reply: None,
+        });
+    }
+
+    let envelope_source = ses.map(|ses| ses.mail.source.as_str());
+
+    let sender_rule = config
+        .sender_rules
+        .evaluate(envelope_source, &email_bytes)?;
+    let always_forward = match &sender_rule {
+        Some(rule) => {
+            info!(
+                "Sender rule for {}: {} ({} '{}' matched {})",
+                message_id,
+                rule.action.as_str(),
+                rule.field.as_str(),
+                rule.pattern,
+                rule.value
+            );
+            match rule.action {
+                SenderAction::AlwaysForward => true,
+                SenderAction::Drop | SenderAction::Quarantine => {
+                    if rule.action == SenderAction::Quarantine {
+                        quarantine_message(context, config, message_id).await?;
+                    }
+                    return Ok(Delivery {
+                        route: route.name,
+                        outcome: ForwardOutcome::Suppressed(rule.action.as_str().to_string()),
+                        sender_rule,
+                        auto_reply: None,
+                    });
+                }
+            }
+        }
+        None => false,
+    };
+
+    let unknown = unknown_recipients(config, &recipients);
+    if !unknown.is_empty() {
+        // Only live deliveries count towards the report
+        if ses.is_some() {
+            if let Err(e) = record_unknown_recipients(context, config, &unknown, Utc::now()).await {
+                warn!(
+                    "Failed to count unknown recipients of {}: {}",
+                    message_id, e
+                );
+            }
+        }
+
+        let reason = match config.unknown_recipient_policy {
+            _ if always_forward => None,
+            UnknownRecipientPolicy::CatchAll => None,
+            UnknownRecipientPolicy::StoreOnly => Some(UNKNOWN_RECIPIENT_REASON),
+            UnknownRecipientPolicy::Drop => Some(UNKNOWN_RECIPIENT_DROPPED_REASON),
+        };
+        if let Some(reason) = reason {
+            info!("Not forwarding {} to unknown {:?}", message_id, unknown);
+            return Ok(Delivery {
+                route: route.name,
+                outcome: ForwardOutcome::Suppressed(reason.to_string()),
+                sender_rule,
+                auto_reply: None,
+            });
+        }
+        route = config.routes.for_category(CATCH_ALL_ROUTE, None);
+    }
+
+    // Only live deliveries count; re-processed and released mail is not limited
+    if let (Some(limit), Some(ses), false) = (&config.rate_limit, ses, always_forward) {
+        if let Some(sender) = rate_limit_sender(&email_bytes, Some(ses.mail.source.as_str())) {
+            // A limiter that cannot be read or written never holds back mail
+            let held_by =
+                match record_arrival(context, config, limit, message_id, &sender, Utc::now()).await
+                {
+                    Ok(held_by) => held_by,
+                    Err(e) => {
+                        warn!("Failed to record arrival from {}: {}", sender, e);
+                        None
+                    }
+                };
+            if held_by.is_some() {
+                let destination = incoming_key(&config.held_prefix, message_id)?;
+                context
+                    .store
+                    .copy_object(&config.email_bucket, &key, &destination)
+                    .await?;
+                return Ok(Delivery {
+                    route: route.name,
+                    outcome: ForwardOutcome::Suppressed(RATE_LIMITED_REASON.to_string()),
+                    sender_rule,
+                    auto_reply: None,
+                });
+            }
+        }
+    }
 
-    let request = ForwardEmailRequest {
-        bucket: config.email_bucket.clone(),
-        incoming_path: config.incoming_prefix.clone(),
+    let mut stripped = Vec::new();
+    let email_bytes = match screen_attachments(config.attachment_policy, &email_bytes)? {
+        AttachmentScreening::Clean => email_bytes,
+        AttachmentScreening::Stripped { message, removed } => {
+            for found in &removed {
+                info!(
+                    "Stripping attachment {} from {}: {}",
+                    found.attachment.index, message_id, found.danger
+                );
+            }
+            stripped = removed;
+            message
+        }
+        AttachmentScreening::Quarantine(found) => {
+            warn!(
+                "Quarantining {}: {}",
+                message_id,
+                found
+                    .iter()
+                    .map(|found| found.danger.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            quarantine_message(context, config, message_id).await?;
+            return Ok(Delivery {
+                route: route.name,
+                outcome: ForwardOutcome::Suppressed(DANGEROUS_ATTACHMENT_REASON.to_string()),
+                sender_rule,
+                auto_reply: None,
+            });
+        }
+    };
+
+    if let Some(scanner) = &config.scanner {
+        match scanner.scan_message(&email_bytes).await {
+            Ok(Some(finding)) => {
+                warn!(
+                    "Quarantining {}: {} found{}",
+                    message_id,
+                    finding.signature,
+                    finding
+                        .attachment
+                        .map(|name| format!(" in {}", name))
+                        .unwrap_or_default()
+                );
+                quarantine_message(context, config, message_id).await?;
+                return Ok(Delivery {
+                    route: route.name,
+                    outcome: ForwardOutcome::Suppressed(INFECTED_REASON.to_string()),
+                    sender_rule,
+                    auto_reply: None,
+                });
+            }
+            Ok(None) => {}
+            // The scan adds to SES's own virus check; a scanner outage does
+            // not hold mail back
+            Err(e) => warn!("Could not scan {}: {}", message_id, e),
+        }
+    }
+
+    // The archive is a convenience; a failure never holds up forwarding
+    if let Some(prefix) = &config.attachment_prefix {
+        if let Err(e) = archive_attachments(
+            context,
+            config,
+            prefix,
+            message_id,
+            &email_bytes,
+            Utc::now(),
+        )
+        .await
+        {
+            warn!("Failed to archive attachments of {}: {}", message_id, e);
+        }
+    }
+
+    let mut options = ForwardOptions::default();
+    let extra_headers = &mut options.extra_headers;
+
+    if !stripped.is_empty() {
+        extra_headers.push((
+            STRIPPED_ATTACHMENTS_HEADER.to_string(),
+            stripped_header_value(&stripped),
+        ));
+    }
+
+    if let Some(detail) = &subaddress {
+        extra_headers.push((SUBADDRESS_HEADER.to_string(), detail.clone()));
+    }
+
+    if let Some(receipt) = ses.and_then(|ses| ses.receipt.as_ref()) {
+        if let Some(verdicts) = verdicts_header_value(receipt) {
+            extra_headers.push((VERDICTS_HEADER.to_string(), verdicts));
+        }
+        if let Some(results) = authentication_results(
+            config.forwarder_domain(),
+            receipt,
+            envelope_source,
+            &email_bytes,
+        ) {
+            extra_headers.push((AUTHENTICATION_RESULTS_HEADER.to_string(), results));
+        }
+    }
+
+    if let Some(kind) = detect_automated(&email_bytes, envelope_source)? {
+        match config.automated_policy(kind) {
+            AutomatedMailPolicy::Forward => {}
+            AutomatedMailPolicy::StoreOnly if !always_forward => {
+                info!(
+                    "Storing {} without forwarding: {}",
+                    message_id,
+                    kind.as_str()
+                );
+                return Ok(Delivery {
+                    route: route.name,
+                    outcome: ForwardOutcome::Suppressed(kind.as_str().to_string()),
+                    sender_rule,
+                    auto_reply: None,
+                });
+            }
+            // Allowed senders are forwarded, but still tagged as automated
+            AutomatedMailPolicy::Tag | AutomatedMailPolicy::StoreOnly => {
+                extra_headers.push((AUTOMATED_HEADER.to_string(), kind.as_str().to_string()));
+            }
+        }
+    }
+
+    if let Some(classifier) = &config.classifier {
+        match classifier.classify_email(&email_bytes) {
+            Ok(Some(classification)) => {
+                info!(
+                    "Classified {} as {}",
+                    message_id,
+                    classification.header_value()
+                );
+                extra_headers.push((
+                    CLASSIFICATION_HEADER.to_string(),
+                    classification.header_value(),
+                ));
+                // Mail to a specific address keeps that address's route
+                if route.name == DEFAULT_ROUTE {
+                    route = config.routes.for_category(
+                        &classification.category,
+                        classification.subject_tag.as_deref(),
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not classify {}: {}", message_id, e),
+        }
+    }
+    options.subject_tag = route.subject_tag.clone();
+
+    if let Some(secret) = &config.relay_secret {
+        let our_address = recipients
+            .iter()
+            .find(|recipient| {
+                recipient.rsplit_once('@').is_some_and(|(_, domain)| {
+                    domain.eq_ignore_ascii_case(config.forwarder_domain())
+                })
+            })
+            .map(String::as_str)
+            .unwrap_or(config.relay_from());
+        // Without a stored alias, a signed reply address still keeps the
+        // forwarding mailbox out of replies
+        match conversation_alias(
+            context,
+            config,
+            secret,
+            &email_bytes,
+            our_address,
+            Utc::now(),
+        )
+        .await
+        {
+            Ok(alias) => options.reply_alias = alias.map(|alias| alias.alias),
+            Err(e) => {
+                warn!(
+                    "No conversation alias for {}, using a signed reply address: {}",
+                    message_id, e
+                );
+                options.reply_alias = signed_reply_address(config, secret, &email_bytes);
+            }
+        }
+    }
+
+    let forward_to = EmailAddress::try_from(config.forward_to_email.clone())?;
+    let forwarded_id = forward_raw_email(
+        context,
         message_id,
-        forward_to,
+        &email_bytes,
+        &forward_to,
+        config,
+        &options,
+    )
+    .await?;
+
+    let auto_reply = match (&config.auto_responder, ses) {
+        (Some(responder), Some(ses)) if responder.is_active(&route.name, Utc::now()) => {
+            match send_auto_reply(
+                context,
+                config,
+                responder,
+                message_id,
+                &ses.mail,
+                &email_bytes,
+                Utc::now(),
+            )
+            .await
+            {
+                Ok(outcome) => Some(outcome),
+                Err(e) => {
+                    warn!("Failed to auto-reply to {}: {}", message_id, e);
+                    None
+                }
+            }
+        }
+        _ => None,
     };
 
-    match forward_email(context, request, config).await {
-        Ok(forwarded_message_id) => {
-            info!("Email forwarded successfully: {}", forwarded_message_id);
-            Ok(json!({
-                "statusCode": 200,
-                "body": json!({
-                    "message": "Email forwarded successfully",
-                    "forwardedMessageId": forwarded_message_id
-                }).to_string()
-            }))
+    Ok(Delivery {
+        route: route.name,
+        outcome: ForwardOutcome::Forwarded(forwarded_id),
+        sender_rule,
+        auto_reply,
+    })
+}
+
+/// Copy the stored message to the quarantine prefix for review
+async fn quarantine_message(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+) -> Result<(), AwsError> {
+    let source = incoming_key(&config.incoming_prefix, message_id)?;
+    let destination = incoming_key(&config.quarantine_prefix, message_id)?;
+    Ok(context
+        .store
+        .copy_object(&config.email_bucket, &source, &destination)
+        .await?)
+}
+
+/// Relay a stored reply to a `reply+` address, record what happened and
+/// dead-letter it once it will never succeed
+async fn relay_stored_reply(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    ses: &SesMessage,
+    relay_address: &str,
+) -> Result<RelayOutcome, AwsError> {
+    let result = load_and_relay(context, config, message_id, ses, relay_address).await;
+    let disposition = match &result {
+        Ok(RelayOutcome::Relayed { message_id, .. }) => {
+            Disposition::forwarded(RELAY_ROUTE, message_id)
         }
+        Ok(RelayOutcome::Refused(refusal)) => Disposition::skipped(RELAY_ROUTE, refusal.as_str()),
         Err(e) => {
-            error!("Error forwarding email: {}", e);
-            Err(lambda_runtime::Error::from(e.to_string()))
+            record_failure(context, config, message_id, Some(ses), RELAY_ROUTE, e).await;
+            return result;
+        }
+    };
+    record_outcome(context, config, message_id, Some(ses), &disposition).await;
+
+    result
+}
+
+/// Load a stored reply and relay it to the correspondent
+async fn load_and_relay(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    ses: &SesMessage,
+    relay_address: &str,
+) -> Result<RelayOutcome, AwsError> {
+    let key = incoming_key(&config.incoming_prefix, message_id)?;
+    let email_bytes = context.store.get_object(&config.email_bucket, &key).await?;
+
+    relay_reply(
+        context,
+        config,
+        message_id,
+        ses,
+        relay_address,
+        &email_bytes,
+    )
+    .await
+}
+
+/// Record the outcome on the stored message, in the message index and in its
+/// conversation thread. Failures are logged rather than returned so a tagging
+/// problem never turns a delivered email into an error.
+async fn record_outcome(
+    context: &AppContext,
+    config: &config::Config,
+    message_id: &MessageId,
+    ses: Option<&SesMessage>,
+    disposition: &Disposition,
+) {
+    if let Err(e) =
+        record_disposition(context.store.as_ref(), config, message_id, disposition).await
+    {
+        warn!("Failed to record outcome for {}: {}", message_id, e);
+    }
+
+    if config.index_prefix.is_none() && config.thread_prefix.is_none() {
+        return;
+    }
+    let email_bytes = match incoming_key(&config.incoming_prefix, message_id) {
+        Ok(key) => context.store.get_object(&config.email_bucket, &key).await,
+        Err(e) => Err(e),
+    };
+    let email_bytes = match email_bytes {
+        Ok(email_bytes) => email_bytes,
+        Err(e) => {
+            warn!("Failed to load {} for indexing: {}", message_id, e);
+            return;
+        }
+    };
+
+    if let Some(prefix) = &config.index_prefix {
+        let record = IndexRecord::new(message_id, &email_bytes, ses, disposition, Utc::now());
+        if let Err(e) = write_index_record(context, config, prefix, &record).await {
+            warn!("Failed to index {}: {}", message_id, e);
+        }
+    }
+    if let Some(prefix) = &config.thread_prefix {
+        if let Err(e) = record_thread(
+            context,
+            config,
+            prefix,
+            message_id,
+            &email_bytes,
+            Utc::now(),
+        )
+        .await
+        {
+            warn!("Failed to thread {}: {}", message_id, e);
         }
     }
 }
@@ -83,6 +795,17 @@ fn is_report_email(destination: &str) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_stored_destination() {
+        let raw = b"To: Jim <booking@jimmillerdrums.com>\r\nCc: dmarc@jimmillerdrums.com\r\n\r\n";
+        assert_eq!(stored_destination(raw), "dmarc@jimmillerdrums.com");
+        assert_eq!(
+            stored_destination(b"To: booking@jimmillerdrums.com\r\n\r\n"),
+            "booking@jimmillerdrums.com"
+        );
+        assert_eq!(stored_destination(b"Subject: None\r\n\r\n"), "");
+    }
+
     #[test]
     fn test_is_report_email_dmarc() {
         assert!(is_report_email("dmarc@jimmillerdrums.com"));
@@ -0,0 +1,130 @@
+use crate::email::{extract_email_address, EmailError};
+use mailparse::{parse_headers, MailHeaderMap};
+
+/// Trace header added to every forwarded message, e.g.
+/// `X-Forwarded-By-Processor: forwarder@jimmillerdrums.com; hops=1`
+pub const TRACE_HEADER: &str = "X-Forwarded-By-Processor";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopReason {
+    /// The message has already passed through the processor this many times
+    HopLimit(u32),
+    /// The message was sent by the forwarder address itself
+    FromForwarder,
+}
+
+impl LoopReason {
+    /// Short slug used in logs, tags and the handler response
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoopReason::HopLimit(_) => "mail-loop-hop-limit",
+            LoopReason::FromForwarder => "mail-loop-from-forwarder",
+        }
+    }
+}
+
+/// Number of times the processor has already forwarded this message, taken from
+/// our trace header or, when a hop stripped it, from `Received` headers naming
+/// the forwarder address
+pub fn forward_hops(raw_email: &[u8], forwarder: &str) -> Result<u32, EmailError> {
+    let (headers, _) = parse_headers(raw_email)?;
+
+    let traced = headers
+        .get_all_values(TRACE_HEADER)
+        .iter()
+        .filter_map(|value| parse_hops(value))
+        .max()
+        .unwrap_or(0);
+
+    let forwarder = forwarder.to_lowercase();
+    let received = headers
+        .get_all_values("Received")
+        .iter()
+        .filter(|value| value.to_lowercase().contains(&forwarder))
+        .count() as u32;
+
+    Ok(traced.max(received))
+}
+
+/// Decide whether forwarding `raw_email` again would create a mail loop
+pub fn detect_loop(
+    raw_email: &[u8],
+    forwarder: &str,
+    max_hops: u32,
+) -> Result<Option<LoopReason>, EmailError> {
+    let (headers, _) = parse_headers(raw_email)?;
+
+    for name in ["From", "Sender", "Return-Path"] {
+        if let Some(value) = headers.get_first_value(name) {
+            if extract_email_address(&value)?.eq_ignore_ascii_case(forwarder) {
+                return Ok(Some(LoopReason::FromForwarder));
+            }
+        }
+    }
+
+    let hops = forward_hops(raw_email, forwarder)?;
+    if hops >= max_hops {
+        return Ok(Some(LoopReason::HopLimit(hops)));
+    }
+
+    Ok(None)
+}
+
+/// Value of the trace header for a message that has now been forwarded `hops` times
+pub fn trace_header_value(forwarder: &str, hops: u32) -> String {
+    format!("{}; hops={}", forwarder, hops)
+}
+
+fn parse_hops(value: &str) -> Option<u32> {
+    value
+        .split(';')
+        .filter_map(|part| part.trim().strip_prefix("hops="))
+        .find_map(|hops| hops.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORWARDER: &str = "forwarder@jimmillerdrums.com";
+
+    #[test]
+    fn test_fresh_message_has_no_hops() {
+        let email = b"From: fan@example.com\r\nSubject: Hi\r\n\r\nBody";
+        assert_eq!(forward_hops(email, FORWARDER).unwrap(), 0);
+        assert_eq!(detect_loop(email, FORWARDER, 3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_hops_from_trace_header() {
+        let email = b"X-Forwarded-By-Processor: forwarder@jimmillerdrums.com; hops=3\r\nFrom: me@gmail.com\r\n\r\nBody";
+        assert_eq!(forward_hops(email, FORWARDER).unwrap(), 3);
+        assert_eq!(
+            detect_loop(email, FORWARDER, 3).unwrap(),
+            Some(LoopReason::HopLimit(3))
+        );
+    }
+
+    #[test]
+    fn test_hops_from_received_headers() {
+        let email = b"Received: from a.amazonses.com by mx.google.com for <me@gmail.com>; envelope-from <forwarder@jimmillerdrums.com>\r\nReceived: from b.amazonses.com; envelope-from <Forwarder@JimMillerDrums.com>\r\nFrom: me@gmail.com\r\n\r\nBody";
+        assert_eq!(forward_hops(email, FORWARDER).unwrap(), 2);
+        assert_eq!(detect_loop(email, FORWARDER, 3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_message_from_forwarder_is_a_loop() {
+        let email =
+            b"From: \"Fan\" (via jimmillerdrums.com) <forwarder@jimmillerdrums.com>\r\n\r\nBody";
+        assert_eq!(
+            detect_loop(email, FORWARDER, 3).unwrap(),
+            Some(LoopReason::FromForwarder)
+        );
+    }
+
+    #[test]
+    fn test_trace_header_round_trip() {
+        let value = trace_header_value(FORWARDER, 2);
+        assert_eq!(parse_hops(&value), Some(2));
+    }
+}
@@ -1,5 +1,5 @@
 use email_processor::config::Config;
-use email_processor::{process_ses_event, AppContext, SesEvent};
+use email_processor::{process_invocation, AppContext, Invocation};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 
 #[tokio::main]
@@ -17,8 +17,8 @@ async fn main() -> Result<(), Error> {
     let lambda_config =
         Config::from_env().map_err(|e| Error::from(format!("Configuration error: {}", e)))?;
 
-    run(service_fn(|event: LambdaEvent<SesEvent>| async {
-        process_ses_event(event.payload, &context, &lambda_config).await
+    run(service_fn(|event: LambdaEvent<Invocation>| async {
+        process_invocation(event.payload, &context, &lambda_config).await
     }))
     .await
 }
@@ -0,0 +1,105 @@
+use crate::email::extract_email_address;
+use chrono::{DateTime, Utc};
+use mailparse::{dateparse, parse_headers, MailHeaderMap};
+
+/// Envelope sender on the separator line when the message names none
+const UNKNOWN_SENDER: &str = "MAILER-DAEMON";
+
+/// The time a message says it was sent, from its `Date` header
+pub fn message_date(raw_email: &[u8]) -> Option<DateTime<Utc>> {
+    let (headers, _) = parse_headers(raw_email).ok()?;
+    let timestamp = dateparse(&headers.get_first_value("Date")?).ok()?;
+    DateTime::from_timestamp(timestamp, 0)
+}
+
+/// Sender for the `From ` separator line: `Return-Path`, else the `From`
+/// address. Whitespace would end the field, so it is never kept.
+fn envelope_sender(raw_email: &[u8]) -> String {
+    let headers = parse_headers(raw_email).ok().map(|(headers, _)| headers);
+    let header = |name: &str| {
+        headers
+            .as_ref()
+            .and_then(|headers| headers.get_first_value(name))
+    };
+    header("Return-Path")
+        .or_else(|| header("From"))
+        .and_then(|value| extract_email_address(&value).ok())
+        .map(|address| {
+            address
+                .trim_matches(['<', '>', ' '])
+                .replace(char::is_whitespace, "")
+        })
+        .filter(|address| !address.is_empty())
+        .unwrap_or_else(|| UNKNOWN_SENDER.to_string())
+}
+
+/// One message in mboxrd form: a `From sender date` separator, the message
+/// with LF line endings and every line matching `>*From ` quoted with one
+/// more `>`, then a blank line. Readers strip exactly one `>` again, so no
+/// line of the message is changed for good. `received` dates the separator
+/// when the message has no usable `Date` header.
+pub fn mboxrd_entry(raw_email: &[u8], received: DateTime<Utc>) -> Vec<u8> {
+    let date = message_date(raw_email).unwrap_or(received);
+    let mut entry = format!(
+        "From {} {}\n",
+        envelope_sender(raw_email),
+        date.format("%a %b %e %H:%M:%S %Y")
+    )
+    .into_bytes();
+    entry.reserve(raw_email.len() + 2);
+
+    let body = raw_email.strip_suffix(b"\n").unwrap_or(raw_email);
+    for line in body.split(|&byte| byte == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let quotes = line.iter().take_while(|&&byte| byte == b'>').count();
+        if line[quotes..].starts_with(b"From ") {
+            entry.push(b'>');
+        }
+        entry.extend_from_slice(line);
+        entry.push(b'\n');
+    }
+    entry.push(b'\n');
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_mboxrd_entry() {
+        let raw = b"Return-Path: <pat@venue.example>\r\n\
+From: Pat <pat@venue.example>\r\n\
+Date: Fri, 05 Jun 2026 18:30:00 +0000\r\n\
+Subject: Load-in\r\n\
+\r\n\
+From the loading dock, turn left.\r\n\
+>From the stage door too.\r\n\
+\x20From here it is indented.\r\n";
+        let received = Utc.with_ymd_and_hms(2026, 6, 6, 0, 0, 0).unwrap();
+
+        assert_eq!(
+            String::from_utf8(mboxrd_entry(raw, received)).unwrap(),
+            "From pat@venue.example Fri Jun  5 18:30:00 2026\n\
+Return-Path: <pat@venue.example>\n\
+From: Pat <pat@venue.example>\n\
+Date: Fri, 05 Jun 2026 18:30:00 +0000\n\
+Subject: Load-in\n\
+\n\
+>From the loading dock, turn left.\n\
+>>From the stage door too.\n\
+\x20From here it is indented.\n\
+\n"
+        );
+    }
+
+    #[test]
+    fn test_mboxrd_entry_without_headers() {
+        let received = Utc.with_ymd_and_hms(2026, 6, 6, 9, 5, 0).unwrap();
+        assert_eq!(
+            String::from_utf8(mboxrd_entry(b"Subject: Hi\n\nNo newline at end", received)).unwrap(),
+            "From MAILER-DAEMON Sat Jun  6 09:05:00 2026\nSubject: Hi\n\nNo newline at end\n\n"
+        );
+    }
+}
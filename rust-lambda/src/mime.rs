@@ -1,4 +1,7 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use mailparse::parse_mail;
+use std::str::FromStr;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,6 +12,85 @@ pub enum MimeError {
     InvalidStructure(String),
 }
 
+/// Original header kept as `X-Original-*` when it is replaced or stripped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginalHeader {
+    From,
+    To,
+    MessageId,
+    ReturnPath,
+}
+
+impl OriginalHeader {
+    pub const ALL: [OriginalHeader; 4] = [
+        OriginalHeader::From,
+        OriginalHeader::To,
+        OriginalHeader::MessageId,
+        OriginalHeader::ReturnPath,
+    ];
+
+    /// Header of the incoming message
+    pub fn source_name(&self) -> &'static str {
+        match self {
+            OriginalHeader::From => "From",
+            OriginalHeader::To => "To",
+            OriginalHeader::MessageId => "Message-ID",
+            OriginalHeader::ReturnPath => "Return-Path",
+        }
+    }
+
+    /// Header it is preserved under on the forwarded message
+    pub fn preserved_name(&self) -> &'static str {
+        match self {
+            OriginalHeader::From => "X-Original-From",
+            OriginalHeader::To => "X-Original-To",
+            OriginalHeader::MessageId => "X-Original-Message-ID",
+            OriginalHeader::ReturnPath => "X-Original-Return-Path",
+        }
+    }
+}
+
+impl FromStr for OriginalHeader {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        OriginalHeader::ALL
+            .into_iter()
+            .find(|header| {
+                header.source_name().eq_ignore_ascii_case(value)
+                    || header.preserved_name().eq_ignore_ascii_case(value)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "expected from, to, message-id or return-path, got '{}'",
+                    value
+                )
+            })
+    }
+}
+
+/// Header changes applied to a message before it is forwarded
+#[derive(Debug, Clone, Default)]
+pub struct HeaderRewrite {
+    pub from: String,
+    pub to: String,
+    pub reply_to: String,
+    /// Additional headers appended after the replaced address headers
+    pub extra_headers: Vec<(String, String)>,
+    /// Original headers to keep as `X-Original-*` before they are dropped
+    pub preserve: Vec<OriginalHeader>,
+    /// Trace headers written above all others, e.g. our `Received` stamp
+    pub prepend_headers: Vec<(String, String)>,
+    /// Incoming `Authentication-Results` claiming this authserv-id are forged
+    /// (RFC 8601 section 5) and dropped
+    pub authserv_id: Option<String>,
+    /// Prefix for the Subject, e.g. `[booking]`, added unless already present
+    pub subject_tag: Option<String>,
+    /// Further headers removed outright, e.g. traces on a relayed reply
+    pub drop_headers: Vec<String>,
+}
+
 /// Modify email headers while preserving the entire MIME body
 /// Replaces From, To, and Reply-To headers with new values
 /// Strips DKIM-Signature and internal SES headers to prevent duplication errors
@@ -17,6 +99,22 @@ pub fn modify_email_headers(
     new_from: &str,
     new_to: &str,
     new_reply_to: &str,
+) -> Result<Vec<u8>, MimeError> {
+    rewrite_email_headers(
+        raw_email,
+        &HeaderRewrite {
+            from: new_from.to_string(),
+            to: new_to.to_string(),
+            reply_to: new_reply_to.to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Apply a [`HeaderRewrite`] while preserving the entire MIME body
+pub fn rewrite_email_headers(
+    raw_email: &[u8],
+    rewrite: &HeaderRewrite,
 ) -> Result<Vec<u8>, MimeError> {
     let parsed = parse_mail(raw_email)?;
 
@@ -25,24 +123,91 @@ pub fn modify_email_headers(
         MimeError::InvalidStructure("Could not find header/body boundary".to_string())
     })?;
 
-    // Build new headers
-    let mut new_headers = Vec::new();
+    // Build new headers, trace headers first
+    let mut new_headers: Vec<String> = rewrite
+        .prepend_headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    let mut originals = Vec::new();
+    let mut subject_seen = false;
 
     // Copy headers EXCEPT those we are replacing or those that cause conflicts
     for header in &parsed.headers {
         let key = header.get_key();
 
+        // Incoming X-Original-* headers would be indistinguishable from ours
+        if rewrite
+            .preserve
+            .iter()
+            .any(|original| original.preserved_name().eq_ignore_ascii_case(&key))
+        {
+            continue;
+        }
+
+        if let Some(id) = &rewrite.authserv_id {
+            if key.eq_ignore_ascii_case(crate::auth::AUTHENTICATION_RESULTS_HEADER)
+                && crate::auth::authserv_id(&header.get_value()).eq_ignore_ascii_case(id)
+            {
+                continue;
+            }
+        }
+
+        if rewrite
+            .drop_headers
+            .iter()
+            .any(|dropped| dropped.eq_ignore_ascii_case(&key))
+        {
+            continue;
+        }
+
         if is_forbidden_header(&key) {
+            if let Some(original) = rewrite
+                .preserve
+                .iter()
+                .find(|original| original.source_name().eq_ignore_ascii_case(&key))
+            {
+                if !originals.iter().any(|(kept, _)| kept == original) {
+                    originals.push((*original, raw_header_value(header.get_value_raw())));
+                }
+            }
             continue;
         }
 
-        new_headers.push(format!("{}: {}\r\n", key, header.get_value()));
+        // Raw values keep RFC 2047 encoded words intact
+        let value = String::from_utf8_lossy(header.get_value_raw());
+
+        if key.eq_ignore_ascii_case("subject") && !subject_seen {
+            subject_seen = true;
+            if let Some(tag) = &rewrite.subject_tag {
+                new_headers.push(format!(
+                    "{}: {}\r\n",
+                    key,
+                    tag_subject(&header.get_value(), &value, tag)
+                ));
+                continue;
+            }
+        }
+
+        new_headers.push(format!("{}: {}\r\n", key, value));
+    }
+
+    if let (Some(tag), false) = (&rewrite.subject_tag, subject_seen) {
+        new_headers.push(format!("Subject: {}\r\n", encode_header_value(tag)));
     }
 
     // Add new headers
-    new_headers.push(format!("From: {}\r\n", new_from));
-    new_headers.push(format!("To: {}\r\n", new_to));
-    new_headers.push(format!("Reply-To: {}\r\n", new_reply_to));
+    new_headers.push(format!("From: {}\r\n", rewrite.from));
+    new_headers.push(format!("To: {}\r\n", rewrite.to));
+    new_headers.push(format!("Reply-To: {}\r\n", rewrite.reply_to));
+    for original in &rewrite.preserve {
+        if let Some((_, value)) = originals.iter().find(|(kept, _)| kept == original) {
+            new_headers.push(format!("{}: {}\r\n", original.preserved_name(), value));
+        }
+    }
+    for (name, value) in &rewrite.extra_headers {
+        new_headers.push(format!("{}: {}\r\n", name, value));
+    }
 
     // Combine new headers with original body
     let mut result = Vec::new();
@@ -56,6 +221,56 @@ pub fn modify_email_headers(
     Ok(result)
 }
 
+/// Prefix a raw Subject value with `tag`. `decoded` is the same value after
+/// RFC 2047 decoding; when it already contains the tag (e.g. a reply to a
+/// tagged message) the subject is left alone.
+pub fn tag_subject(decoded: &str, raw: &str, tag: &str) -> String {
+    if decoded.to_lowercase().contains(&tag.to_lowercase()) {
+        return raw.to_string();
+    }
+
+    let raw = raw.trim_start();
+    if raw.is_empty() {
+        encode_header_value(tag)
+    } else {
+        format!("{} {}", encode_header_value(tag), raw)
+    }
+}
+
+/// Header value as it appeared on the wire, unfolded. Encoded words are kept
+/// as-is; raw 8-bit values (SMTPUTF8) are re-encoded so the result is ASCII.
+fn raw_header_value(raw: &[u8]) -> String {
+    let value = String::from_utf8_lossy(raw);
+    let unfolded: String = value.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+    encode_header_value(unfolded.trim())
+}
+
+/// Encode an unstructured header value as RFC 2047 encoded words when it is not
+/// plain ASCII. Words are kept under 76 characters and folded onto
+/// continuation lines.
+pub fn encode_header_value(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+
+    // 45 bytes of UTF-8 become 60 base64 characters, 72 with the delimiters
+    const MAX_CHUNK: usize = 45;
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > MAX_CHUNK {
+            words.push(format!("=?UTF-8?B?{}?=", BASE64.encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", BASE64.encode(&chunk)));
+    }
+
+    words.join("\r\n ")
+}
+
 /// Helper to identify headers that must be removed to avoid SES conflicts
 fn is_forbidden_header(key: &str) -> bool {
     let k = key.to_lowercase();
@@ -70,6 +285,12 @@ fn is_forbidden_header(key: &str) -> bool {
             | "message-id"
             | "x-ses-message-id"
             | "x-ses-outgoing"
+            | "x-forwarded-by-processor"
+            | "x-processor-verdicts"
+            | "x-processor-classification"
+            | "x-subaddress"
+            | "x-processor-stripped-attachments"
+            | "x-processor-automated"
     )
 }
 
@@ -90,6 +311,7 @@ fn find_header_body_boundary(email: &[u8]) -> Option<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mailparse::MailHeaderMap;
 
     #[test]
     fn test_modify_simple_email() {
@@ -153,6 +375,207 @@ mod tests {
         assert!(modified_str.contains("Plain text"));
     }
 
+    #[test]
+    fn test_rewrite_replaces_trace_header() {
+        let email = b"X-Forwarded-By-Processor: forwarder@example.com; hops=1\r\nFrom: old@example.com\r\nSubject: Test\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            from: "new@example.com".to_string(),
+            to: "newrecipient@example.com".to_string(),
+            reply_to: "reply@example.com".to_string(),
+            extra_headers: vec![(
+                "X-Forwarded-By-Processor".to_string(),
+                "forwarder@example.com; hops=2".to_string(),
+            )],
+            ..Default::default()
+        };
+
+        let modified = rewrite_email_headers(email, &rewrite).unwrap();
+        let modified_str = String::from_utf8_lossy(&modified);
+
+        assert!(!modified_str.contains("hops=1"));
+        assert!(
+            modified_str.contains("X-Forwarded-By-Processor: forwarder@example.com; hops=2\r\n")
+        );
+        assert!(modified_str.ends_with("\r\n\r\nBody"));
+    }
+
+    #[test]
+    fn test_rewrite_preserves_original_headers() {
+        let email = b"Return-Path: <bounce@mailer.example.com>\r\nFrom: =?UTF-8?Q?Jos=C3=A9?= <jose@example.com>\r\nTo: info@jimmillerdrums.com\r\nMessage-ID: <abc@example.com>\r\nX-Original-From: spoofed@example.com\r\nSubject: Test\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            from: "new@example.com".to_string(),
+            to: "newrecipient@example.com".to_string(),
+            reply_to: "reply@example.com".to_string(),
+            preserve: vec![
+                OriginalHeader::From,
+                OriginalHeader::MessageId,
+                OriginalHeader::ReturnPath,
+            ],
+            ..Default::default()
+        };
+
+        let modified = rewrite_email_headers(email, &rewrite).unwrap();
+        let modified_str = String::from_utf8_lossy(&modified);
+
+        assert!(
+            modified_str.contains("X-Original-From: =?UTF-8?Q?Jos=C3=A9?= <jose@example.com>\r\n")
+        );
+        assert!(modified_str.contains("X-Original-Message-ID: <abc@example.com>\r\n"));
+        assert!(modified_str.contains("X-Original-Return-Path: <bounce@mailer.example.com>\r\n"));
+        assert!(!modified_str.contains("X-Original-To"));
+        assert!(!modified_str.contains("spoofed"));
+    }
+
+    #[test]
+    fn test_raw_utf8_original_is_encoded() {
+        let email = "From: José <jose@example.com>\r\nSubject: Test\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            preserve: vec![OriginalHeader::From],
+            ..Default::default()
+        };
+
+        let modified = rewrite_email_headers(email.as_bytes(), &rewrite).unwrap();
+        let modified_str = String::from_utf8(modified).unwrap();
+
+        let expected = format!(
+            "X-Original-From: =?UTF-8?B?{}?=\r\n",
+            BASE64.encode("José <jose@example.com>")
+        );
+        assert!(modified_str.contains(&expected));
+    }
+
+    #[test]
+    fn test_encode_header_value() {
+        assert_eq!(encode_header_value("Plain subject"), "Plain subject");
+
+        let long = "é".repeat(40);
+        let encoded = encode_header_value(&long);
+        assert!(encoded.is_ascii());
+        assert!(encoded.split("\r\n ").all(|word| word.len() <= 75));
+        let decoded = mailparse::parse_header(format!("Subject: {}", encoded).as_bytes())
+            .unwrap()
+            .0
+            .get_value();
+        assert_eq!(decoded, long);
+    }
+
+    #[test]
+    fn test_original_header_from_str() {
+        assert_eq!("message-id".parse(), Ok(OriginalHeader::MessageId));
+        assert_eq!(
+            "X-Original-Return-Path".parse(),
+            Ok(OriginalHeader::ReturnPath)
+        );
+        assert!("subject".parse::<OriginalHeader>().is_err());
+    }
+
+    #[test]
+    fn test_rewrite_prepends_trace_headers() {
+        let email = b"Received: from mx.example.com\r\nX-Processor-Verdicts: spam=pass\r\nFrom: old@example.com\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            prepend_headers: vec![("Received".to_string(), "from amazonses.com".to_string())],
+            ..Default::default()
+        };
+
+        let modified = rewrite_email_headers(email, &rewrite).unwrap();
+        let modified_str = String::from_utf8_lossy(&modified);
+
+        assert!(modified_str
+            .starts_with("Received: from amazonses.com\r\nReceived: from mx.example.com\r\n"));
+        assert!(!modified_str.contains("X-Processor-Verdicts"));
+    }
+
+    #[test]
+    fn test_rewrite_drops_forged_automated_header() {
+        let email = b"X-Processor-Automated: bulk\r\nFrom: old@example.com\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            extra_headers: vec![(
+                "X-Processor-Automated".to_string(),
+                "auto-reply".to_string(),
+            )],
+            ..Default::default()
+        };
+
+        let modified = rewrite_email_headers(email, &rewrite).unwrap();
+        let modified_str = String::from_utf8_lossy(&modified);
+
+        assert_eq!(modified_str.matches("X-Processor-Automated").count(), 1);
+        assert!(modified_str.contains("X-Processor-Automated: auto-reply\r\n"));
+    }
+
+    #[test]
+    fn test_rewrite_drops_forged_authentication_results() {
+        let email = b"Authentication-Results: jimmillerdrums.com; spf=pass\r\nAuthentication-Results: amazonses.com; spf=fail\r\nFrom: old@example.com\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            authserv_id: Some("jimmillerdrums.com".to_string()),
+            ..Default::default()
+        };
+
+        let modified = rewrite_email_headers(email, &rewrite).unwrap();
+        let modified_str = String::from_utf8_lossy(&modified);
+
+        assert!(!modified_str.contains("jimmillerdrums.com; spf=pass"));
+        assert!(modified_str.contains("Authentication-Results: amazonses.com; spf=fail\r\n"));
+    }
+
+    #[test]
+    fn test_subject_tag_is_added_once() {
+        let email = b"From: old@example.com\r\nSubject: Gig on Friday\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            subject_tag: Some("[booking]".to_string()),
+            ..Default::default()
+        };
+
+        let tagged = rewrite_email_headers(email, &rewrite).unwrap();
+        assert!(String::from_utf8_lossy(&tagged).contains("Subject: [booking] Gig on Friday\r\n"));
+
+        let reply = b"From: old@example.com\r\nSubject: Re: [Booking] Gig on Friday\r\n\r\nBody";
+        let retagged = rewrite_email_headers(reply, &rewrite).unwrap();
+        assert!(
+            String::from_utf8_lossy(&retagged).contains("Subject: Re: [Booking] Gig on Friday\r\n")
+        );
+    }
+
+    #[test]
+    fn test_subject_tag_keeps_encoded_words() {
+        let email =
+            b"From: old@example.com\r\nSubject: =?UTF-8?Q?Le=C3=A7ons_de_batterie?=\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            subject_tag: Some("[lessons]".to_string()),
+            ..Default::default()
+        };
+
+        let tagged = rewrite_email_headers(email, &rewrite).unwrap();
+        let (headers, _) = mailparse::parse_headers(&tagged).unwrap();
+        let subject = headers.get_first_header("Subject").unwrap();
+
+        assert_eq!(
+            subject.get_value_raw(),
+            b"[lessons] =?UTF-8?Q?Le=C3=A7ons_de_batterie?="
+        );
+        assert_eq!(subject.get_value(), "[lessons] Leçons de batterie");
+
+        // The decoded subject already carries the tag
+        let encoded_tag =
+            b"From: old@example.com\r\nSubject: =?UTF-8?Q?=5Blessons=5D_Le=C3=A7ons?=\r\n\r\nBody";
+        let retagged = rewrite_email_headers(encoded_tag, &rewrite).unwrap();
+        assert!(!String::from_utf8_lossy(&retagged).contains("Subject: [lessons]"));
+    }
+
+    #[test]
+    fn test_subject_tag_added_when_subject_missing() {
+        let email = b"From: old@example.com\r\n\r\nBody";
+        let rewrite = HeaderRewrite {
+            subject_tag: Some("[réservation]".to_string()),
+            ..Default::default()
+        };
+
+        let tagged = rewrite_email_headers(email, &rewrite).unwrap();
+        let (headers, _) = mailparse::parse_headers(&tagged).unwrap();
+        assert_eq!(headers.get_first_value("Subject").unwrap(), "[réservation]");
+        assert!(tagged.is_ascii());
+    }
+
     #[test]
     fn test_find_boundary() {
         let email = b"H: V\r\n\r\nBody";
@@ -0,0 +1,427 @@
+use crate::aws::{send_email_via_ses, AppContext, AwsError};
+use crate::backfill::ForwardFailure;
+use crate::config::Config;
+use crate::deadletter::ReplayReport;
+use crate::disposition::{incoming_key, ForwardOutcome};
+use crate::domain::{EmailAddress, MessageId, S3Key};
+use crate::email::extract_email_address;
+use crate::store::{MailStore, StoreError, StoredObject};
+use crate::template::{Template, TemplateKind, Variables};
+use chrono::{DateTime, Duration, Utc};
+use mailparse::{parse_headers, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+/// Skip reason recorded on messages held for exceeding the rate limit
+pub const RATE_LIMITED_REASON: &str = "rate-limited";
+
+/// How many messages one sender may have forwarded in a sliding window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Per sender address (unlimited when unset)
+    pub per_address: Option<u32>,
+    /// Per sender domain (unlimited when unset)
+    pub per_domain: Option<u32>,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    fn limit(&self, scope: SenderScope) -> Option<u32> {
+        match scope {
+            SenderScope::Address => self.per_address,
+            SenderScope::Domain => self.per_domain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderScope {
+    Address,
+    Domain,
+}
+
+impl SenderScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SenderScope::Address => "address",
+            SenderScope::Domain => "domain",
+        }
+    }
+}
+
+/// Arrival log for one sender address or domain, stored as JSON under
+/// `rate_limit_prefix` keyed by a hash of the sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderWindow {
+    pub scope: SenderScope,
+    pub sender: String,
+    /// Arrivals inside the window, oldest first
+    pub arrivals: Vec<DateTime<Utc>>,
+    /// Held messages not yet reported in a notice
+    #[serde(default)]
+    pub held: Vec<String>,
+    #[serde(default)]
+    pub last_held_at: Option<DateTime<Utc>>,
+}
+
+impl SenderWindow {
+    fn new(scope: SenderScope, sender: &str) -> Self {
+        Self {
+            scope,
+            sender: sender.to_string(),
+            arrivals: Vec::new(),
+            held: Vec::new(),
+            last_held_at: None,
+        }
+    }
+
+    /// A burst is over once a full window passes without holding anything
+    pub fn burst_ended(&self, window: Duration, now: DateTime<Utc>) -> bool {
+        !self.held.is_empty() && self.last_held_at.is_some_and(|at| now - at >= window)
+    }
+}
+
+/// The sender window that held a message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HeldBy {
+    pub scope: SenderScope,
+    pub sender: String,
+}
+
+/// A "N messages held from X" notice that was sent
+#[derive(Debug, Clone, Serialize)]
+pub struct HeldNotice {
+    pub sender: String,
+    pub held: usize,
+    pub notice_message_id: String,
+}
+
+/// Address rate limits apply to: the `From` address, or the envelope sender
+/// when there is none
+pub fn rate_limit_sender(raw_email: &[u8], envelope_source: Option<&str>) -> Option<String> {
+    let from = parse_headers(raw_email)
+        .ok()
+        .and_then(|(headers, _)| headers.get_first_value("From"));
+
+    from.as_deref()
+        .or(envelope_source)
+        .and_then(|value| extract_email_address(value).ok())
+        .map(|address| address.trim().to_lowercase())
+        .filter(|address| address.contains('@'))
+}
+
+/// The sender comes from a header, so it is hashed rather than trusted to
+/// make a sane key
+fn window_key(config: &Config, scope: SenderScope, sender: &str) -> Result<S3Key, StoreError> {
+    let digest: String = Sha256::digest(sender.to_lowercase().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    S3Key::try_from(format!(
+        "{}/{}/{}.json",
+        config.rate_limit_prefix,
+        scope.as_str(),
+        digest
+    ))
+    .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+async fn load_window(
+    store: &dyn MailStore,
+    config: &Config,
+    scope: SenderScope,
+    sender: &str,
+) -> Result<SenderWindow, StoreError> {
+    let key = window_key(config, scope, sender)?;
+    match store.get_object(&config.email_bucket, &key).await {
+        Ok(body) => match serde_json::from_slice(&body) {
+            Ok(window) => Ok(window),
+            Err(e) => {
+                warn!("Resetting unreadable rate limit window {}: {}", key, e);
+                Ok(SenderWindow::new(scope, sender))
+            }
+        },
+        Err(StoreError::NotFound(_)) => Ok(SenderWindow::new(scope, sender)),
+        Err(e) => Err(e),
+    }
+}
+
+async fn save_window(
+    store: &dyn MailStore,
+    config: &Config,
+    window: &SenderWindow,
+) -> Result<(), StoreError> {
+    let key = window_key(config, window.scope, &window.sender)?;
+    let body = serde_json::to_vec(window)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode rate limit window: {}", e)))?;
+    store.put_object(&config.email_bucket, &key, body).await
+}
+
+/// Count a message from `sender` against its address and domain windows.
+/// Returns the window that tripped when the message is over the limit; the
+/// message is then recorded as held there. A stale burst found on the way is
+/// reported first. Windows are read and written without locking, so
+/// concurrent deliveries can let a message or two past the limit.
+pub async fn record_arrival(
+    context: &AppContext,
+    config: &Config,
+    limit: &RateLimit,
+    message_id: &MessageId,
+    sender: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<HeldBy>, AwsError> {
+    let domain = sender.rsplit_once('@').map(|(_, domain)| domain);
+    let scopes = [
+        (SenderScope::Address, Some(sender)),
+        (SenderScope::Domain, domain),
+    ];
+
+    let mut held_by = None;
+    for (scope, value) in scopes {
+        let (Some(max), Some(value)) = (limit.limit(scope), value) else {
+            continue;
+        };
+
+        let mut window = load_window(context.store.as_ref(), config, scope, value).await?;
+        // A failed notice leaves the messages listed for the next attempt
+        if window.burst_ended(limit.window, now) {
+            if let Err(e) = send_held_notice(context, config, limit, &mut window).await {
+                warn!("Failed to send held-mail notice for {}: {}", value, e);
+            }
+        }
+
+        window.arrivals.retain(|at| now - *at < limit.window);
+        window.arrivals.push(now);
+
+        if held_by.is_none() && window.arrivals.len() > max as usize {
+            info!(
+                "Holding {} from {}: {} messages in {} minutes exceeds the {} limit of {}",
+                message_id,
+                value,
+                window.arrivals.len(),
+                limit.window.num_minutes(),
+                scope.as_str(),
+                max
+            );
+            window.held.push(message_id.to_string());
+            window.last_held_at = Some(now);
+            held_by = Some(HeldBy {
+                scope,
+                sender: value.to_string(),
+            });
+        }
+
+        save_window(context.store.as_ref(), config, &window).await?;
+    }
+
+    Ok(held_by)
+}
+
+/// Send one "N messages held from X" notice for the window's held messages
+/// and clear them. The caller saves the window.
+async fn send_held_notice(
+    context: &AppContext,
+    config: &Config,
+    limit: &RateLimit,
+    window: &mut SenderWindow,
+) -> Result<String, AwsError> {
+    let count = window.held.len();
+    let to = EmailAddress::try_from(config.forward_to_email.clone())?;
+    let reply_to = EmailAddress::try_from(config.forwarder_email.clone())?;
+    let variables = Variables::from([
+        ("sender", window.sender.clone()),
+        ("scope", window.scope.as_str().to_string()),
+        ("count", count.to_string()),
+        (
+            "messages",
+            if count == 1 { "message" } else { "messages" }.to_string(),
+        ),
+        (
+            "limit",
+            limit.limit(window.scope).unwrap_or_default().to_string(),
+        ),
+        ("window_minutes", limit.window.num_minutes().to_string()),
+        ("held_prefix", config.held_prefix.clone()),
+        ("message_ids", window.held.join("\n")),
+    ]);
+    let message = Template::builtin(TemplateKind::HeldNotice).render(&variables)?;
+
+    let notice_message_id = send_email_via_ses(
+        &context.ses_client,
+        &config.forwarder_email,
+        &to,
+        &reply_to,
+        &message,
+    )
+    .await?;
+    info!(
+        "Sent held-mail notice for {} message(s) from {}",
+        count, window.sender
+    );
+
+    window.held.clear();
+    Ok(notice_message_id)
+}
+
+/// Send notices for bursts that have ended (or for every window with held
+/// messages when `force` is set). Runs on the Lambda's EventBridge schedule
+/// so the last burst from a sender is reported without waiting for its next
+/// message.
+pub async fn flush_held_notices(
+    context: &AppContext,
+    config: &Config,
+    force: bool,
+    now: DateTime<Utc>,
+) -> Result<Vec<HeldNotice>, AwsError> {
+    let Some(limit) = &config.rate_limit else {
+        return Ok(Vec::new());
+    };
+
+    let prefix = format!("{}/", config.rate_limit_prefix);
+    let objects = context
+        .store
+        .list_objects(&config.email_bucket, &prefix)
+        .await?;
+
+    let mut notices = Vec::new();
+    for object in objects {
+        let body = context
+            .store
+            .get_object(&config.email_bucket, &object.key)
+            .await?;
+        let mut window: SenderWindow = match serde_json::from_slice(&body) {
+            Ok(window) => window,
+            Err(e) => {
+                warn!(
+                    "Skipping unreadable rate limit window {}: {}",
+                    object.key, e
+                );
+                continue;
+            }
+        };
+        if window.held.is_empty() || !(force || window.burst_ended(limit.window, now)) {
+            continue;
+        }
+
+        let held = window.held.len();
+        let notice_message_id = send_held_notice(context, config, limit, &mut window).await?;
+        save_window(context.store.as_ref(), config, &window).await?;
+        notices.push(HeldNotice {
+            sender: window.sender,
+            held,
+            notice_message_id,
+        });
+    }
+
+    Ok(notices)
+}
+
+/// Messages waiting under `held_prefix`, sorted by key
+pub async fn list_held(
+    context: &AppContext,
+    config: &Config,
+) -> Result<Vec<StoredObject>, AwsError> {
+    let prefix = format!("{}/", config.held_prefix);
+    Ok(context
+        .store
+        .list_objects(&config.email_bucket, &prefix)
+        .await?)
+}
+
+/// Forward held messages after all and remove their held copies. Released
+/// messages go through the pipeline as re-processed mail, which is not rate
+/// limited.
+pub async fn release_held(
+    context: &AppContext,
+    config: &Config,
+    message_ids: &[String],
+) -> Result<ReplayReport, AwsError> {
+    let mut report = ReplayReport::default();
+
+    for id in message_ids {
+        let message_id = MessageId::try_from(id.clone())?;
+        match crate::forward_stored_message(context, config, &message_id, None).await {
+            Ok(delivery) => {
+                let key = incoming_key(&config.held_prefix, &message_id)?;
+                context
+                    .store
+                    .delete_object(&config.email_bucket, &key)
+                    .await?;
+                match delivery.outcome {
+                    ForwardOutcome::Forwarded(_) => report.replayed.push(id.clone()),
+                    ForwardOutcome::Suppressed(_) => report.suppressed.push(id.clone()),
+                }
+            }
+            Err(e) => report.failed.push(ForwardFailure {
+                message_id: id.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_rate_limit_sender() {
+        let email = b"From: \"Contact Form\" <Form@Venue.example>\r\n\r\nBody";
+        assert_eq!(
+            rate_limit_sender(email, Some("bounce@mailer.example")).as_deref(),
+            Some("form@venue.example")
+        );
+        assert_eq!(
+            rate_limit_sender(b"Subject: Hi\r\n\r\nBody", Some("bounce@mailer.example")).as_deref(),
+            Some("bounce@mailer.example")
+        );
+        assert_eq!(
+            rate_limit_sender(b"Subject: Hi\r\n\r\nBody", Some("<>")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_window_key_hashes_the_sender() {
+        let config = Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "me@example.com".to_string(),
+        );
+        let key = window_key(&config, SenderScope::Address, "form@venue.example").unwrap();
+        let name = key
+            .as_str()
+            .strip_prefix("rate-limit/address/")
+            .and_then(|name| name.strip_suffix(".json"))
+            .unwrap();
+        assert_eq!(name.len(), 64);
+        assert!(name.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            window_key(&config, SenderScope::Address, "Form@Venue.example")
+                .unwrap()
+                .as_str(),
+            key.as_str()
+        );
+
+        let hostile = format!("\"../{}\"@bücher.example", "x".repeat(2000));
+        let key = window_key(&config, SenderScope::Address, &hostile).unwrap();
+        assert_eq!(key.as_str().len(), "rate-limit/address/".len() + 64 + 5);
+    }
+
+    #[test]
+    fn test_burst_ends_after_a_quiet_window() {
+        let at = Utc.with_ymd_and_hms(2026, 3, 14, 9, 0, 0).unwrap();
+        let mut window = SenderWindow::new(SenderScope::Address, "form@venue.example");
+        assert!(!window.burst_ended(Duration::minutes(60), at));
+
+        window.held.push("abc".to_string());
+        window.last_held_at = Some(at);
+        assert!(!window.burst_ended(Duration::minutes(60), at + Duration::minutes(59)));
+        assert!(window.burst_ended(Duration::minutes(60), at + Duration::minutes(60)));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::aws::{send_email_via_ses, AppContext, AwsError};
+use crate::config::Config;
+use crate::domain::{EmailAddress, S3Key};
+use crate::routing::split_subaddress;
+use crate::store::StoreError;
+use crate::template::{Template, TemplateKind, Variables};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use tracing::info;
+
+/// Skip reason for mail to an unknown address kept under the store-only policy
+pub const UNKNOWN_RECIPIENT_REASON: &str = "unknown-recipient";
+/// Skip reason for mail to an unknown address under the drop policy. The
+/// bucket lifecycle expires objects tagged with it.
+pub const UNKNOWN_RECIPIENT_DROPPED_REASON: &str = "unknown-recipient-dropped";
+
+/// What to do with mail none of whose recipients is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownRecipientPolicy {
+    /// Forward on the `catch-all` route
+    #[default]
+    CatchAll,
+    /// Do not forward; the stored copy expires
+    Drop,
+    /// Do not forward; keep the stored copy
+    StoreOnly,
+}
+
+impl FromStr for UnknownRecipientPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "catch-all" | "forward" => Ok(UnknownRecipientPolicy::CatchAll),
+            "drop" => Ok(UnknownRecipientPolicy::Drop),
+            "store" | "store-only" => Ok(UnknownRecipientPolicy::StoreOnly),
+            other => Err(format!(
+                "expected catch-all, drop or store-only, got '{}'",
+                other
+            )),
+        }
+    }
+}
+
+/// Addresses on our domain the message was sent to that nobody receives
+/// mail at: not in `known_recipients` and not claimed by any route. Empty
+/// when no known-recipient list is configured, and subaddresses are ignored.
+pub fn unknown_recipients<S: AsRef<str>>(config: &Config, recipients: &[S]) -> Vec<String> {
+    if config.known_recipients.is_empty() {
+        return Vec::new();
+    }
+
+    let ours: Vec<String> = recipients
+        .iter()
+        .map(|recipient| recipient.as_ref().trim().to_lowercase())
+        .filter(|recipient| {
+            recipient
+                .rsplit_once('@')
+                .is_some_and(|(_, domain)| domain.eq_ignore_ascii_case(config.forwarder_domain()))
+        })
+        .collect();
+
+    let is_known = |recipient: &str| {
+        let (base, _) = split_subaddress(recipient);
+        let local_part = base
+            .split_once('@')
+            .map_or(base.as_str(), |(local, _)| local);
+        config.routes.claims(recipient)
+            || config.known_recipients.iter().any(|known| {
+                let known = known.trim().to_lowercase();
+                if known.contains('@') {
+                    known == base
+                } else {
+                    known == local_part
+                }
+            })
+    };
+
+    // One known address is enough to deliver the message normally
+    if ours.iter().any(|recipient| is_known(recipient)) {
+        return Vec::new();
+    }
+    ours
+}
+
+/// Hits per unknown address for one UTC day, kept under `unknown_recipient_prefix`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UnknownRecipientDay {
+    counts: BTreeMap<String, u64>,
+}
+
+fn day_key(config: &Config, day: NaiveDate) -> Result<S3Key, StoreError> {
+    S3Key::try_from(format!(
+        "{}/{}.json",
+        config.unknown_recipient_prefix,
+        day.format("%Y-%m-%d")
+    ))
+    .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+async fn load_day(
+    context: &AppContext,
+    config: &Config,
+    day: NaiveDate,
+) -> Result<UnknownRecipientDay, AwsError> {
+    let key = day_key(config, day)?;
+    match context.store.get_object(&config.email_bucket, &key).await {
+        Ok(body) => Ok(serde_json::from_slice(&body).unwrap_or_default()),
+        Err(StoreError::NotFound(_)) => Ok(UnknownRecipientDay::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Count a message to unknown addresses. Like the rate limiter's windows,
+/// the counters are read-modify-write, so concurrent deliveries can lose a
+/// hit; the report only needs to be roughly right.
+pub async fn record_unknown_recipients(
+    context: &AppContext,
+    config: &Config,
+    addresses: &[String],
+    now: DateTime<Utc>,
+) -> Result<(), AwsError> {
+    let day = now.date_naive();
+    let mut counts = load_day(context, config, day).await?;
+    for address in addresses {
+        *counts.counts.entry(address.clone()).or_default() += 1;
+    }
+
+    let body = serde_json::to_vec_pretty(&counts)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode counters: {}", e)))?;
+    context
+        .store
+        .put_object(&config.email_bucket, &day_key(config, day)?, body)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRecipientHits {
+    pub address: String,
+    pub hits: u64,
+    pub last_seen: NaiveDate,
+}
+
+/// The most-hit unknown addresses over a run of days
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRecipientReport {
+    pub since: NaiveDate,
+    pub until: NaiveDate,
+    /// Hits on every unknown address, not just the listed ones
+    pub total: u64,
+    pub addresses: Vec<UnknownRecipientHits>,
+}
+
+impl fmt::Display for UnknownRecipientReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Unknown recipients {} to {}: {} hits",
+            self.since, self.until, self.total
+        )?;
+        for hits in &self.addresses {
+            writeln!(
+                f,
+                "{:>8}  {}  (last {})",
+                hits.hits, hits.address, hits.last_seen
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Add up the last `days` days of counters, ending today, and keep the `top`
+/// most-hit addresses
+pub async fn unknown_recipient_report(
+    context: &AppContext,
+    config: &Config,
+    days: u32,
+    top: usize,
+    now: DateTime<Utc>,
+) -> Result<UnknownRecipientReport, AwsError> {
+    let until = now.date_naive();
+    let since = until - Duration::days(i64::from(days.max(1)) - 1);
+
+    let mut totals: BTreeMap<String, (u64, NaiveDate)> = BTreeMap::new();
+    for day in since.iter_days().take_while(|day| *day <= until) {
+        for (address, hits) in load_day(context, config, day).await?.counts {
+            let entry = totals.entry(address).or_insert((0, day));
+            entry.0 += hits;
+            entry.1 = day;
+        }
+    }
+
+    let total = totals.values().map(|(hits, _)| hits).sum();
+    let mut addresses: Vec<UnknownRecipientHits> = totals
+        .into_iter()
+        .map(|(address, (hits, last_seen))| UnknownRecipientHits {
+            address,
+            hits,
+            last_seen,
+        })
+        .collect();
+    // Most hits first, then alphabetically for a stable report
+    addresses.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.address.cmp(&b.address)));
+    addresses.truncate(top);
+
+    Ok(UnknownRecipientReport {
+        since,
+        until,
+        total,
+        addresses,
+    })
+}
+
+/// Email the report to the forwarding mailbox
+pub async fn send_unknown_recipient_report(
+    context: &AppContext,
+    config: &Config,
+    report: &UnknownRecipientReport,
+) -> Result<String, AwsError> {
+    let to = EmailAddress::try_from(config.forward_to_email.clone())?;
+    let reply_to = EmailAddress::try_from(config.forwarder_email.clone())?;
+
+    let lines: Vec<String> = report
+        .addresses
+        .iter()
+        .map(|hits| format!("{:>6}  {}", hits.hits, hits.address))
+        .collect();
+    let variables = Variables::from([
+        ("domain", config.forwarder_domain().to_string()),
+        ("since", report.since.to_string()),
+        ("until", report.until.to_string()),
+        ("total", report.total.to_string()),
+        ("addresses", lines.join("\n")),
+    ]);
+    let message = Template::builtin(TemplateKind::UnknownRecipientReport).render(&variables)?;
+
+    let sent_id = send_email_via_ses(
+        &context.ses_client,
+        &config.forwarder_email,
+        &to,
+        &reply_to,
+        &message,
+    )
+    .await?;
+    info!(
+        "Sent unknown-recipient report for {} to {}",
+        report.since, report.until
+    );
+    Ok(sent_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::RoutingTable;
+
+    fn config() -> Config {
+        let mut config = Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "me@gmail.com".to_string(),
+        );
+        config.known_recipients = vec!["info".to_string(), "jim@jimmillerdrums.com".to_string()];
+        config.routes =
+            RoutingTable::from_json(r#"[{"name": "booking", "addresses": ["booking", "gigs"]}]"#)
+                .unwrap();
+        config
+    }
+
+    #[test]
+    fn test_unknown_recipients() {
+        let config = config();
+        assert!(unknown_recipients(&config, &["info+gigsalad@jimmillerdrums.com"]).is_empty());
+        assert!(unknown_recipients(&config, &["Gigs@jimmillerdrums.com"]).is_empty());
+        assert!(unknown_recipients(&config, &["jim@jimmillerdrums.com"]).is_empty());
+        assert_eq!(
+            unknown_recipients(&config, &["admin@jimmillerdrums.com", "fan@example.com"]),
+            vec!["admin@jimmillerdrums.com"]
+        );
+        // Any known recipient is enough
+        assert!(unknown_recipients(
+            &config,
+            &["admin@jimmillerdrums.com", "info@jimmillerdrums.com"]
+        )
+        .is_empty());
+
+        let open = Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "me@gmail.com".to_string(),
+        );
+        assert!(unknown_recipients(&open, &["admin@jimmillerdrums.com"]).is_empty());
+    }
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!(
+            "Catch-All".parse::<UnknownRecipientPolicy>(),
+            Ok(UnknownRecipientPolicy::CatchAll)
+        );
+        assert_eq!("drop".parse(), Ok(UnknownRecipientPolicy::Drop));
+        assert_eq!("store-only".parse(), Ok(UnknownRecipientPolicy::StoreOnly));
+        assert!("bounce".parse::<UnknownRecipientPolicy>().is_err());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::alias::{alias_id_from_address, load_alias};
+use crate::aws::{send_raw_email_via_ses, validate_email_size, AppContext, AwsError};
+use crate::config::Config;
+use crate::domain::{MessageId, SesMessage};
+use crate::email::{extract_email_address, extract_reply_to_info};
+use crate::mime::{rewrite_email_headers, HeaderRewrite};
+use hmac::{Hmac, Mac};
+use mailparse::{parse_headers, MailHeaderMap};
+use sha2::Sha256;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Local-part prefix of relay addresses, e.g.
+/// `reply+1a2b3c4d=example.com=fan@jimmillerdrums.com`
+pub(crate) const RELAY_PREFIX: &str = "reply+";
+const MAX_LOCAL_PART_LEN: usize = 64;
+
+/// Headers of a relayed reply that would reveal the personal mailbox it was
+/// written in: envelope and authentication traces, the provider's own ids
+/// and copies to anyone but the correspondent
+const RELAY_DROPPED_HEADERS: &[&str] = &[
+    "Cc",
+    "Bcc",
+    "Received",
+    "Received-SPF",
+    "Authentication-Results",
+    "ARC-Seal",
+    "ARC-Message-Signature",
+    "ARC-Authentication-Results",
+    "X-Received",
+    "X-Google-DKIM-Signature",
+    "X-Google-Smtp-Source",
+    "X-Gm-Message-State",
+    "X-Gm-Gg",
+    "X-Gm-Features",
+    "X-Gmail-Original-Message-ID",
+    "X-SES-Spam-Verdict",
+    "X-SES-Virus-Verdict",
+    "X-SES-Receipt",
+    "X-SES-DKIM-Signature",
+    "X-Original-To",
+    "Delivered-To",
+];
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RelayError {
+    #[error("Not a relay address: {0}")]
+    NotRelay(String),
+    #[error("Malformed relay address: {0}")]
+    Malformed(String),
+    #[error("Relay token mismatch for {0}")]
+    BadToken(String),
+}
+
+/// Why a message to a relay address was not sent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayRefusal {
+    /// Not from the forwarding mailbox, or not authenticated as it
+    Unauthorized,
+    /// The token did not verify against the signing key, or names no alias
+    InvalidToken,
+    /// The conversation alias was revoked
+    Revoked,
+}
+
+impl RelayRefusal {
+    /// Skip reason recorded on the stored message
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RelayRefusal::Unauthorized => "relay-unauthorized",
+            RelayRefusal::InvalidToken => "relay-invalid",
+            RelayRefusal::Revoked => "relay-revoked",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayOutcome {
+    /// Sent to the correspondent; carries their address and the SES message id
+    Relayed {
+        correspondent: String,
+        message_id: String,
+    },
+    Refused(RelayRefusal),
+}
+
+/// Whether `address` is a reply address produced by [`encode_relay_address`]
+pub fn is_relay_address(address: &str) -> bool {
+    address
+        .trim()
+        .get(..RELAY_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(RELAY_PREFIX))
+}
+
+/// Reply address on `domain` that stands in for `correspondent` on forwarded
+/// mail. `None` when the result would not fit in a local part.
+pub fn encode_relay_address(correspondent: &str, domain: &str, secret: &str) -> Option<String> {
+    let (local, sender_domain) = correspondent.trim().rsplit_once('@')?;
+    if local.is_empty() || sender_domain.is_empty() {
+        return None;
+    }
+
+    let token = signature(secret, sender_domain, local);
+    let local_part = format!(
+        "{}{}={}={}",
+        RELAY_PREFIX,
+        token,
+        sender_domain.to_lowercase(),
+        local.to_lowercase()
+    );
+    if local_part.len() > MAX_LOCAL_PART_LEN {
+        return None;
+    }
+
+    Some(format!("{}@{}", local_part, domain))
+}
+
+/// Signed reply address for whoever a reply to `raw_email` would go to, for
+/// when no conversation alias can be stored. `None` when that is the
+/// forwarding mailbox itself or the address does not fit.
+pub fn signed_reply_address(config: &Config, secret: &str, raw_email: &[u8]) -> Option<String> {
+    let (correspondent, _) = extract_reply_to_info(raw_email).ok()?;
+    if correspondent
+        .trim()
+        .eq_ignore_ascii_case(&config.forward_to_email)
+    {
+        return None;
+    }
+    encode_relay_address(&correspondent, config.forwarder_domain(), secret)
+}
+
+/// Recover the correspondent from a reply address, checking its token
+pub fn decode_relay_address(address: &str, secret: &str) -> Result<String, RelayError> {
+    let address = address.trim();
+    if !is_relay_address(address) {
+        return Err(RelayError::NotRelay(address.to_string()));
+    }
+
+    let local_part = address
+        .rsplit_once('@')
+        .map(|(local, _)| local)
+        .unwrap_or(address);
+    let mut fields = local_part[RELAY_PREFIX.len()..].splitn(3, '=');
+    let (Some(token), Some(sender_domain), Some(local)) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(RelayError::Malformed(address.to_string()));
+    };
+    if local.is_empty() || sender_domain.is_empty() {
+        return Err(RelayError::Malformed(address.to_string()));
+    }
+
+    if !signature(secret, sender_domain, local).eq_ignore_ascii_case(token) {
+        return Err(RelayError::BadToken(address.to_string()));
+    }
+
+    Ok(format!("{}@{}", local, sender_domain).to_lowercase())
+}
+
+fn signature(secret: &str, domain: &str, local: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(b"relay:");
+    mac.update(domain.to_lowercase().as_bytes());
+    mac.update(b"@");
+    mac.update(local.to_lowercase().as_bytes());
+    mac.finalize().into_bytes()[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether a message to a relay address really comes from the forwarding
+/// mailbox: its `From` must be `forward_to_email` and SES must have seen
+/// DMARC pass, i.e. SPF or DKIM pass for a domain aligned with that `From`.
+/// A bare SPF or DKIM pass may be for any domain and proves nothing about
+/// the `From`. Hand-built events without verdicts never qualify.
+pub fn is_authorized_relay_sender(config: &Config, ses: &SesMessage, raw_email: &[u8]) -> bool {
+    let from = parse_headers(raw_email)
+        .ok()
+        .and_then(|(headers, _)| headers.get_first_value("From"))
+        .and_then(|value| extract_email_address(&value).ok());
+    let from_matches =
+        from.is_some_and(|from| from.trim().eq_ignore_ascii_case(&config.forward_to_email));
+
+    let authenticated = ses.receipt.as_ref().is_some_and(|receipt| {
+        receipt
+            .dmarc_verdict
+            .as_ref()
+            .is_some_and(|verdict| verdict.status == "PASS")
+    });
+
+    from_matches && authenticated
+}
+
+/// Send a reply written in the forwarding mailbox on to the correspondent
+/// behind `relay_address`. Conversation aliases relay from the address the
+/// correspondent wrote to and restore the conversation's threading; signed
+/// addresses relay from `relay_from_email`.
+pub async fn relay_reply(
+    context: &AppContext,
+    config: &Config,
+    message_id: &MessageId,
+    ses: &SesMessage,
+    relay_address: &str,
+    raw_email: &[u8],
+) -> Result<RelayOutcome, AwsError> {
+    let Some(secret) = &config.relay_secret else {
+        return Ok(RelayOutcome::Refused(RelayRefusal::InvalidToken));
+    };
+
+    if !is_authorized_relay_sender(config, ses, raw_email) {
+        warn!(
+            "Refusing to relay {}: not from the forwarding mailbox",
+            message_id
+        );
+        return Ok(RelayOutcome::Refused(RelayRefusal::Unauthorized));
+    }
+
+    let mut drop_headers: Vec<String> = RELAY_DROPPED_HEADERS
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+    let mut extra_headers = Vec::new();
+
+    let (correspondent, from) = match alias_id_from_address(relay_address) {
+        Some(id) => match load_alias(context, config, &id).await? {
+            Some(alias) if alias.is_revoked() => {
+                warn!("Refusing to relay {}: alias {} revoked", message_id, id);
+                return Ok(RelayOutcome::Refused(RelayRefusal::Revoked));
+            }
+            Some(alias) => {
+                // The mailbox threads on our forwarded copies, whose ids the
+                // correspondent never saw
+                let thread_headers = alias.thread_headers();
+                if !thread_headers.is_empty() {
+                    drop_headers.extend(["In-Reply-To".to_string(), "References".to_string()]);
+                    extra_headers = thread_headers;
+                }
+                (alias.correspondent, alias.our_address)
+            }
+            None => {
+                warn!("Refusing to relay {}: no alias {}", message_id, id);
+                return Ok(RelayOutcome::Refused(RelayRefusal::InvalidToken));
+            }
+        },
+        None => match decode_relay_address(relay_address, secret) {
+            Ok(correspondent) => (correspondent, config.relay_from().to_string()),
+            Err(e) => {
+                warn!("Refusing to relay {}: {}", message_id, e);
+                return Ok(RelayOutcome::Refused(RelayRefusal::InvalidToken));
+            }
+        },
+    };
+
+    validate_email_size(raw_email, config.max_email_size_mb)?;
+    let rewrite = HeaderRewrite {
+        from: from.clone(),
+        to: correspondent.clone(),
+        reply_to: from.clone(),
+        extra_headers,
+        drop_headers,
+        authserv_id: Some(config.forwarder_domain().to_string()),
+        ..Default::default()
+    };
+    let relayed = rewrite_email_headers(raw_email, &rewrite)?;
+
+    let sent_id = send_raw_email_via_ses(&context.ses_client, &relayed, &from, None).await?;
+    info!("Relayed reply {} to {}", message_id, correspondent);
+
+    Ok(RelayOutcome::Relayed {
+        correspondent,
+        message_id: sent_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{SesMail, SesReceipt, SesVerdict};
+
+    const SECRET: &str = "relay-secret";
+
+    fn ses(spf: &str, dkim: &str, dmarc: &str) -> SesMessage {
+        let verdict = |status: &str| {
+            Some(SesVerdict {
+                status: status.to_string(),
+            })
+        };
+        SesMessage {
+            mail: SesMail {
+                message_id: "reply1".to_string(),
+                source: "jim@gmail.com".to_string(),
+                destination: vec!["reply+x@jimmillerdrums.com".to_string()],
+            },
+            receipt: Some(SesReceipt {
+                spf_verdict: verdict(spf),
+                dkim_verdict: verdict(dkim),
+                dmarc_verdict: verdict(dmarc),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let address =
+            encode_relay_address("Fan@Example.com", "jimmillerdrums.com", SECRET).unwrap();
+        assert!(address.starts_with("reply+"));
+        assert!(address.ends_with("=example.com=fan@jimmillerdrums.com"));
+        assert!(is_relay_address(&address));
+
+        assert_eq!(
+            decode_relay_address(&address.to_uppercase(), SECRET).unwrap(),
+            "fan@example.com"
+        );
+    }
+
+    #[test]
+    fn test_tampered_address_is_rejected() {
+        let address =
+            encode_relay_address("fan@example.com", "jimmillerdrums.com", SECRET).unwrap();
+        let forged = address.replace("=fan@", "=victim@");
+        assert!(matches!(
+            decode_relay_address(&forged, SECRET),
+            Err(RelayError::BadToken(_))
+        ));
+        assert!(matches!(
+            decode_relay_address("reply+abc@jimmillerdrums.com", SECRET),
+            Err(RelayError::Malformed(_))
+        ));
+        assert!(!is_relay_address("info@jimmillerdrums.com"));
+
+        let long = format!("{}@example.com", "a".repeat(50));
+        assert_eq!(
+            encode_relay_address(&long, "jimmillerdrums.com", SECRET),
+            None
+        );
+    }
+
+    #[test]
+    fn test_only_the_forwarding_mailbox_may_relay() {
+        let config = Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "jim@gmail.com".to_string(),
+        );
+        let reply = b"From: Jim <Jim@gmail.com>\r\nSubject: Re: Gig\r\n\r\nSounds good";
+        assert!(is_authorized_relay_sender(
+            &config,
+            &ses("PASS", "FAIL", "PASS"),
+            reply
+        ));
+        assert!(!is_authorized_relay_sender(
+            &config,
+            &ses("FAIL", "GRAY", "FAIL"),
+            reply
+        ));
+
+        let spoofed = b"From: someone@example.com\r\nSubject: Re: Gig\r\n\r\nHi";
+        assert!(!is_authorized_relay_sender(
+            &config,
+            &ses("PASS", "PASS", "PASS"),
+            spoofed
+        ));
+    }
+
+    #[test]
+    fn test_unaligned_pass_does_not_authorize_a_spoofed_from() {
+        let config = Config::new(
+            "bucket".to_string(),
+            "incoming".to_string(),
+            "jim@gmail.com".to_string(),
+        );
+        // Sent through the attacker's own domain, which passes SPF and DKIM
+        // for itself, with the forwarding mailbox in `From`
+        let spoofed = b"Return-Path: <bounce@attacker.example>\r\nFrom: Jim <jim@gmail.com>\r\nSubject: Re: Gig\r\n\r\nSend the deposit here";
+        assert!(!is_authorized_relay_sender(
+            &config,
+            &ses("PASS", "PASS", "FAIL"),
+            spoofed
+        ));
+        let mut no_dmarc = ses("PASS", "PASS", "GRAY");
+        no_dmarc.receipt.as_mut().unwrap().dmarc_verdict = None;
+        assert!(!is_authorized_relay_sender(&config, &no_dmarc, spoofed));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::autoreply::detect_automated;
+use crate::aws::{send_email_via_ses, AppContext, AwsError};
+use crate::config::Config;
+use crate::domain::{EmailAddress, MessageId, S3Key, SesMail, TimeWindow};
+use crate::email::{extract_email_address, extract_sender_name, EmailError};
+use crate::store::StoreError;
+use crate::template::{RenderedMessage, Template, TemplateError, TemplateKind, Variables};
+use chrono::{DateTime, Duration, Utc};
+use mailparse::{parse_headers, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+/// Local parts that never get an auto-reply (RFC 3834 section 2)
+const NO_REPLY_LOCAL_PARTS: &[&str] = &[
+    "noreply",
+    "no-reply",
+    "no_reply",
+    "donotreply",
+    "do-not-reply",
+    "mailer-daemon",
+    "postmaster",
+    "listserv",
+    "majordomo",
+];
+
+/// Headers that mark mail distributed by a list (RFC 2369, RFC 2919)
+const LIST_HEADERS: &[&str] = &["List-Id", "List-Unsubscribe", "List-Post", "List-Help"];
+
+#[derive(Error, Debug)]
+pub enum ResponderError {
+    #[error("Invalid auto-responder settings: {0}")]
+    InvalidJson(String),
+    #[error("Invalid auto-responder date: {0}")]
+    InvalidDate(String),
+    #[error(transparent)]
+    InvalidTemplate(#[from] TemplateError),
+}
+
+/// Auto-responder settings as configured in `AUTO_RESPONDER`:
+/// `{"routes": ["booking"], "since": "2026-06-01", "until": "2026-07-15", "body": "Hi {{sender_name}}, ..."}`.
+/// Without a `body` the built-in `auto_reply` template is used.
+#[derive(Debug, Clone, Deserialize)]
+struct ResponderConfig {
+    /// Routes whose mail is answered (all when empty)
+    #[serde(default)]
+    routes: Vec<String>,
+    #[serde(default)]
+    since: Option<String>,
+    #[serde(default)]
+    until: Option<String>,
+    #[serde(default)]
+    subject: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+    /// Optional HTML alternative to `body`
+    #[serde(default)]
+    html: Option<String>,
+    /// Reply to the same sender at most once per this many days
+    #[serde(default = "default_interval_days")]
+    interval_days: u32,
+}
+
+fn default_interval_days() -> u32 {
+    7
+}
+
+/// Why a message got no auto-reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoReplyReason {
+    /// Auto-submitted, bulk, null sender or a bounce
+    Automated,
+    MailingList,
+    NoReplySender,
+    /// The sender already had a reply within the interval
+    RecentlyReplied,
+}
+
+impl NoReplyReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoReplyReason::Automated => "automated",
+            NoReplyReason::MailingList => "mailing-list",
+            NoReplyReason::NoReplySender => "no-reply-sender",
+            NoReplyReason::RecentlyReplied => "recently-replied",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoReplyOutcome {
+    /// Carries the SES message id of the reply
+    Sent(String),
+    Skipped(NoReplyReason),
+}
+
+/// When the responder last answered a sender, kept under `responder_prefix`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReplyRecord {
+    sender: String,
+    replied_at: DateTime<Utc>,
+}
+
+/// Vacation-style acknowledgement for mail on selected routes while active.
+/// Templates may use `{{sender_name}}`, `{{subject}}`, `{{since}}` and `{{until}}`.
+#[derive(Debug, Clone)]
+pub struct AutoResponder {
+    routes: Vec<String>,
+    active: TimeWindow,
+    template: Template,
+    interval: Duration,
+}
+
+impl AutoResponder {
+    pub fn from_json(json: &str) -> Result<Self, ResponderError> {
+        let config: ResponderConfig =
+            serde_json::from_str(json).map_err(|e| ResponderError::InvalidJson(e.to_string()))?;
+
+        let bound = |value: Option<String>| {
+            value
+                .map(|v| {
+                    TimeWindow::parse_bound(&v).map_err(|_| ResponderError::InvalidDate(v.clone()))
+                })
+                .transpose()
+        };
+
+        let template = match config.body {
+            Some(body) => Template::parse(
+                TemplateKind::AutoReply,
+                config.subject.as_deref().unwrap_or("Auto: {{subject}}"),
+                &body,
+                config.html.as_deref(),
+            )?,
+            None => Template::builtin(TemplateKind::AutoReply),
+        };
+
+        Ok(Self {
+            routes: config.routes,
+            active: TimeWindow {
+                since: bound(config.since)?,
+                until: bound(config.until)?,
+            },
+            template,
+            interval: Duration::days(config.interval_days.into()),
+        })
+    }
+
+    /// Whether mail on `route` arriving at `now` should be answered
+    pub fn is_active(&self, route: &str, now: DateTime<Utc>) -> bool {
+        self.active.contains(now)
+            && (self.routes.is_empty()
+                || self
+                    .routes
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(route)))
+    }
+
+    /// The reply to `raw_email`, without threading headers
+    pub fn render(&self, raw_email: &[u8]) -> Result<RenderedMessage, AwsError> {
+        let headers = parse_headers(raw_email).map(|(headers, _)| headers).ok();
+        let header = |name: &str| {
+            headers
+                .as_ref()
+                .and_then(|headers| headers.get_first_value(name))
+                .unwrap_or_default()
+        };
+        let subject = header("Subject");
+        let sender_name = extract_sender_name(&header("From"));
+        let date = |bound: Option<DateTime<Utc>>| {
+            bound
+                .map(|at| at.format("%B %-d, %Y").to_string())
+                .unwrap_or_default()
+        };
+
+        let variables = Variables::from([
+            ("sender_name", sender_name.trim().to_string()),
+            ("subject", subject.trim().to_string()),
+            ("since", date(self.active.since)),
+            ("until", date(self.active.until)),
+        ]);
+        Ok(self.template.render(&variables)?)
+    }
+}
+
+/// RFC 3834 checks that do not need the reply history: never answer
+/// automated mail, list traffic or addresses that cannot take replies
+pub fn no_reply_reason(
+    raw_email: &[u8],
+    envelope_source: &str,
+) -> Result<Option<NoReplyReason>, EmailError> {
+    if detect_automated(raw_email, Some(envelope_source))?.is_some() {
+        return Ok(Some(NoReplyReason::Automated));
+    }
+
+    let (headers, _) = parse_headers(raw_email)?;
+    let is_list = LIST_HEADERS
+        .iter()
+        .any(|name| headers.get_first_value(name).is_some())
+        || headers
+            .get_first_value("Precedence")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("list"));
+    if is_list {
+        return Ok(Some(NoReplyReason::MailingList));
+    }
+
+    let local_part = envelope_source
+        .split_once('@')
+        .map(|(local, _)| local.trim().to_lowercase())
+        .unwrap_or_default();
+    let no_reply = NO_REPLY_LOCAL_PARTS.contains(&local_part.as_str())
+        || local_part.starts_with("bounce")
+        || local_part.starts_with("owner-")
+        || local_part.ends_with("-request");
+    if no_reply {
+        return Ok(Some(NoReplyReason::NoReplySender));
+    }
+
+    Ok(None)
+}
+
+fn reply_record_key(config: &Config, sender: &str) -> Result<S3Key, StoreError> {
+    S3Key::try_from(format!("{}/{}.json", config.responder_prefix, sender))
+        .map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+/// Answer a forwarded message from the address it was sent to. Replies go
+/// to the envelope sender (RFC 3834 section 4) and are marked
+/// `Auto-Submitted: auto-replied`.
+pub async fn send_auto_reply(
+    context: &AppContext,
+    config: &Config,
+    responder: &AutoResponder,
+    message_id: &MessageId,
+    mail: &SesMail,
+    raw_email: &[u8],
+    now: DateTime<Utc>,
+) -> Result<AutoReplyOutcome, AwsError> {
+    let envelope_source = mail.source.as_str();
+    if let Some(reason) = no_reply_reason(raw_email, envelope_source)? {
+        return Ok(AutoReplyOutcome::Skipped(reason));
+    }
+    let Some(our_address) = mail.destination.first() else {
+        return Ok(AutoReplyOutcome::Skipped(NoReplyReason::NoReplySender));
+    };
+    let sender = extract_email_address(envelope_source)?.to_lowercase();
+
+    let key = reply_record_key(config, &sender)?;
+    match context.store.get_object(&config.email_bucket, &key).await {
+        Ok(body) => {
+            let recent = serde_json::from_slice::<ReplyRecord>(&body)
+                .is_ok_and(|record| now - record.replied_at < responder.interval);
+            if recent {
+                return Ok(AutoReplyOutcome::Skipped(NoReplyReason::RecentlyReplied));
+            }
+        }
+        Err(StoreError::NotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut reply = responder.render(raw_email)?;
+    let headers = &mut reply.headers;
+    headers.push(("Auto-Submitted".to_string(), "auto-replied".to_string()));
+    if let Some(original_id) = parse_headers(raw_email)
+        .ok()
+        .and_then(|(headers, _)| headers.get_first_value("Message-ID"))
+    {
+        headers.push(("In-Reply-To".to_string(), original_id.trim().to_string()));
+        headers.push(("References".to_string(), original_id.trim().to_string()));
+    }
+
+    let reply_id = send_email_via_ses(
+        &context.ses_client,
+        our_address,
+        &EmailAddress::try_from(sender.clone())?,
+        &EmailAddress::try_from(our_address.clone())?,
+        &reply,
+    )
+    .await?;
+    info!("Sent auto-reply for {} to {}", message_id, sender);
+
+    let record = ReplyRecord {
+        sender,
+        replied_at: now,
+    };
+    let body = serde_json::to_vec(&record)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode reply record: {}", e)))?;
+    context
+        .store
+        .put_object(&config.email_bucket, &key, body)
+        .await?;
+
+    Ok(AutoReplyOutcome::Sent(reply_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn responder() -> AutoResponder {
+        AutoResponder::from_json(
+            r#"{
+                "routes": ["booking"],
+                "since": "2026-06-01",
+                "until": "2026-07-15",
+                "body": "Hi {{sender_name}},\n\nI'm on tour until {{until}} and will reply to \"{{subject}}\" when I'm back.",
+                "html": "<p>Hi {{sender_name}}, I'm on tour until {{until}}.</p>"
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_active_by_route_and_date() {
+        let responder = responder();
+        let on_tour = Utc.with_ymd_and_hms(2026, 6, 20, 12, 0, 0).unwrap();
+        assert!(responder.is_active("booking", on_tour));
+        assert!(!responder.is_active("lessons", on_tour));
+
+        let back = Utc.with_ymd_and_hms(2026, 7, 15, 0, 0, 0).unwrap();
+        assert!(!responder.is_active("booking", back));
+    }
+
+    #[test]
+    fn test_render() {
+        let email =
+            b"From: \"Pat Planner\" <pat@example.com>\r\nSubject: Wedding on June 6\r\n\r\nBody";
+        let reply = responder().render(email).unwrap();
+        assert_eq!(reply.subject.as_str(), "Auto: Wedding on June 6");
+        assert_eq!(
+            reply.text.as_str(),
+            "Hi Pat Planner,\n\nI'm on tour until July 15, 2026 and will reply to \"Wedding on June 6\" when I'm back."
+        );
+        assert_eq!(
+            reply.html.as_deref(),
+            Some("<p>Hi Pat Planner, I'm on tour until July 15, 2026.</p>")
+        );
+
+        // Without a body the built-in template is used
+        let builtin = AutoResponder::from_json(r#"{"until": "2026-07-15"}"#).unwrap();
+        let reply = builtin.render(email).unwrap();
+        assert_eq!(reply.subject.as_str(), "Auto: Wedding on June 6");
+        assert!(reply.text.as_str().contains("away until July 15, 2026"));
+        assert!(reply.html.is_some());
+    }
+
+    #[test]
+    fn test_rfc3834_exclusions() {
+        let human = b"From: pat@example.com\r\nSubject: Hi\r\n\r\nBody";
+        assert_eq!(no_reply_reason(human, "pat@example.com").unwrap(), None);
+
+        let vacation = b"Auto-Submitted: auto-replied\r\nFrom: pat@example.com\r\n\r\nAway";
+        assert_eq!(
+            no_reply_reason(vacation, "pat@example.com").unwrap(),
+            Some(NoReplyReason::Automated)
+        );
+
+        let list = b"List-Id: <drummers.lists.example.org>\r\nFrom: pat@example.com\r\n\r\nBody";
+        assert_eq!(
+            no_reply_reason(list, "drummers-bounces@lists.example.org").unwrap(),
+            Some(NoReplyReason::MailingList)
+        );
+
+        assert_eq!(
+            no_reply_reason(human, "No-Reply@venue.example").unwrap(),
+            Some(NoReplyReason::NoReplySender)
+        );
+        assert_eq!(
+            no_reply_reason(human, "drummers-request@lists.example.org").unwrap(),
+            Some(NoReplyReason::NoReplySender)
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings_are_rejected() {
+        assert!(matches!(
+            AutoResponder::from_json(r#"{"since": "June", "body": "x"}"#),
+            Err(ResponderError::InvalidDate(_))
+        ));
+        assert!(AutoResponder::from_json(r#"{"routes": "booking"}"#).is_err());
+        assert!(matches!(
+            AutoResponder::from_json(r#"{"body": "Hi {{name}}"}"#),
+            Err(ResponderError::InvalidTemplate(_))
+        ));
+    }
+}
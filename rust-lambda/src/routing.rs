@@ -0,0 +1,352 @@
+use crate::disposition::DEFAULT_ROUTE;
+use mailparse::{addrparse_header, parse_headers, MailAddr};
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Header carrying the `+detail` of the address a message was sent to
+pub const SUBADDRESS_HEADER: &str = "X-Subaddress";
+/// Placeholder in a route's subject tag replaced with the subaddress
+const SUBADDRESS_PLACEHOLDER: &str = "{{subaddress}}";
+
+#[derive(Error, Debug)]
+pub enum RoutingError {
+    #[error("Invalid routing table: {0}")]
+    InvalidJson(String),
+    #[error("Invalid route name '{0}': use letters, digits, '-', '_' or '.'")]
+    InvalidRouteName(String),
+}
+
+/// A named group of our addresses, e.g. `booking` for `booking@` and `gigs@`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Route {
+    pub name: String,
+    /// Full addresses or bare local parts (matching any of our domains). A
+    /// plain address also takes its plus-addressed forms; `info+gigsalad`
+    /// takes only that subaddress and `info+*` any subaddress.
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// Subject prefix for mail on this route, e.g. `[booking]`. May use
+    /// `{{subaddress}}`, e.g. `[lead:{{subaddress}}]`.
+    #[serde(default)]
+    pub subject_tag: Option<String>,
+}
+
+/// How specifically a route address matched a destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchKind {
+    Base,
+    AnySubaddress,
+    Subaddress,
+}
+
+impl Route {
+    fn matches(&self, destination: &str) -> Option<MatchKind> {
+        let destination = destination.trim().to_lowercase();
+        let (base, detail) = split_subaddress(&destination);
+
+        self.addresses
+            .iter()
+            .filter_map(|address| {
+                let address = address.trim().to_lowercase();
+                let (address_base, address_detail) = split_subaddress(&address);
+                let same_base = if address_base.contains('@') {
+                    address_base == base
+                } else {
+                    base.split_once('@')
+                        .map_or(base.as_str(), |(local, _)| local)
+                        == address_base
+                };
+                if !same_base {
+                    return None;
+                }
+                match (address_detail.as_deref(), detail.as_deref()) {
+                    (None, _) => Some(MatchKind::Base),
+                    (Some("*"), Some(_)) => Some(MatchKind::AnySubaddress),
+                    (Some(wanted), Some(detail)) if wanted == detail => Some(MatchKind::Subaddress),
+                    _ => None,
+                }
+            })
+            .max()
+    }
+
+    /// This route for mail sent to `subaddress`, with the subject tag filled in
+    fn for_subaddress(&self, subaddress: Option<&str>) -> Route {
+        let mut route = self.clone();
+        route.subject_tag = route
+            .subject_tag
+            .map(|tag| tag.replace(SUBADDRESS_PLACEHOLDER, subaddress.unwrap_or_default()));
+        route
+    }
+}
+
+/// Split `info+gigsalad@jimmillerdrums.com` into `info@jimmillerdrums.com`
+/// and `gigsalad`. Bare local parts split the same way.
+pub fn split_subaddress(address: &str) -> (String, Option<String>) {
+    let (local, domain) = match address.rsplit_once('@') {
+        Some((local, domain)) => (local, Some(domain)),
+        None => (address, None),
+    };
+    let (base, detail) = match local.split_once('+') {
+        Some((base, detail)) if !base.is_empty() && !detail.is_empty() => {
+            (base, Some(detail.to_string()))
+        }
+        _ => (local, None),
+    };
+    let base = match domain {
+        Some(domain) => format!("{}@{}", base, domain),
+        None => base.to_string(),
+    };
+    (base, detail)
+}
+
+/// Ordered routes, configured as JSON in `ROUTES`:
+/// `[{"name": "booking", "addresses": ["booking", "gigs"], "subject_tag": "[booking]"}]`.
+/// A route named `default` supplies settings for mail no other route matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn from_json(json: &str) -> Result<Self, RoutingError> {
+        let table: RoutingTable =
+            serde_json::from_str(json).map_err(|e| RoutingError::InvalidJson(e.to_string()))?;
+
+        for route in &table.routes {
+            if !is_valid_route_name(&route.name) {
+                return Err(RoutingError::InvalidRouteName(route.name.clone()));
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Route by name, e.g. one chosen by a classifier
+    pub fn get(&self, name: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| route.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Route for mail classified as `category`: the route of that name, or a
+    /// route carrying the classifier's subject tag
+    pub fn for_category(&self, category: &str, subject_tag: Option<&str>) -> Route {
+        self.get(category).cloned().unwrap_or_else(|| Route {
+            name: category.to_string(),
+            addresses: Vec::new(),
+            subject_tag: subject_tag.map(str::to_string),
+        })
+    }
+
+    /// Whether any route claims `destination`
+    pub fn claims(&self, destination: &str) -> bool {
+        self.routes
+            .iter()
+            .any(|route| route.matches(destination).is_some())
+    }
+
+    /// Route for the first destination any route claims, falling back to the
+    /// `default` route
+    pub fn resolve<S: AsRef<str>>(&self, destinations: &[S]) -> Route {
+        self.resolve_with_subaddress(destinations).0
+    }
+
+    /// Like [`RoutingTable::resolve`], also returning the subaddress of the
+    /// destination that decided the route. Among the routes claiming that
+    /// destination the most specific wins: an exact subaddress, then
+    /// `+*`, then the plain address, earlier routes breaking ties.
+    pub fn resolve_with_subaddress<S: AsRef<str>>(
+        &self,
+        destinations: &[S],
+    ) -> (Route, Option<String>) {
+        for destination in destinations {
+            let destination = destination.as_ref();
+            let mut best: Option<(MatchKind, &Route)> = None;
+            for route in &self.routes {
+                if let Some(kind) = route.matches(destination) {
+                    if best.is_none_or(|(best_kind, _)| kind > best_kind) {
+                        best = Some((kind, route));
+                    }
+                }
+            }
+            if let Some((_, route)) = best {
+                let (_, subaddress) = split_subaddress(&destination.trim().to_lowercase());
+                return (route.for_subaddress(subaddress.as_deref()), subaddress);
+            }
+        }
+
+        let subaddress = destinations.first().and_then(|destination| {
+            split_subaddress(&destination.as_ref().trim().to_lowercase()).1
+        });
+        let route = self.get(DEFAULT_ROUTE).cloned().unwrap_or_else(|| Route {
+            name: DEFAULT_ROUTE.to_string(),
+            addresses: Vec::new(),
+            subject_tag: None,
+        });
+        (route.for_subaddress(subaddress.as_deref()), subaddress)
+    }
+}
+
+/// Route names end up in S3 tags, so keep them to a safe character set
+pub(crate) fn is_valid_route_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Addresses in the stored message's `To` and `Cc` headers, used for routing
+/// when the SES envelope recipients are not available
+pub fn stored_recipients(raw_email: &[u8]) -> Vec<String> {
+    let Ok((headers, _)) = parse_headers(raw_email) else {
+        return Vec::new();
+    };
+
+    headers
+        .iter()
+        .filter(|h| {
+            let key = h.get_key_ref();
+            key.eq_ignore_ascii_case("To") || key.eq_ignore_ascii_case("Cc")
+        })
+        .filter_map(|h| addrparse_header(h).ok())
+        .flat_map(|list| list.iter().cloned().collect::<Vec<_>>())
+        .flat_map(|addr| match addr {
+            MailAddr::Single(single) => vec![single.addr],
+            MailAddr::Group(group) => group.addrs.into_iter().map(|s| s.addr).collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> RoutingTable {
+        RoutingTable::from_json(
+            r#"[
+                {"name": "booking", "addresses": ["booking", "gigs@jimmillerdrums.com"], "subject_tag": "[booking]"},
+                {"name": "lessons", "addresses": ["lessons"], "subject_tag": "[lessons]"}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_by_local_part_or_address() {
+        let table = table();
+        assert_eq!(
+            table.resolve(&["Booking@jimmillerdrums.com"]).name,
+            "booking"
+        );
+        assert_eq!(table.resolve(&["gigs@jimmillerdrums.com"]).name, "booking");
+        assert_eq!(table.resolve(&["gigs@example.com"]).name, "default");
+        assert_eq!(
+            table
+                .resolve(&["info@jimmillerdrums.com", "lessons@jimmillerdrums.com"])
+                .subject_tag
+                .as_deref(),
+            Some("[lessons]")
+        );
+    }
+
+    #[test]
+    fn test_subaddress_routing() {
+        let leads = RoutingTable::from_json(
+            r#"[
+                {"name": "info", "addresses": ["info"], "subject_tag": "[info]"},
+                {"name": "leads", "addresses": ["info+*"], "subject_tag": "[lead:{{subaddress}}]"},
+                {"name": "gigsalad", "addresses": ["info+gigsalad@jimmillerdrums.com"]}
+            ]"#,
+        )
+        .unwrap();
+
+        let (route, subaddress) =
+            leads.resolve_with_subaddress(&["Info+GigSalad@jimmillerdrums.com"]);
+        assert_eq!(route.name, "gigsalad");
+        assert_eq!(subaddress.as_deref(), Some("gigsalad"));
+
+        let (route, subaddress) =
+            leads.resolve_with_subaddress(&["info+thumbtack@jimmillerdrums.com"]);
+        assert_eq!(route.name, "leads");
+        assert_eq!(route.subject_tag.as_deref(), Some("[lead:thumbtack]"));
+        assert_eq!(subaddress.as_deref(), Some("thumbtack"));
+
+        let (route, subaddress) = leads.resolve_with_subaddress(&["info@jimmillerdrums.com"]);
+        assert_eq!(route.name, "info");
+        assert_eq!(subaddress, None);
+
+        // A plain address takes plus-addressed mail when nothing more specific does
+        assert_eq!(
+            table()
+                .resolve(&["booking+wedding@jimmillerdrums.com"])
+                .name,
+            "booking"
+        );
+        let (route, subaddress) = table().resolve_with_subaddress(&["nobody+x@jimmillerdrums.com"]);
+        assert_eq!(route.name, "default");
+        assert_eq!(subaddress.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn test_split_subaddress() {
+        assert_eq!(
+            split_subaddress("info+gigsalad@jimmillerdrums.com"),
+            (
+                "info@jimmillerdrums.com".to_string(),
+                Some("gigsalad".to_string())
+            )
+        );
+        assert_eq!(
+            split_subaddress("info+a+b@jimmillerdrums.com"),
+            (
+                "info@jimmillerdrums.com".to_string(),
+                Some("a+b".to_string())
+            )
+        );
+        assert_eq!(split_subaddress("info+"), ("info+".to_string(), None));
+        assert_eq!(
+            split_subaddress("+x@example.com"),
+            ("+x@example.com".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn test_default_route_settings() {
+        let table =
+            RoutingTable::from_json(r#"[{"name": "default", "subject_tag": "[jmd]"}]"#).unwrap();
+        let route = table.resolve(&["info@jimmillerdrums.com"]);
+        assert_eq!(route.name, "default");
+        assert_eq!(route.subject_tag.as_deref(), Some("[jmd]"));
+
+        let route = RoutingTable::default().resolve::<&str>(&[]);
+        assert_eq!(route.name, "default");
+        assert_eq!(route.subject_tag, None);
+    }
+
+    #[test]
+    fn test_stored_recipients() {
+        let email = b"To: \"Jim\" <lessons@jimmillerdrums.com>, info@jimmillerdrums.com\r\nCc: band: gigs@jimmillerdrums.com;\r\n\r\nBody";
+        assert_eq!(
+            stored_recipients(email),
+            vec![
+                "lessons@jimmillerdrums.com",
+                "info@jimmillerdrums.com",
+                "gigs@jimmillerdrums.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_tables_are_rejected() {
+        assert!(RoutingTable::from_json("{").is_err());
+        assert!(matches!(
+            RoutingTable::from_json(r#"[{"name": "gig bookings"}]"#),
+            Err(RoutingError::InvalidRouteName(_))
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::attachments::extract_attachments;
+use crate::email::EmailError;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// Skip reason for messages the scanner found infected
+pub const INFECTED_REASON: &str = "infected";
+
+/// Bytes sent per INSTREAM chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Longest reply clamd sends for a stream scan
+const MAX_REPLY_LEN: usize = 4096;
+
+#[derive(Error, Debug)]
+pub enum ScannerError {
+    #[error("Invalid scanner address: {0}")]
+    InvalidAddress(String),
+    #[error("Scanner connection failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Scanner did not answer within {0:?}")]
+    Timeout(Duration),
+    #[error("Scanner error: {0}")]
+    Scanner(String),
+    #[error("Failed to parse email: {0}")]
+    Email(#[from] EmailError),
+}
+
+/// Where clamd listens: `tcp://host:3310` or `host:3310`, and
+/// `unix:///run/clamd.sock` or an absolute socket path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScannerAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ScannerAddress {
+    type Err = ScannerError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(path) = value
+            .strip_prefix("unix://")
+            .or(value.strip_prefix("unix:"))
+        {
+            return Ok(ScannerAddress::Unix(PathBuf::from(path)));
+        }
+        if value.starts_with('/') {
+            return Ok(ScannerAddress::Unix(PathBuf::from(value)));
+        }
+        let address = value.strip_prefix("tcp://").unwrap_or(value);
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(ScannerAddress::Tcp(address.to_string()))
+            }
+            _ => Err(ScannerError::InvalidAddress(value.to_string())),
+        }
+    }
+}
+
+/// What is sent to the scanner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanTarget {
+    /// The raw message in one stream
+    #[default]
+    Message,
+    /// Each decoded attachment in its own stream
+    Attachments,
+}
+
+impl FromStr for ScanTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "message" => Ok(ScanTarget::Message),
+            "attachments" => Ok(ScanTarget::Attachments),
+            other => Err(format!("expected message or attachments, got '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Carries the signature name clamd reported
+    Infected(String),
+}
+
+/// A positive scan of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanFinding {
+    pub signature: String,
+    /// Filename of the infected attachment; `None` for a whole-message scan
+    pub attachment: Option<String>,
+}
+
+/// Client for clamd's `INSTREAM` command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClamdScanner {
+    pub address: ScannerAddress,
+    pub target: ScanTarget,
+    /// Limit on each scan, connection included
+    pub timeout: Duration,
+}
+
+impl ClamdScanner {
+    /// Scan one stream of bytes
+    pub async fn scan(&self, data: &[u8]) -> Result<ScanVerdict, ScannerError> {
+        let scan = async {
+            match &self.address {
+                ScannerAddress::Tcp(address) => {
+                    instream(TcpStream::connect(address).await?, data).await
+                }
+                #[cfg(unix)]
+                ScannerAddress::Unix(path) => {
+                    instream(tokio::net::UnixStream::connect(path).await?, data).await
+                }
+                #[cfg(not(unix))]
+                ScannerAddress::Unix(path) => {
+                    Err(ScannerError::InvalidAddress(path.display().to_string()))
+                }
+            }
+        };
+        tokio::time::timeout(self.timeout, scan)
+            .await
+            .map_err(|_| ScannerError::Timeout(self.timeout))?
+    }
+
+    /// Scan a message as configured, stopping at the first infection
+    pub async fn scan_message(
+        &self,
+        raw_email: &[u8],
+    ) -> Result<Option<ScanFinding>, ScannerError> {
+        match self.target {
+            ScanTarget::Message => Ok(match self.scan(raw_email).await? {
+                ScanVerdict::Clean => None,
+                ScanVerdict::Infected(signature) => Some(ScanFinding {
+                    signature,
+                    attachment: None,
+                }),
+            }),
+            ScanTarget::Attachments => {
+                for attachment in extract_attachments(raw_email)? {
+                    if let ScanVerdict::Infected(signature) = self.scan(&attachment.body).await? {
+                        return Ok(Some(ScanFinding {
+                            signature,
+                            attachment: Some(
+                                attachment
+                                    .filename
+                                    .unwrap_or_else(|| format!("attachment {}", attachment.index)),
+                            ),
+                        }));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Send `data` with `zINSTREAM` (length-prefixed chunks ending with an
+/// empty one) and read clamd's NUL-terminated reply
+async fn instream<S>(mut stream: S, data: &[u8]) -> Result<ScanVerdict, ScannerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !reply.contains(&0) && reply.len() < MAX_REPLY_LEN {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buffer[..read]);
+    }
+    let reply = String::from_utf8_lossy(&reply);
+    let verdict = parse_reply(reply.trim_end_matches(['\0', '\n']))?;
+    info!("Scanner verdict: {:?}", verdict);
+    Ok(verdict)
+}
+
+/// `stream: OK`, `stream: <signature> FOUND`, or `<message> ERROR`
+fn parse_reply(reply: &str) -> Result<ScanVerdict, ScannerError> {
+    let result = reply
+        .strip_prefix("stream:")
+        .map(str::trim)
+        .unwrap_or(reply.trim());
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(ScannerError::Scanner(
+            result.trim_end_matches(" ERROR").to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            "tcp://clamd.internal:3310"
+                .parse::<ScannerAddress>()
+                .unwrap(),
+            ScannerAddress::Tcp("clamd.internal:3310".to_string())
+        );
+        assert_eq!(
+            "127.0.0.1:3310".parse::<ScannerAddress>().unwrap(),
+            ScannerAddress::Tcp("127.0.0.1:3310".to_string())
+        );
+        assert_eq!(
+            "unix:///run/clamd.sock".parse::<ScannerAddress>().unwrap(),
+            ScannerAddress::Unix(PathBuf::from("/run/clamd.sock"))
+        );
+        assert_eq!(
+            "/run/clamd.sock".parse::<ScannerAddress>().unwrap(),
+            ScannerAddress::Unix(PathBuf::from("/run/clamd.sock"))
+        );
+        assert!("clamd".parse::<ScannerAddress>().is_err());
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply("stream: OK").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND").unwrap(),
+            ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string())
+        );
+        assert!(matches!(
+            parse_reply("INSTREAM size limit exceeded. ERROR"),
+            Err(ScannerError::Scanner(message)) if message == "INSTREAM size limit exceeded."
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::email::{extract_email_address, EmailError};
+use mailparse::{parse_headers, MailHeaderMap};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SenderRuleError {
+    #[error("Invalid sender rules: {0}")]
+    InvalidJson(String),
+    #[error("Invalid sender pattern '{0}': {1}")]
+    InvalidPattern(String, String),
+}
+
+/// What to do with mail from a matching sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SenderAction {
+    /// Do not forward; the stored copy is kept and tagged
+    Drop,
+    /// Do not forward; copy the message to the quarantine prefix for review
+    Quarantine,
+    /// Forward even when a block rule or a store-only policy would stop it
+    AlwaysForward,
+}
+
+impl SenderAction {
+    /// Short slug used in logs, tags and the handler response
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SenderAction::Drop => "sender-drop",
+            SenderAction::Quarantine => "sender-quarantine",
+            SenderAction::AlwaysForward => "sender-allow",
+        }
+    }
+}
+
+/// Which sender identity a rule looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderField {
+    /// The SES envelope `mail.source` (`Return-Path` when re-processing)
+    Source,
+    /// The address in the `From` header
+    From,
+    /// The domain of either of the above
+    Domain,
+}
+
+impl SenderField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SenderField::Source => "source",
+            SenderField::From => "from",
+            SenderField::Domain => "domain",
+        }
+    }
+}
+
+/// One rule as configured in `SENDER_RULES`:
+/// `{"action": "drop", "field": "domain", "pattern": "*.seo-experts.example"}`.
+/// Patterns are case-insensitive wildcards (`*`, `?`) matching the whole
+/// value, or regular expressions when written as `/.../`.
+#[derive(Debug, Clone, Deserialize)]
+struct SenderRuleConfig {
+    action: SenderAction,
+    field: SenderField,
+    pattern: String,
+}
+
+#[derive(Debug, Clone)]
+struct SenderRule {
+    action: SenderAction,
+    field: SenderField,
+    pattern: String,
+    matcher: Regex,
+}
+
+/// The rule that decided what happens to a message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SenderMatch {
+    pub action: SenderAction,
+    pub field: SenderField,
+    pub pattern: String,
+    /// The sender identity the pattern matched
+    pub value: String,
+}
+
+/// Sender allow and block lists. Allow (`always-forward`) rules are checked
+/// first, so one trusted address can be let through a blocked domain; block
+/// rules then apply in configured order.
+#[derive(Debug, Clone, Default)]
+pub struct SenderRules {
+    rules: Vec<SenderRule>,
+}
+
+impl SenderRules {
+    pub fn from_json(json: &str) -> Result<Self, SenderRuleError> {
+        let configs: Vec<SenderRuleConfig> =
+            serde_json::from_str(json).map_err(|e| SenderRuleError::InvalidJson(e.to_string()))?;
+
+        let rules = configs
+            .into_iter()
+            .map(|config| {
+                let matcher = compile_pattern(&config.pattern).map_err(|e| {
+                    SenderRuleError::InvalidPattern(config.pattern.clone(), e.to_string())
+                })?;
+                Ok(SenderRule {
+                    action: config.action,
+                    field: config.field,
+                    pattern: config.pattern,
+                    matcher,
+                })
+            })
+            .collect::<Result<_, SenderRuleError>>()?;
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// First matching rule for the sender identities. `envelope_source` is
+    /// the SES `mail.source` when known; otherwise the stored `Return-Path`
+    /// stands in for it.
+    pub fn evaluate(
+        &self,
+        envelope_source: Option<&str>,
+        raw_email: &[u8],
+    ) -> Result<Option<SenderMatch>, EmailError> {
+        if self.rules.is_empty() {
+            return Ok(None);
+        }
+
+        let (headers, _) = parse_headers(raw_email)?;
+        let source = match envelope_source {
+            Some(source) => Some(source.trim().to_string()),
+            None => headers.get_first_value("Return-Path"),
+        }
+        .and_then(|value| extract_email_address(&value).ok())
+        .filter(|address| address.contains('@'));
+        let from = headers
+            .get_first_value("From")
+            .and_then(|value| extract_email_address(&value).ok())
+            .filter(|address| address.contains('@'));
+
+        let domains: Vec<&str> = [&source, &from]
+            .into_iter()
+            .flatten()
+            .filter_map(|address| address.rsplit_once('@').map(|(_, domain)| domain))
+            .collect();
+
+        let allow = self
+            .rules
+            .iter()
+            .filter(|rule| rule.action == SenderAction::AlwaysForward);
+        let block = self
+            .rules
+            .iter()
+            .filter(|rule| rule.action != SenderAction::AlwaysForward);
+
+        Ok(allow.chain(block).find_map(|rule| {
+            let candidates: Vec<&str> = match rule.field {
+                SenderField::Source => source.as_deref().into_iter().collect(),
+                SenderField::From => from.as_deref().into_iter().collect(),
+                SenderField::Domain => domains.clone(),
+            };
+            candidates
+                .into_iter()
+                .find(|value| rule.matcher.is_match(value))
+                .map(|value| SenderMatch {
+                    action: rule.action,
+                    field: rule.field,
+                    pattern: rule.pattern.clone(),
+                    value: value.to_lowercase(),
+                })
+        }))
+    }
+}
+
+/// `/regex/` as written, anything else as an anchored wildcard pattern
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    let pattern = pattern.trim();
+    let source = match pattern
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'))
+    {
+        Some(regex) if !regex.is_empty() => regex.to_string(),
+        _ => format!(
+            "^{}$",
+            regex::escape(pattern)
+                .replace(r"\*", ".*")
+                .replace(r"\?", ".")
+        ),
+    };
+    RegexBuilder::new(&source).case_insensitive(true).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"[
+        {"action": "drop", "field": "domain", "pattern": "*seo-experts.example"},
+        {"action": "quarantine", "field": "from", "pattern": "/^(crypto|invest)[0-9]*@/"},
+        {"action": "always-forward", "field": "from", "pattern": "booker@seo-experts.example"},
+        {"action": "drop", "field": "source", "pattern": "bounce-???@mailer.example.com"}
+    ]"#;
+
+    fn rules() -> SenderRules {
+        SenderRules::from_json(RULES).unwrap()
+    }
+
+    fn email(from: &str) -> Vec<u8> {
+        format!("From: {}\r\nSubject: Hi\r\n\r\nBody", from).into_bytes()
+    }
+
+    #[test]
+    fn test_wildcard_domain_block() {
+        let result = rules()
+            .evaluate(
+                Some("news@mail.seo-experts.example"),
+                &email("Rank <rank@mail.seo-experts.example>"),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.action, SenderAction::Drop);
+        assert_eq!(result.field, SenderField::Domain);
+        assert_eq!(result.value, "mail.seo-experts.example");
+    }
+
+    #[test]
+    fn test_allow_rule_wins_over_block() {
+        let result = rules()
+            .evaluate(
+                Some("booker@seo-experts.example"),
+                &email("Booker@SEO-Experts.example"),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.action, SenderAction::AlwaysForward);
+        assert_eq!(result.action.as_str(), "sender-allow");
+    }
+
+    #[test]
+    fn test_regex_and_envelope_rules() {
+        let result = rules()
+            .evaluate(Some("a@example.com"), &email("invest42@example.org"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.action, SenderAction::Quarantine);
+
+        // Without an SES event the stored Return-Path is the envelope sender
+        let stored =
+            b"Return-Path: <bounce-abc@mailer.example.com>\r\nFrom: fan@example.com\r\n\r\nBody";
+        let result = rules().evaluate(None, stored).unwrap().unwrap();
+        assert_eq!(result.field, SenderField::Source);
+
+        assert_eq!(
+            rules()
+                .evaluate(Some("fan@example.com"), &email("fan@example.com"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wildcards_match_whole_value() {
+        // "bounce-???" needs exactly three characters, and the pattern is anchored
+        assert_eq!(
+            rules()
+                .evaluate(
+                    Some("bounce-abcd@mailer.example.com"),
+                    &email("x@example.com")
+                )
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        assert!(matches!(
+            SenderRules::from_json(r#"[{"action": "bounce", "field": "from", "pattern": "*"}]"#),
+            Err(SenderRuleError::InvalidJson(_))
+        ));
+        assert!(matches!(
+            SenderRules::from_json(r#"[{"action": "drop", "field": "from", "pattern": "/(/"}]"#),
+            Err(SenderRuleError::InvalidPattern(_, _))
+        ));
+    }
+}
@@ -0,0 +1,414 @@
+use crate::aws::{send_email_via_ses, AppContext, AwsError};
+use crate::config::Config;
+use crate::domain::{EmailAddress, MessageId, S3Key};
+use crate::email::extract_email_address;
+use crate::store::StoreError;
+use crate::template::{Template, TemplateKind, Variables};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use mailparse::{parse_headers, parse_mail, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Local-part prefix of return addresses we generate, e.g.
+/// `SRS0=1a2b3c4d=QE=example.com=fan@jimmillerdrums.com`
+const SRS_PREFIX: &str = "SRS0=";
+const TIMESTAMP_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// Timestamps are days since the epoch modulo this (two base32 characters)
+const TIMESTAMP_PERIOD: i64 = 1024;
+const MAX_LOCAL_PART_LEN: usize = 64;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SrsError {
+    #[error("Not an SRS address: {0}")]
+    NotSrs(String),
+    #[error("Malformed SRS address: {0}")]
+    Malformed(String),
+    #[error("SRS hash mismatch for {0}")]
+    BadHash(String),
+    #[error("SRS address expired ({0} days old)")]
+    Expired(i64),
+}
+
+/// Whether `address` is a return address produced by [`encode_return_path`]
+pub fn is_srs_address(address: &str) -> bool {
+    address
+        .get(..SRS_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(SRS_PREFIX))
+}
+
+/// Return address on `domain` that encodes `original` so bounces come back
+/// to us and can be traced. `None` when there is nothing to encode (null
+/// sender) or the result would not fit in a local part.
+pub fn encode_return_path(
+    original: &str,
+    domain: &str,
+    secret: &str,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    let (local, sender_domain) = original.trim().rsplit_once('@')?;
+    if local.is_empty() || sender_domain.is_empty() {
+        return None;
+    }
+
+    let timestamp = encode_timestamp(now);
+    let hash = signature(secret, &timestamp, sender_domain, local);
+    let local_part = format!(
+        "{}{}={}={}={}",
+        SRS_PREFIX, hash, timestamp, sender_domain, local
+    );
+    if local_part.len() > MAX_LOCAL_PART_LEN {
+        return None;
+    }
+
+    Some(format!("{}@{}", local_part, domain))
+}
+
+/// Recover the original envelope sender from a return address, checking the
+/// signature and that it is no older than `max_age_days`
+pub fn decode_return_path(
+    address: &str,
+    secret: &str,
+    max_age_days: u32,
+    now: DateTime<Utc>,
+) -> Result<String, SrsError> {
+    if !is_srs_address(address) {
+        return Err(SrsError::NotSrs(address.to_string()));
+    }
+
+    let local_part = address
+        .rsplit_once('@')
+        .map(|(local, _)| local)
+        .unwrap_or(address);
+    let mut fields = local_part[SRS_PREFIX.len()..].splitn(4, '=');
+    let (Some(hash), Some(timestamp), Some(sender_domain), Some(local)) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(SrsError::Malformed(address.to_string()));
+    };
+    if local.is_empty() || sender_domain.is_empty() {
+        return Err(SrsError::Malformed(address.to_string()));
+    }
+
+    // Hashes are compared case-insensitively since relays may fold the local part
+    if !signature(secret, timestamp, sender_domain, local).eq_ignore_ascii_case(hash) {
+        return Err(SrsError::BadHash(address.to_string()));
+    }
+
+    let issued =
+        decode_timestamp(timestamp).ok_or_else(|| SrsError::Malformed(address.to_string()))?;
+    let age = (days_since_epoch(now) - issued).rem_euclid(TIMESTAMP_PERIOD);
+    if age > i64::from(max_age_days) {
+        return Err(SrsError::Expired(age));
+    }
+
+    Ok(format!("{}@{}", local, sender_domain))
+}
+
+/// Envelope sender of a stored message: the `Return-Path` SES records on
+/// receipt, falling back to `From`. `None` for the null sender.
+pub fn original_envelope_sender(raw_email: &[u8]) -> Option<String> {
+    let (headers, _) = parse_headers(raw_email).ok()?;
+    let value = headers
+        .get_first_value("Return-Path")
+        .or_else(|| headers.get_first_value("From"))?;
+    let address = extract_email_address(&value).ok()?;
+    let address = address.trim();
+    (!address.is_empty() && address.contains('@')).then(|| address.to_string())
+}
+
+fn signature(secret: &str, timestamp: &str, domain: &str, local: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_ascii_uppercase().as_bytes());
+    mac.update(domain.to_lowercase().as_bytes());
+    mac.update(local.to_lowercase().as_bytes());
+    mac.finalize().into_bytes()[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn days_since_epoch(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(86_400)
+}
+
+fn encode_timestamp(now: DateTime<Utc>) -> String {
+    let days = days_since_epoch(now).rem_euclid(TIMESTAMP_PERIOD) as usize;
+    [days >> 5, days & 31]
+        .iter()
+        .map(|&index| TIMESTAMP_ALPHABET[index] as char)
+        .collect()
+}
+
+fn decode_timestamp(timestamp: &str) -> Option<i64> {
+    let timestamp = timestamp.to_ascii_uppercase();
+    if timestamp.len() != 2 {
+        return None;
+    }
+    timestamp.bytes().try_fold(0i64, |days, c| {
+        let index = TIMESTAMP_ALPHABET.iter().position(|&a| a == c)?;
+        Some((days << 5) | index as i64)
+    })
+}
+
+/// What we learned from a bounce sent to one of our return addresses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BounceRecord {
+    pub message_id: String,
+    pub return_path: String,
+    /// Original envelope sender, when the return address verified
+    pub original_sender: Option<String>,
+    /// Why the return address could not be decoded
+    pub decode_error: Option<String>,
+    pub subject: Option<String>,
+    /// DSN `Status`, e.g. `5.1.1`
+    pub status: Option<String>,
+    /// DSN `Diagnostic-Code`
+    pub diagnostic_code: Option<String>,
+    /// SES message id of the notice sent to the original sender
+    pub notice_message_id: Option<String>,
+    pub received_at: DateTime<Utc>,
+}
+
+impl BounceRecord {
+    /// Skip reason recorded on the stored bounce
+    pub fn reason(&self) -> &'static str {
+        if self.original_sender.is_some() {
+            "srs-bounce"
+        } else {
+            "srs-invalid"
+        }
+    }
+}
+
+/// Decode the return address a bounce was sent to, write a [`BounceRecord`]
+/// under `bounce_prefix` and, when enabled, tell the original sender
+pub async fn handle_srs_bounce(
+    context: &AppContext,
+    config: &Config,
+    message_id: &MessageId,
+    return_path: &str,
+) -> Result<BounceRecord, AwsError> {
+    let now = Utc::now();
+    let decoded = match &config.srs_secret {
+        Some(secret) => decode_return_path(return_path, secret, config.srs_max_age_days, now),
+        None => Err(SrsError::NotSrs(return_path.to_string())),
+    };
+
+    let key = crate::disposition::incoming_key(&config.incoming_prefix, message_id)?;
+    let email_bytes = context.store.get_object(&config.email_bucket, &key).await?;
+    let (subject, status, diagnostic_code) = delivery_status(&email_bytes);
+
+    let mut record = BounceRecord {
+        message_id: message_id.to_string(),
+        return_path: return_path.to_string(),
+        original_sender: None,
+        decode_error: None,
+        subject,
+        status,
+        diagnostic_code,
+        notice_message_id: None,
+        received_at: now,
+    };
+
+    match decoded {
+        Ok(original_sender) => {
+            info!(
+                "Bounce {} for message from {}: {:?}",
+                message_id, original_sender, record.status
+            );
+            // A failed notice must not fail the bounce: SES would retry the
+            // whole event and send another notice on every attempt
+            if config.notify_bounced_senders {
+                match notify_original_sender(context, config, &original_sender, &record).await {
+                    Ok(notice_id) => record.notice_message_id = Some(notice_id),
+                    Err(e) => warn!(
+                        "Failed to notify {} of bounce {}: {}",
+                        original_sender, message_id, e
+                    ),
+                }
+            }
+            record.original_sender = Some(original_sender);
+        }
+        Err(e) => {
+            warn!("Ignoring bounce {} to {}: {}", message_id, return_path, e);
+            record.decode_error = Some(e.to_string());
+        }
+    }
+
+    let key = S3Key::try_from(format!("{}/{}.json", config.bounce_prefix, message_id))?;
+    let body = serde_json::to_vec_pretty(&record)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode bounce: {}", e)))?;
+    context
+        .store
+        .put_object(&config.email_bucket, &key, body)
+        .await?;
+
+    Ok(record)
+}
+
+async fn notify_original_sender(
+    context: &AppContext,
+    config: &Config,
+    original_sender: &str,
+    record: &BounceRecord,
+) -> Result<String, AwsError> {
+    let to = EmailAddress::try_from(original_sender.to_string())?;
+    let reply_to = EmailAddress::try_from(config.forwarder_email.clone())?;
+    // Never reveal where mail is forwarded to
+    let diagnostic = record
+        .diagnostic_code
+        .as_ref()
+        .map(|diagnostic| diagnostic.replace(&config.forward_to_email, "the recipient"));
+    let variables = Variables::from([
+        ("domain", config.forwarder_domain().to_string()),
+        ("status", record.status.clone().unwrap_or_default()),
+        ("diagnostic", diagnostic.unwrap_or_default()),
+    ]);
+    let message = Template::builtin(TemplateKind::BounceNotice).render(&variables)?;
+
+    send_email_via_ses(
+        &context.ses_client,
+        &config.forwarder_email,
+        &to,
+        &reply_to,
+        &message,
+    )
+    .await
+}
+
+/// Subject plus the first `Status` and `Diagnostic-Code` fields of a DSN
+fn delivery_status(raw_email: &[u8]) -> (Option<String>, Option<String>, Option<String>) {
+    let Ok(parsed) = parse_mail(raw_email) else {
+        return (None, None, None);
+    };
+    let subject = parsed.headers.get_first_value("Subject");
+
+    let mut status = None;
+    let mut diagnostic_code = None;
+    let mut parts = vec![&parsed];
+    while let Some(part) = parts.pop() {
+        parts.extend(part.subparts.iter());
+        if !part
+            .ctype
+            .mimetype
+            .eq_ignore_ascii_case("message/delivery-status")
+        {
+            continue;
+        }
+        let body = part.get_body_raw().unwrap_or_default();
+        // Per-message and per-recipient field groups are separated by blank lines
+        let body = String::from_utf8_lossy(&body).replace("\r\n", "\n");
+        for group in body.split("\n\n") {
+            let Ok((fields, _)) = parse_headers(group.trim_start().as_bytes()) else {
+                continue;
+            };
+            status = status.or_else(|| fields.get_first_value("Status"));
+            diagnostic_code = diagnostic_code.or_else(|| fields.get_first_value("Diagnostic-Code"));
+        }
+    }
+
+    (subject, status, diagnostic_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    const SECRET: &str = "test-secret";
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 14, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let address =
+            encode_return_path("fan@example.com", "jimmillerdrums.com", SECRET, now()).unwrap();
+        assert!(address.starts_with("SRS0="));
+        assert!(address.ends_with("=example.com=fan@jimmillerdrums.com"));
+        assert!(is_srs_address(&address));
+
+        let decoded = decode_return_path(&address, SECRET, 21, now()).unwrap();
+        assert_eq!(decoded, "fan@example.com");
+    }
+
+    #[test]
+    fn test_decode_tolerates_case_folding() {
+        let address =
+            encode_return_path("Fan@Example.com", "jimmillerdrums.com", SECRET, now()).unwrap();
+        let decoded = decode_return_path(&address.to_lowercase(), SECRET, 21, now()).unwrap();
+        assert_eq!(decoded, "fan@example.com");
+    }
+
+    #[test]
+    fn test_tampered_address_is_rejected() {
+        let address =
+            encode_return_path("fan@example.com", "jimmillerdrums.com", SECRET, now()).unwrap();
+        let forged = address.replace("=fan@", "=victim@");
+        assert!(matches!(
+            decode_return_path(&forged, SECRET, 21, now()),
+            Err(SrsError::BadHash(_))
+        ));
+        assert!(matches!(
+            decode_return_path(&address, "other-secret", 21, now()),
+            Err(SrsError::BadHash(_))
+        ));
+    }
+
+    #[test]
+    fn test_expired_address_is_rejected() {
+        let address =
+            encode_return_path("fan@example.com", "jimmillerdrums.com", SECRET, now()).unwrap();
+        let later = now() + Duration::days(30);
+        assert_eq!(
+            decode_return_path(&address, SECRET, 21, later),
+            Err(SrsError::Expired(30))
+        );
+    }
+
+    #[test]
+    fn test_nothing_to_encode() {
+        assert_eq!(
+            encode_return_path("", "jimmillerdrums.com", SECRET, now()),
+            None
+        );
+        let long = format!("{}@example.com", "a".repeat(60));
+        assert_eq!(
+            encode_return_path(&long, "jimmillerdrums.com", SECRET, now()),
+            None
+        );
+        assert!(!is_srs_address("info@jimmillerdrums.com"));
+    }
+
+    #[test]
+    fn test_original_envelope_sender() {
+        let email = b"Return-Path: <bounce-123@mailer.example.com>\r\nFrom: Fan <fan@example.com>\r\n\r\nBody";
+        assert_eq!(
+            original_envelope_sender(email).as_deref(),
+            Some("bounce-123@mailer.example.com")
+        );
+
+        let email = b"Return-Path: <>\r\nFrom: MAILER-DAEMON@example.com\r\n\r\nBody";
+        assert_eq!(original_envelope_sender(email), None);
+    }
+
+    #[test]
+    fn test_delivery_status_fields() {
+        let email = b"From: MAILER-DAEMON@gmail.com\r\nSubject: Delivery Status Notification (Failure)\r\nContent-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nAddress not found\r\n--b\r\nContent-Type: message/delivery-status\r\n\r\nReporting-MTA: dns; googlemail.com\r\n\r\nFinal-Recipient: rfc822; me@gmail.com\r\nAction: failed\r\nStatus: 5.1.1\r\nDiagnostic-Code: smtp; 550 5.1.1 The email account does not exist\r\n--b--";
+        let (subject, status, diagnostic) = delivery_status(email);
+        assert_eq!(
+            subject.as_deref(),
+            Some("Delivery Status Notification (Failure)")
+        );
+        assert_eq!(status.as_deref(), Some("5.1.1"));
+        assert_eq!(
+            diagnostic.as_deref(),
+            Some("smtp; 550 5.1.1 The email account does not exist")
+        );
+    }
+}
@@ -0,0 +1,849 @@
+use crate::domain::S3Key;
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{Tag, Tagging};
+use aws_sdk_s3::Client as S3Client;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Object not found: {0}")]
+    NotFound(String),
+    #[error("Storage backend error: {0}")]
+    Backend(String),
+}
+
+/// Object tags, ordered by key so every backend reports them identically
+pub type ObjectTags = BTreeMap<String, String>;
+
+/// User metadata of an object (`x-amz-meta-*` on S3); values must be ASCII
+pub type ObjectMetadata = BTreeMap<String, String>;
+
+/// Listing entry for a stored object
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: S3Key,
+    pub last_modified: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// Object storage used by the processor (S3 in production, in-memory in tests)
+#[async_trait]
+pub trait MailStore: Send + Sync {
+    async fn get_object(&self, bucket: &str, key: &S3Key) -> Result<Vec<u8>, StoreError>;
+
+    async fn put_object(&self, bucket: &str, key: &S3Key, body: Vec<u8>) -> Result<(), StoreError>;
+
+    /// Store an object with its content type and user metadata
+    async fn put_object_with_metadata(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        body: Vec<u8>,
+        content_type: &str,
+        metadata: &ObjectMetadata,
+    ) -> Result<(), StoreError>;
+
+    async fn delete_object(&self, bucket: &str, key: &S3Key) -> Result<(), StoreError>;
+
+    /// Copy an object (including its tags) within the same bucket
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source: &S3Key,
+        destination: &S3Key,
+    ) -> Result<(), StoreError>;
+
+    /// Replace the full tag set of an object
+    async fn put_object_tags(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        tags: &ObjectTags,
+    ) -> Result<(), StoreError>;
+
+    async fn get_object_tags(&self, bucket: &str, key: &S3Key) -> Result<ObjectTags, StoreError>;
+
+    /// List every object under `prefix`, sorted by key
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StoreError>;
+}
+
+#[async_trait]
+impl MailStore for S3Client {
+    async fn get_object(&self, bucket: &str, key: &S3Key) -> Result<Vec<u8>, StoreError> {
+        let response = self
+            .get_object()
+            .bucket(bucket)
+            .key(key.as_str())
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(service_error) if service_error.is_no_such_key() => {
+                    StoreError::NotFound(key.to_string())
+                }
+                _ => StoreError::Backend(e.to_string()),
+            })?;
+
+        let bytes = response
+            .body
+            .collect()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?
+            .into_bytes()
+            .to_vec();
+
+        Ok(bytes)
+    }
+
+    async fn put_object(&self, bucket: &str, key: &S3Key, body: Vec<u8>) -> Result<(), StoreError> {
+        self.put_object()
+            .bucket(bucket)
+            .key(key.as_str())
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn put_object_with_metadata(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        body: Vec<u8>,
+        content_type: &str,
+        metadata: &ObjectMetadata,
+    ) -> Result<(), StoreError> {
+        self.put_object()
+            .bucket(bucket)
+            .key(key.as_str())
+            .content_type(content_type)
+            .set_metadata(Some(metadata.clone().into_iter().collect()))
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &S3Key) -> Result<(), StoreError> {
+        self.delete_object()
+            .bucket(bucket)
+            .key(key.as_str())
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source: &S3Key,
+        destination: &S3Key,
+    ) -> Result<(), StoreError> {
+        info!("Copying s3://{}/{} to {}", bucket, source, destination);
+
+        // Keys are "<prefix>/<SES message id>", which never need URL encoding
+        self.copy_object()
+            .bucket(bucket)
+            .copy_source(format!("{}/{}", bucket, source))
+            .key(destination.as_str())
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn put_object_tags(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        tags: &ObjectTags,
+    ) -> Result<(), StoreError> {
+        let tag_set = tags
+            .iter()
+            .map(|(k, v)| Tag::builder().key(k).value(v).build())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        let tagging = Tagging::builder()
+            .set_tag_set(Some(tag_set))
+            .build()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        self.put_object_tagging()
+            .bucket(bucket)
+            .key(key.as_str())
+            .tagging(tagging)
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get_object_tags(&self, bucket: &str, key: &S3Key) -> Result<ObjectTags, StoreError> {
+        let response = self
+            .get_object_tagging()
+            .bucket(bucket)
+            .key(key.as_str())
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        Ok(response
+            .tag_set()
+            .iter()
+            .map(|tag| (tag.key().to_string(), tag.value().to_string()))
+            .collect())
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StoreError> {
+        let mut pages = self
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| StoreError::Backend(e.to_string()))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else { continue };
+                let last_modified = object
+                    .last_modified()
+                    .and_then(|t| DateTime::from_timestamp(t.secs(), t.subsec_nanos()))
+                    .unwrap_or_default();
+                objects.push(StoredObject {
+                    key: S3Key::try_from(key.to_string())
+                        .map_err(|e| StoreError::Backend(e.to_string()))?,
+                    last_modified,
+                    size: object.size().unwrap_or_default().max(0) as u64,
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct MemoryObject {
+    body: Vec<u8>,
+    tags: ObjectTags,
+    content_type: Option<String>,
+    metadata: ObjectMetadata,
+    last_modified: DateTime<Utc>,
+}
+
+/// In-memory store for tests and local tooling
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    objects: Mutex<HashMap<(String, String), MemoryObject>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed an object without going through the async trait
+    pub fn insert(&self, bucket: &str, key: &str, body: impl Into<Vec<u8>>) {
+        self.insert_at(bucket, key, body, Utc::now());
+    }
+
+    /// Seed an object with an explicit last-modified time
+    pub fn insert_at(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: impl Into<Vec<u8>>,
+        last_modified: DateTime<Utc>,
+    ) {
+        self.lock().insert(
+            (bucket.to_string(), key.to_string()),
+            MemoryObject {
+                body: body.into(),
+                last_modified,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn contains(&self, bucket: &str, key: &str) -> bool {
+        self.lock()
+            .contains_key(&(bucket.to_string(), key.to_string()))
+    }
+
+    pub fn tags(&self, bucket: &str, key: &str) -> Option<ObjectTags> {
+        self.lock()
+            .get(&(bucket.to_string(), key.to_string()))
+            .map(|object| object.tags.clone())
+    }
+
+    pub fn content_type(&self, bucket: &str, key: &str) -> Option<String> {
+        self.lock()
+            .get(&(bucket.to_string(), key.to_string()))
+            .and_then(|object| object.content_type.clone())
+    }
+
+    pub fn metadata(&self, bucket: &str, key: &str) -> Option<ObjectMetadata> {
+        self.lock()
+            .get(&(bucket.to_string(), key.to_string()))
+            .map(|object| object.metadata.clone())
+    }
+
+    /// Replace an object's tags without going through the async trait
+    pub fn set_tags(&self, bucket: &str, key: &str, tags: ObjectTags) {
+        if let Some(object) = self.lock().get_mut(&(bucket.to_string(), key.to_string())) {
+            object.tags = tags;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, String), MemoryObject>> {
+        // A poisoned lock only means another test thread panicked; the data is still usable
+        self.objects.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl MailStore for MemoryStore {
+    async fn get_object(&self, bucket: &str, key: &S3Key) -> Result<Vec<u8>, StoreError> {
+        self.lock()
+            .get(&(bucket.to_string(), key.to_string()))
+            .map(|object| object.body.clone())
+            .ok_or_else(|| StoreError::NotFound(key.to_string()))
+    }
+
+    async fn put_object(&self, bucket: &str, key: &S3Key, body: Vec<u8>) -> Result<(), StoreError> {
+        self.insert(bucket, key.as_str(), body);
+        Ok(())
+    }
+
+    async fn put_object_with_metadata(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        body: Vec<u8>,
+        content_type: &str,
+        metadata: &ObjectMetadata,
+    ) -> Result<(), StoreError> {
+        self.lock().insert(
+            (bucket.to_string(), key.to_string()),
+            MemoryObject {
+                body,
+                content_type: Some(content_type.to_string()),
+                metadata: metadata.clone(),
+                last_modified: Utc::now(),
+                ..Default::default()
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &S3Key) -> Result<(), StoreError> {
+        self.lock().remove(&(bucket.to_string(), key.to_string()));
+        Ok(())
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source: &S3Key,
+        destination: &S3Key,
+    ) -> Result<(), StoreError> {
+        let mut objects = self.lock();
+        let object = objects
+            .get(&(bucket.to_string(), source.to_string()))
+            .cloned()
+            .ok_or_else(|| StoreError::NotFound(source.to_string()))?;
+        objects.insert((bucket.to_string(), destination.to_string()), object);
+        Ok(())
+    }
+
+    async fn put_object_tags(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        tags: &ObjectTags,
+    ) -> Result<(), StoreError> {
+        let mut objects = self.lock();
+        let object = objects
+            .get_mut(&(bucket.to_string(), key.to_string()))
+            .ok_or_else(|| StoreError::NotFound(key.to_string()))?;
+        object.tags = tags.clone();
+        Ok(())
+    }
+
+    async fn get_object_tags(&self, bucket: &str, key: &S3Key) -> Result<ObjectTags, StoreError> {
+        self.tags(bucket, key.as_str())
+            .ok_or_else(|| StoreError::NotFound(key.to_string()))
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StoreError> {
+        let mut objects = self
+            .lock()
+            .iter()
+            .filter(|((b, k), _)| b == bucket && k.starts_with(prefix))
+            .map(|((_, k), object)| {
+                Ok(StoredObject {
+                    key: S3Key::try_from(k.clone())
+                        .map_err(|e| StoreError::Backend(e.to_string()))?,
+                    last_modified: object.last_modified,
+                    size: object.body.len() as u64,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        objects.sort_by(|a, b| a.key.as_str().cmp(b.key.as_str()));
+        Ok(objects)
+    }
+}
+
+/// Directory of an `FsStore` holding tags and metadata
+const FS_META_DIR: &str = ".meta";
+
+/// Tags, content type and metadata of an object in an `FsStore`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FsSidecar {
+    #[serde(default)]
+    tags: ObjectTags,
+    #[serde(default)]
+    content_type: Option<String>,
+    #[serde(default)]
+    metadata: ObjectMetadata,
+}
+
+/// Store on the local filesystem, laid out like the bucket: the object
+/// `incoming/abc` of `bucket` is the file `<root>/bucket/incoming/abc`. Tags
+/// and metadata live beside the tree in `<root>/.meta/`. Meant for local
+/// tooling and copies synced down with `aws s3 sync`; files are written in
+/// place, without S3's atomic replace.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    root: PathBuf,
+}
+
+impl FsStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Relative path of a key, refusing anything that would leave the bucket
+    fn relative_path(name: &str) -> Result<PathBuf, StoreError> {
+        let mut path = PathBuf::new();
+        for component in name.split('/') {
+            if component.is_empty() || component == "." || component == ".." {
+                return Err(StoreError::Backend(format!("Invalid key: {}", name)));
+            }
+            path.push(component);
+        }
+        Ok(path)
+    }
+
+    fn object_path(&self, bucket: &str, key: &S3Key) -> Result<PathBuf, StoreError> {
+        Ok(self
+            .root
+            .join(Self::relative_path(bucket)?)
+            .join(Self::relative_path(key.as_str())?))
+    }
+
+    fn sidecar_path(&self, bucket: &str, key: &S3Key) -> Result<PathBuf, StoreError> {
+        let mut path = self
+            .root
+            .join(FS_META_DIR)
+            .join(Self::relative_path(bucket)?)
+            .join(Self::relative_path(key.as_str())?)
+            .into_os_string();
+        path.push(".json");
+        Ok(path.into())
+    }
+
+    fn read_sidecar(&self, bucket: &str, key: &S3Key) -> Result<FsSidecar, StoreError> {
+        match fs::read(self.sidecar_path(bucket, key)?) {
+            Ok(body) => serde_json::from_slice(&body)
+                .map_err(|e| StoreError::Backend(format!("Invalid metadata for {}: {}", key, e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(FsSidecar::default()),
+            Err(e) => Err(StoreError::Backend(e.to_string())),
+        }
+    }
+
+    /// Replace the sidecar; an empty one is removed rather than written
+    fn write_sidecar(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        sidecar: &FsSidecar,
+    ) -> Result<(), StoreError> {
+        let path = self.sidecar_path(bucket, key)?;
+        if sidecar.tags.is_empty() && sidecar.content_type.is_none() && sidecar.metadata.is_empty()
+        {
+            return remove_file(&path);
+        }
+        let body = serde_json::to_vec_pretty(sidecar)
+            .map_err(|e| StoreError::Backend(format!("Failed to encode metadata: {}", e)))?;
+        write_file(&path, &body)
+    }
+
+    fn write_object(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        body: &[u8],
+        sidecar: &FsSidecar,
+    ) -> Result<(), StoreError> {
+        write_file(&self.object_path(bucket, key)?, body)?;
+        self.write_sidecar(bucket, key, sidecar)
+    }
+}
+
+fn write_file(path: &Path, body: &[u8]) -> Result<(), StoreError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| StoreError::Backend(e.to_string()))?;
+    }
+    fs::write(path, body).map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+fn remove_file(path: &Path) -> Result<(), StoreError> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(StoreError::Backend(e.to_string())),
+    }
+}
+
+/// Every file under `dir`, as `/`-separated paths relative to `base`
+fn walk_files(base: &Path, dir: &Path, files: &mut Vec<(String, fs::Metadata)>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if metadata.is_dir() {
+            walk_files(base, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(base) {
+            let name: Vec<String> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push((name.join("/"), metadata));
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl MailStore for FsStore {
+    async fn get_object(&self, bucket: &str, key: &S3Key) -> Result<Vec<u8>, StoreError> {
+        match fs::read(self.object_path(bucket, key)?) {
+            Ok(body) => Ok(body),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(StoreError::NotFound(key.to_string()))
+            }
+            Err(e) => Err(StoreError::Backend(e.to_string())),
+        }
+    }
+
+    async fn put_object(&self, bucket: &str, key: &S3Key, body: Vec<u8>) -> Result<(), StoreError> {
+        self.write_object(bucket, key, &body, &FsSidecar::default())
+    }
+
+    async fn put_object_with_metadata(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        body: Vec<u8>,
+        content_type: &str,
+        metadata: &ObjectMetadata,
+    ) -> Result<(), StoreError> {
+        let sidecar = FsSidecar {
+            tags: ObjectTags::new(),
+            content_type: Some(content_type.to_string()),
+            metadata: metadata.clone(),
+        };
+        self.write_object(bucket, key, &body, &sidecar)
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &S3Key) -> Result<(), StoreError> {
+        remove_file(&self.object_path(bucket, key)?)?;
+        remove_file(&self.sidecar_path(bucket, key)?)
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source: &S3Key,
+        destination: &S3Key,
+    ) -> Result<(), StoreError> {
+        let body = self.get_object(bucket, source).await?;
+        let sidecar = self.read_sidecar(bucket, source)?;
+        self.write_object(bucket, destination, &body, &sidecar)
+    }
+
+    async fn put_object_tags(
+        &self,
+        bucket: &str,
+        key: &S3Key,
+        tags: &ObjectTags,
+    ) -> Result<(), StoreError> {
+        if !self.object_path(bucket, key)?.is_file() {
+            return Err(StoreError::NotFound(key.to_string()));
+        }
+        let mut sidecar = self.read_sidecar(bucket, key)?;
+        sidecar.tags = tags.clone();
+        self.write_sidecar(bucket, key, &sidecar)
+    }
+
+    async fn get_object_tags(&self, bucket: &str, key: &S3Key) -> Result<ObjectTags, StoreError> {
+        if !self.object_path(bucket, key)?.is_file() {
+            return Err(StoreError::NotFound(key.to_string()));
+        }
+        Ok(self.read_sidecar(bucket, key)?.tags)
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StoreError> {
+        let base = self.root.join(Self::relative_path(bucket)?);
+        // Only walk the directory the prefix names; the rest may be partial
+        let dir = match prefix.rsplit_once('/') {
+            Some((dir, _)) if !dir.is_empty() => base.join(Self::relative_path(dir)?),
+            _ => base.clone(),
+        };
+
+        let mut files = Vec::new();
+        walk_files(&base, &dir, &mut files).map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        let mut objects = files
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, metadata)| {
+                Ok(StoredObject {
+                    key: S3Key::try_from(name).map_err(|e| StoreError::Backend(e.to_string()))?,
+                    last_modified: metadata
+                        .modified()
+                        .map(DateTime::<Utc>::from)
+                        .unwrap_or_default(),
+                    size: metadata.len(),
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        objects.sort_by(|a, b| a.key.as_str().cmp(b.key.as_str()));
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(value: &str) -> S3Key {
+        S3Key::try_from(value.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_get_missing_object() {
+        let store = MemoryStore::new();
+        let result = store.get_object("bucket", &key("incoming/missing")).await;
+        assert!(matches!(result, Err(StoreError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_copy_keeps_tags() {
+        let store = MemoryStore::new();
+        store.insert("bucket", "incoming/abc", "raw email");
+
+        let mut tags = ObjectTags::new();
+        tags.insert("status".to_string(), "forwarded".to_string());
+        store
+            .put_object_tags("bucket", &key("incoming/abc"), &tags)
+            .await
+            .unwrap();
+        store
+            .copy_object("bucket", &key("incoming/abc"), &key("processed/abc"))
+            .await
+            .unwrap();
+
+        assert_eq!(store.tags("bucket", "processed/abc"), Some(tags));
+        assert!(store.contains("bucket", "incoming/abc"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_list_filters_by_prefix() {
+        let store = MemoryStore::new();
+        store.insert("bucket", "incoming/b", "two");
+        store.insert("bucket", "incoming/a", "one");
+        store.insert("bucket", "processed/a", "one");
+        store.insert("other", "incoming/c", "three");
+
+        let keys: Vec<String> = store
+            .list_objects("bucket", "incoming/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key.to_string())
+            .collect();
+        assert_eq!(keys, vec!["incoming/a", "incoming/b"]);
+    }
+
+    /// A fresh directory for one test, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("fs-store-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fs_store_objects_and_tags() {
+        let dir = TempDir::new("objects");
+        let store = FsStore::new(&dir.0);
+
+        store
+            .put_object("bucket", &key("incoming/abc"), b"raw email".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.0.join("bucket/incoming/abc")).unwrap(),
+            b"raw email"
+        );
+        assert_eq!(
+            store
+                .get_object("bucket", &key("incoming/abc"))
+                .await
+                .unwrap(),
+            b"raw email"
+        );
+        assert!(matches!(
+            store.get_object("bucket", &key("incoming/missing")).await,
+            Err(StoreError::NotFound(_))
+        ));
+        assert!(store
+            .get_object("bucket", &key("incoming/../../etc/passwd"))
+            .await
+            .is_err());
+
+        let mut tags = ObjectTags::new();
+        tags.insert("status".to_string(), "forwarded".to_string());
+        store
+            .put_object_tags("bucket", &key("incoming/abc"), &tags)
+            .await
+            .unwrap();
+        store
+            .copy_object("bucket", &key("incoming/abc"), &key("processed/abc"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .get_object_tags("bucket", &key("processed/abc"))
+                .await
+                .unwrap(),
+            tags
+        );
+
+        // Writing the object again replaces its tags, as on S3
+        store
+            .put_object("bucket", &key("incoming/abc"), b"again".to_vec())
+            .await
+            .unwrap();
+        assert!(store
+            .get_object_tags("bucket", &key("incoming/abc"))
+            .await
+            .unwrap()
+            .is_empty());
+
+        store
+            .delete_object("bucket", &key("processed/abc"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.get_object_tags("bucket", &key("processed/abc")).await,
+            Err(StoreError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fs_store_list_filters_by_prefix() {
+        let dir = TempDir::new("list");
+        let store = FsStore::new(&dir.0);
+        for name in [
+            "incoming/b",
+            "incoming/a",
+            "index/date=2026-06-01/a.jsonl",
+            "processed/a",
+        ] {
+            store
+                .put_object_with_metadata(
+                    "bucket",
+                    &key(name),
+                    b"one".to_vec(),
+                    "text/plain",
+                    &ObjectMetadata::new(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let keys = |objects: Vec<StoredObject>| -> Vec<String> {
+            objects
+                .into_iter()
+                .map(|object| object.key.to_string())
+                .collect()
+        };
+        assert_eq!(
+            keys(store.list_objects("bucket", "incoming/").await.unwrap()),
+            vec!["incoming/a", "incoming/b"]
+        );
+        assert_eq!(
+            keys(store.list_objects("bucket", "index/date=").await.unwrap()),
+            vec!["index/date=2026-06-01/a.jsonl"]
+        );
+        assert_eq!(store.list_objects("bucket", "").await.unwrap().len(), 4);
+        assert!(store
+            .list_objects("other", "incoming/")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
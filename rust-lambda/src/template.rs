@@ -0,0 +1,471 @@
+use crate::domain::{DomainError, EmailBody, Subject};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("Template {template}: missing 'Subject:' line")]
+    MissingSubject { template: String },
+    #[error("Template {template}: {message}")]
+    Syntax { template: String, message: String },
+    #[error("Template {template}: unknown variable '{name}'")]
+    UnknownVariable { template: String, name: String },
+}
+
+/// Values substituted into a template, by variable name
+pub type Variables = BTreeMap<&'static str, String>;
+
+/// The messages the processor writes itself, each with the variables its
+/// templates may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKind {
+    AutoReply,
+    HeldNotice,
+    BounceNotice,
+    UnknownRecipientReport,
+}
+
+impl TemplateKind {
+    pub const ALL: [TemplateKind; 4] = [
+        TemplateKind::AutoReply,
+        TemplateKind::HeldNotice,
+        TemplateKind::BounceNotice,
+        TemplateKind::UnknownRecipientReport,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemplateKind::AutoReply => "auto_reply",
+            TemplateKind::HeldNotice => "held_notice",
+            TemplateKind::BounceNotice => "bounce_notice",
+            TemplateKind::UnknownRecipientReport => "unknown_recipient_report",
+        }
+    }
+
+    pub fn variables(&self) -> &'static [&'static str] {
+        match self {
+            TemplateKind::AutoReply => &["sender_name", "subject", "since", "until"],
+            TemplateKind::HeldNotice => &[
+                "sender",
+                "scope",
+                "count",
+                "messages",
+                "limit",
+                "window_minutes",
+                "held_prefix",
+                "message_ids",
+            ],
+            TemplateKind::BounceNotice => &["domain", "status", "diagnostic"],
+            TemplateKind::UnknownRecipientReport => {
+                &["domain", "since", "until", "total", "addresses"]
+            }
+        }
+    }
+
+    /// Text (with its `Subject:` line) and HTML sources shipped in `templates/`
+    fn builtin_sources(&self) -> (&'static str, &'static str) {
+        match self {
+            TemplateKind::AutoReply => (
+                include_str!("../templates/auto_reply.txt"),
+                include_str!("../templates/auto_reply.html"),
+            ),
+            TemplateKind::HeldNotice => (
+                include_str!("../templates/held_notice.txt"),
+                include_str!("../templates/held_notice.html"),
+            ),
+            TemplateKind::BounceNotice => (
+                include_str!("../templates/bounce_notice.txt"),
+                include_str!("../templates/bounce_notice.html"),
+            ),
+            TemplateKind::UnknownRecipientReport => (
+                include_str!("../templates/unknown_recipient_report.txt"),
+                include_str!("../templates/unknown_recipient_report.html"),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Variable(String),
+    /// `{{#if name}}...{{/if}}`: rendered when the variable is non-empty
+    If(String, Vec<Segment>),
+}
+
+/// A message rendered from a template. SES sends it as
+/// `multipart/alternative` when it has an HTML part.
+#[derive(Debug, Clone)]
+pub struct RenderedMessage {
+    pub subject: Subject,
+    pub text: EmailBody,
+    pub html: Option<String>,
+    /// Extra headers, e.g. `Auto-Submitted`
+    pub headers: Vec<(String, String)>,
+}
+
+/// A compiled template. Sources use `{{name}}` for variables and
+/// `{{#if name}}...{{/if}}` for optional sections; a block tag alone on its
+/// line takes the line with it. Variables are HTML-escaped in the HTML part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    kind: TemplateKind,
+    subject: Vec<Segment>,
+    text: Vec<Segment>,
+    html: Option<Vec<Segment>>,
+}
+
+impl Template {
+    /// Compile a template, checking its syntax and that it only uses the
+    /// variables `kind` provides
+    pub fn parse(
+        kind: TemplateKind,
+        subject: &str,
+        text: &str,
+        html: Option<&str>,
+    ) -> Result<Self, TemplateError> {
+        let compile = |source: &str| {
+            let segments = parse_segments(kind, source)?;
+            check_variables(kind, &segments)?;
+            Ok(segments)
+        };
+
+        Ok(Self {
+            kind,
+            subject: compile(subject)?,
+            text: compile(text)?,
+            html: html.map(compile).transpose()?,
+        })
+    }
+
+    /// Compile a text source whose first line is `Subject: ...`, followed by
+    /// a blank line and the body
+    pub fn parse_message(
+        kind: TemplateKind,
+        text: &str,
+        html: Option<&str>,
+    ) -> Result<Self, TemplateError> {
+        let missing = || TemplateError::MissingSubject {
+            template: kind.name().to_string(),
+        };
+        let (subject_line, body) = text.split_once('\n').ok_or_else(missing)?;
+        let subject = subject_line
+            .strip_prefix("Subject:")
+            .ok_or_else(missing)?
+            .trim();
+        let body = body.strip_prefix("\r\n").or(body.strip_prefix('\n'));
+
+        Self::parse(kind, subject, body.unwrap_or(""), html)
+    }
+
+    /// The template shipped in `templates/` for `kind`. These are checked by
+    /// the tests, so failing to compile one is a bug.
+    pub fn builtin(kind: TemplateKind) -> Self {
+        let (text, html) = kind.builtin_sources();
+        Self::parse_message(kind, text, Some(html))
+            .unwrap_or_else(|e| panic!("built-in template is invalid: {}", e))
+    }
+
+    pub fn kind(&self) -> TemplateKind {
+        self.kind
+    }
+
+    /// Render with `variables`; unset variables render as empty
+    pub fn render(&self, variables: &Variables) -> Result<RenderedMessage, DomainError> {
+        let subject = render_segments(&self.subject, variables, false);
+        // Subjects are a single line whatever the variables contain
+        let subject = subject.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        Ok(RenderedMessage {
+            subject: Subject::try_from(subject)?,
+            text: EmailBody::try_from(render_segments(&self.text, variables, false))?,
+            html: self
+                .html
+                .as_ref()
+                .map(|html| render_segments(html, variables, true)),
+            headers: Vec::new(),
+        })
+    }
+}
+
+fn parse_segments(kind: TemplateKind, source: &str) -> Result<Vec<Segment>, TemplateError> {
+    let error = |message: String| TemplateError::Syntax {
+        template: kind.name().to_string(),
+        message,
+    };
+
+    // Stack of open sections: (variable, segments so far); the root has no variable
+    let mut stack: Vec<(Option<String>, Vec<Segment>)> = vec![(None, Vec::new())];
+    let mut rest = source;
+    // Whether only whitespace precedes the next tag on its line
+    let mut line_blank = true;
+
+    while let Some(start) = rest.find("{{") {
+        let (before, after_open) = rest.split_at(start);
+        let end = after_open
+            .find("}}")
+            .ok_or_else(|| error("unclosed '{{'".to_string()))?;
+        let tag = after_open[2..end].trim();
+        let mut after = &after_open[end + 2..];
+        let mut before = before.to_string();
+
+        let line_start = before.rfind('\n').map(|i| i + 1);
+        line_blank = before[line_start.unwrap_or(0)..].trim().is_empty()
+            && (line_blank || line_start.is_some());
+
+        let is_block = tag.starts_with('#') || tag.starts_with('/');
+        if is_block && line_blank {
+            // A block tag alone on its line removes the whole line
+            if let Some(line_end) = after.find('\n') {
+                if after[..line_end].trim().is_empty() {
+                    before.truncate(line_start.unwrap_or(0));
+                    after = &after[line_end + 1..];
+                }
+            }
+        } else if !is_block {
+            line_blank = false;
+        }
+
+        let segments = &mut stack.last_mut().expect("root section").1;
+        if !before.is_empty() {
+            segments.push(Segment::Text(before));
+        }
+
+        if let Some(name) = tag.strip_prefix("#if ") {
+            stack.push((Some(variable_name(name.trim()).map_err(error)?), Vec::new()));
+        } else if tag == "/if" {
+            let (name, body) = stack.pop().expect("root section");
+            let name = name.ok_or_else(|| error("'{{/if}}' without '{{#if}}'".to_string()))?;
+            stack
+                .last_mut()
+                .ok_or_else(|| error("'{{/if}}' without '{{#if}}'".to_string()))?
+                .1
+                .push(Segment::If(name, body));
+        } else if is_block {
+            return Err(error(format!("unknown block '{{{{{}}}}}'", tag)));
+        } else {
+            segments.push(Segment::Variable(variable_name(tag).map_err(error)?));
+        }
+
+        rest = after;
+    }
+
+    if stack.len() > 1 {
+        return Err(error("unclosed '{{#if}}'".to_string()));
+    }
+    let (_, mut segments) = stack.pop().expect("root section");
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+fn variable_name(name: &str) -> Result<String, String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid variable name '{}'", name))
+    }
+}
+
+fn check_variables(kind: TemplateKind, segments: &[Segment]) -> Result<(), TemplateError> {
+    for segment in segments {
+        let (name, body) = match segment {
+            Segment::Text(_) => continue,
+            Segment::Variable(name) => (name, None),
+            Segment::If(name, body) => (name, Some(body)),
+        };
+        if !kind.variables().contains(&name.as_str()) {
+            return Err(TemplateError::UnknownVariable {
+                template: kind.name().to_string(),
+                name: name.clone(),
+            });
+        }
+        if let Some(body) = body {
+            check_variables(kind, body)?;
+        }
+    }
+    Ok(())
+}
+
+fn render_segments(segments: &[Segment], variables: &Variables, html: bool) -> String {
+    let mut output = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => output.push_str(text),
+            Segment::Variable(name) => {
+                let value = variables.get(name.as_str()).map(String::as_str);
+                let value = value.unwrap_or_default();
+                if html {
+                    output.push_str(&escape_html(value));
+                } else {
+                    output.push_str(value);
+                }
+            }
+            Segment::If(name, body) => {
+                if variables
+                    .get(name.as_str())
+                    .is_some_and(|value| !value.trim().is_empty())
+                {
+                    output.push_str(&render_segments(body, variables, html));
+                }
+            }
+        }
+    }
+    output
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(message: &RenderedMessage) -> String {
+        format!(
+            "Subject: {}\n\n--- text/plain ---\n{}\n--- text/html ---\n{}",
+            message.subject,
+            message.text,
+            message.html.as_deref().unwrap_or_default()
+        )
+    }
+
+    #[test]
+    fn test_builtin_templates_compile() {
+        for kind in TemplateKind::ALL {
+            let (text, html) = kind.builtin_sources();
+            assert!(
+                Template::parse_message(kind, text, Some(html)).is_ok(),
+                "{}",
+                kind.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_auto_reply_snapshot() {
+        let variables = Variables::from([
+            ("sender_name", "Pat <Planner>".to_string()),
+            ("subject", "Wedding on June 6".to_string()),
+            ("until", "July 15, 2026".to_string()),
+        ]);
+        let message = Template::builtin(TemplateKind::AutoReply)
+            .render(&variables)
+            .unwrap();
+        insta::assert_snapshot!(snapshot(&message));
+    }
+
+    #[test]
+    fn test_auto_reply_without_dates_snapshot() {
+        let variables = Variables::from([("sender_name", "Pat".to_string())]);
+        let message = Template::builtin(TemplateKind::AutoReply)
+            .render(&variables)
+            .unwrap();
+        insta::assert_snapshot!(snapshot(&message));
+    }
+
+    #[test]
+    fn test_held_notice_snapshot() {
+        let variables = Variables::from([
+            ("sender", "form@venue.example".to_string()),
+            ("scope", "address".to_string()),
+            ("count", "2".to_string()),
+            ("messages", "messages".to_string()),
+            ("limit", "10".to_string()),
+            ("window_minutes", "60".to_string()),
+            ("held_prefix", "held".to_string()),
+            ("message_ids", "form11\nform12".to_string()),
+        ]);
+        let message = Template::builtin(TemplateKind::HeldNotice)
+            .render(&variables)
+            .unwrap();
+        insta::assert_snapshot!(snapshot(&message));
+    }
+
+    #[test]
+    fn test_bounce_notice_snapshot() {
+        let variables = Variables::from([
+            ("domain", "jimmillerdrums.com".to_string()),
+            ("status", "5.1.1".to_string()),
+        ]);
+        let message = Template::builtin(TemplateKind::BounceNotice)
+            .render(&variables)
+            .unwrap();
+        insta::assert_snapshot!(snapshot(&message));
+    }
+
+    #[test]
+    fn test_unknown_recipient_report_snapshot() {
+        let variables = Variables::from([
+            ("domain", "jimmillerdrums.com".to_string()),
+            ("since", "2026-10-12".to_string()),
+            ("until", "2026-10-18".to_string()),
+            ("total", "42".to_string()),
+            (
+                "addresses",
+                "    30  admin@jimmillerdrums.com\n    12  sales@jimmillerdrums.com".to_string(),
+            ),
+        ]);
+        let message = Template::builtin(TemplateKind::UnknownRecipientReport)
+            .render(&variables)
+            .unwrap();
+        insta::assert_snapshot!(snapshot(&message));
+    }
+
+    #[test]
+    fn test_subject_is_one_line() {
+        let template =
+            Template::parse(TemplateKind::AutoReply, "Auto: {{subject}}", "", None).unwrap();
+        let variables = Variables::from([("subject", "Two\r\n lines".to_string())]);
+        assert_eq!(
+            template.render(&variables).unwrap().subject.as_str(),
+            "Auto: Two lines"
+        );
+    }
+
+    #[test]
+    fn test_invalid_templates_are_rejected() {
+        let parse = |text: &str| Template::parse(TemplateKind::AutoReply, "Hi", text, None);
+
+        assert_eq!(
+            parse("Hello {{name}}"),
+            Err(TemplateError::UnknownVariable {
+                template: "auto_reply".to_string(),
+                name: "name".to_string()
+            })
+        );
+        assert!(matches!(
+            parse("{{#if until}}Away"),
+            Err(TemplateError::Syntax { .. })
+        ));
+        assert!(matches!(
+            parse("{{/if}}"),
+            Err(TemplateError::Syntax { .. })
+        ));
+        assert!(matches!(
+            parse("Hello {{sender_name"),
+            Err(TemplateError::Syntax { .. })
+        ));
+        assert!(matches!(
+            Template::parse_message(TemplateKind::AutoReply, "Hello", None),
+            Err(TemplateError::MissingSubject { .. })
+        ));
+    }
+}
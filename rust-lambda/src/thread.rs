@@ -0,0 +1,376 @@
+use crate::aws::{AppContext, AwsError};
+use crate::config::Config;
+use crate::disposition::incoming_key;
+use crate::domain::{MessageId, S3Key};
+use crate::email::extract_email_address;
+use crate::mbox::{mboxrd_entry, message_date};
+use crate::store::StoreError;
+use chrono::{DateTime, Utc};
+use mailparse::{parse_headers, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+/// Hex characters in a thread id
+const THREAD_ID_LEN: usize = 16;
+
+/// The threading headers of a message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadHeaders {
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    /// Ancestors named in `References`, oldest first
+    pub references: Vec<String>,
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub date: Option<DateTime<Utc>>,
+}
+
+impl ThreadHeaders {
+    pub fn parse(raw_email: &[u8]) -> Self {
+        let Ok((headers, _)) = parse_headers(raw_email) else {
+            return Self::default();
+        };
+        let ids = |name: &str| {
+            headers
+                .get_first_value(name)
+                .map(|value| message_ids(&value))
+                .unwrap_or_default()
+        };
+
+        Self {
+            message_id: ids("Message-ID").into_iter().next(),
+            in_reply_to: ids("In-Reply-To").into_iter().next(),
+            references: ids("References"),
+            subject: headers
+                .get_first_value("Subject")
+                .map(|subject| subject.trim().to_string()),
+            from: headers
+                .get_first_value("From")
+                .and_then(|from| extract_email_address(&from).ok()),
+            date: message_date(raw_email),
+        }
+    }
+
+    /// Messages this one follows, most direct first
+    fn ancestors(&self) -> Vec<&String> {
+        let mut ancestors: Vec<&String> = self.in_reply_to.iter().collect();
+        for id in self.references.iter().rev() {
+            if !ancestors.contains(&id) {
+                ancestors.push(id);
+            }
+        }
+        ancestors
+    }
+
+    /// Message the conversation started with: the first `References` entry,
+    /// else `In-Reply-To`, else the message itself
+    fn root(&self) -> Option<&String> {
+        self.references
+            .first()
+            .or(self.in_reply_to.as_ref())
+            .or(self.message_id.as_ref())
+    }
+}
+
+/// Message ids in a header value, as `<id>`. Bare ids are accepted from
+/// clients that leave the brackets off.
+pub fn message_ids(value: &str) -> Vec<String> {
+    if !value.contains('<') {
+        return value
+            .split_whitespace()
+            .filter(|id| id.contains('@'))
+            .map(|id| format!("<{}>", id))
+            .collect();
+    }
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('>'))
+        .map(|(id, _)| id.trim())
+        .filter(|id| !id.is_empty())
+        .map(|id| format!("<{}>", id))
+        .collect()
+}
+
+fn short_hash(value: &str) -> String {
+    Sha256::digest(value.as_bytes())[..THREAD_ID_LEN / 2]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Thread id for a conversation starting at `root`
+pub fn thread_id(root: &str) -> String {
+    short_hash(root)
+}
+
+/// One message in a thread
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadMessage {
+    /// Id the raw message is stored under in the bucket
+    pub stored_id: String,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub from: Option<String>,
+    /// From the `Date` header, else when the message was threaded
+    pub date: DateTime<Utc>,
+}
+
+/// A conversation, kept under `<thread prefix>/threads/<thread id>.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadRecord {
+    pub thread_id: String,
+    /// Message-ID the conversation started with, when known
+    pub root: Option<String>,
+    /// Subject of the first message threaded
+    pub subject: Option<String>,
+    /// Oldest first
+    pub messages: Vec<ThreadMessage>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Which thread a Message-ID belongs to, kept under
+/// `<thread prefix>/messages/<hash of the id>.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ThreadLink {
+    message_id: String,
+    thread_id: String,
+}
+
+fn store_key(path: String) -> Result<S3Key, StoreError> {
+    S3Key::try_from(path).map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+fn thread_key(prefix: &str, thread_id: &str) -> Result<S3Key, StoreError> {
+    store_key(format!("{}/threads/{}.json", prefix, thread_id))
+}
+
+/// Message-IDs hold characters that do not belong in keys, so links are
+/// named by a hash of the id
+fn link_key(prefix: &str, message_id: &str) -> Result<S3Key, StoreError> {
+    store_key(format!(
+        "{}/messages/{}.json",
+        prefix,
+        short_hash(message_id)
+    ))
+}
+
+async fn load_json<T: for<'de> Deserialize<'de>>(
+    context: &AppContext,
+    config: &Config,
+    key: &S3Key,
+) -> Result<Option<T>, AwsError> {
+    match context.store.get_object(&config.email_bucket, key).await {
+        Ok(body) => Ok(Some(serde_json::from_slice(&body).map_err(|e| {
+            StoreError::Backend(format!("Invalid thread record {}: {}", key, e))
+        })?)),
+        Err(StoreError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn save_json<T: Serialize>(
+    context: &AppContext,
+    config: &Config,
+    key: &S3Key,
+    value: &T,
+) -> Result<(), AwsError> {
+    let body = serde_json::to_vec_pretty(value)
+        .map_err(|e| StoreError::Backend(format!("Failed to encode thread record: {}", e)))?;
+    Ok(context
+        .store
+        .put_object(&config.email_bucket, key, body)
+        .await?)
+}
+
+async fn linked_thread(
+    context: &AppContext,
+    config: &Config,
+    prefix: &str,
+    message_id: &str,
+) -> Result<Option<String>, AwsError> {
+    Ok(
+        load_json::<ThreadLink>(context, config, &link_key(prefix, message_id)?)
+            .await?
+            .map(|link| link.thread_id),
+    )
+}
+
+/// Add a stored message to its conversation. The thread is the one any of
+/// its ancestors (or the message itself, when threaded before) is already
+/// in; otherwise a new one named after the conversation's root, so replies
+/// that arrive before their parent still land together. Like the alias
+/// records, threads are read-modify-write, so two messages of one thread
+/// processed at the same moment can lose an entry.
+pub async fn record_thread(
+    context: &AppContext,
+    config: &Config,
+    prefix: &str,
+    stored_id: &MessageId,
+    raw_email: &[u8],
+    now: DateTime<Utc>,
+) -> Result<ThreadRecord, AwsError> {
+    let headers = ThreadHeaders::parse(raw_email);
+
+    let mut found = None;
+    for id in headers.message_id.iter().chain(headers.ancestors()) {
+        if let Some(thread_id) = linked_thread(context, config, prefix, id).await? {
+            found = Some(thread_id);
+            break;
+        }
+    }
+    let thread_id = found
+        .unwrap_or_else(|| thread_id(headers.root().map_or(stored_id.as_str(), String::as_str)));
+
+    let key = thread_key(prefix, &thread_id)?;
+    let mut thread = load_json::<ThreadRecord>(context, config, &key)
+        .await?
+        .unwrap_or_else(|| ThreadRecord {
+            thread_id: thread_id.clone(),
+            root: headers.root().cloned(),
+            subject: headers.subject.clone(),
+            messages: Vec::new(),
+            updated_at: now,
+        });
+
+    thread
+        .messages
+        .retain(|message| message.stored_id != stored_id.as_str());
+    thread.messages.push(ThreadMessage {
+        stored_id: stored_id.to_string(),
+        message_id: headers.message_id.clone(),
+        in_reply_to: headers.in_reply_to.clone(),
+        from: headers.from.clone(),
+        date: headers.date.unwrap_or(now),
+    });
+    thread.messages.sort_by(|a, b| {
+        a.date
+            .cmp(&b.date)
+            .then_with(|| a.stored_id.cmp(&b.stored_id))
+    });
+    thread.updated_at = now;
+    save_json(context, config, &key, &thread).await?;
+
+    // Link the message and any ancestors not seen yet, so later replies
+    // naming only one of them still find the thread
+    for id in headers.message_id.iter().chain(headers.ancestors()) {
+        if linked_thread(context, config, prefix, id).await?.as_ref() != Some(&thread_id) {
+            let link = ThreadLink {
+                message_id: id.clone(),
+                thread_id: thread_id.clone(),
+            };
+            save_json(context, config, &link_key(prefix, id)?, &link).await?;
+        }
+    }
+
+    info!(
+        "Threaded {} into {} ({} messages)",
+        stored_id,
+        thread_id,
+        thread.messages.len()
+    );
+    Ok(thread)
+}
+
+/// A thread by its id, or the thread a Message-ID (with or without
+/// brackets) belongs to
+pub async fn load_thread(
+    context: &AppContext,
+    config: &Config,
+    prefix: &str,
+    id: &str,
+) -> Result<Option<ThreadRecord>, AwsError> {
+    let id = id.trim();
+    let thread_id = if id.len() == THREAD_ID_LEN && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(id.to_lowercase())
+    } else {
+        match message_ids(id).first() {
+            Some(message_id) => linked_thread(context, config, prefix, message_id).await?,
+            None => None,
+        }
+    };
+
+    match thread_id {
+        Some(thread_id) => load_json(context, config, &thread_key(prefix, &thread_id)?).await,
+        None => Ok(None),
+    }
+}
+
+/// The conversation as an mboxrd file, oldest message first. Messages are
+/// read from `incoming_prefix`, or `processed_prefix` once the incoming copy
+/// has expired; any that are gone from both are left out with a warning.
+pub async fn conversation_mbox(
+    context: &AppContext,
+    config: &Config,
+    thread: &ThreadRecord,
+) -> Result<Vec<u8>, AwsError> {
+    let prefixes: Vec<&str> = std::iter::once(config.incoming_prefix.as_str())
+        .chain(config.processed_prefix.as_deref())
+        .collect();
+
+    let mut mbox = Vec::new();
+    'messages: for message in &thread.messages {
+        let stored_id = MessageId::try_from(message.stored_id.clone())?;
+        for prefix in &prefixes {
+            let key = incoming_key(prefix, &stored_id)?;
+            match context.store.get_object(&config.email_bucket, &key).await {
+                Ok(raw_email) => {
+                    mbox.extend(mboxrd_entry(&raw_email, message.date));
+                    continue 'messages;
+                }
+                Err(StoreError::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        warn!(
+            "Message {} of thread {} is no longer stored",
+            stored_id, thread.thread_id
+        );
+    }
+    Ok(mbox)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_ids() {
+        assert_eq!(
+            message_ids("<1@example.com>\r\n <2@example.com>"),
+            vec!["<1@example.com>", "<2@example.com>"]
+        );
+        assert_eq!(message_ids("3@example.com"), vec!["<3@example.com>"]);
+        assert_eq!(
+            message_ids("Your message <4@example.com> of Friday"),
+            vec!["<4@example.com>"]
+        );
+        assert!(message_ids("").is_empty());
+    }
+
+    #[test]
+    fn test_thread_headers() {
+        let headers = ThreadHeaders::parse(
+            b"Message-ID: <3@example.com>\r\n\
+In-Reply-To: <2@example.com>\r\n\
+References: <1@example.com> <2@example.com>\r\n\
+From: Pat <pat@venue.example>\r\n\
+Subject: Re: Friday\r\n\
+\r\n\
+Works for me",
+        );
+        assert_eq!(headers.message_id.as_deref(), Some("<3@example.com>"));
+        assert_eq!(headers.from.as_deref(), Some("pat@venue.example"));
+        assert_eq!(headers.root().map(String::as_str), Some("<1@example.com>"));
+        assert_eq!(
+            headers.ancestors(),
+            vec!["<2@example.com>", "<1@example.com>"]
+        );
+
+        let first = ThreadHeaders::parse(b"Message-ID: <1@example.com>\r\n\r\nHi");
+        assert_eq!(first.root().map(String::as_str), Some("<1@example.com>"));
+        assert!(first.ancestors().is_empty());
+        assert_eq!(thread_id("<1@example.com>").len(), THREAD_ID_LEN);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::domain::{MessageId, SesReceipt};
+use chrono::{DateTime, Utc};
+
+/// Name the processor uses for itself in `Received` headers
+pub const PROCESSOR_NAME: &str = "email-processor";
+
+/// Summary of the SES receipt verdicts, e.g.
+/// `X-Processor-Verdicts: spam=pass; virus=pass; spf=pass; dkim=fail; dmarc=gray`
+pub const VERDICTS_HEADER: &str = "X-Processor-Verdicts";
+
+/// Value of the `Received` header we prepend, in RFC 5321 stamp form:
+/// `from amazonses.com by <domain> (email-processor) with SES id <id>; <date>`
+pub fn received_header_value(
+    by_domain: &str,
+    message_id: &MessageId,
+    received_at: DateTime<Utc>,
+) -> String {
+    format!(
+        "from amazonses.com by {} ({}) with SES id {}; {}",
+        by_domain,
+        PROCESSOR_NAME,
+        message_id,
+        received_at.to_rfc2822()
+    )
+}
+
+/// Value of the [`VERDICTS_HEADER`], or `None` when SES reported no verdicts
+pub fn verdicts_header_value(receipt: &SesReceipt) -> Option<String> {
+    let verdicts: Vec<String> = [
+        ("spam", &receipt.spam_verdict),
+        ("virus", &receipt.virus_verdict),
+        ("spf", &receipt.spf_verdict),
+        ("dkim", &receipt.dkim_verdict),
+        ("dmarc", &receipt.dmarc_verdict),
+    ]
+    .into_iter()
+    .filter_map(|(name, verdict)| {
+        verdict
+            .as_ref()
+            .map(|verdict| format!("{}={}", name, verdict.status.to_lowercase()))
+    })
+    .collect();
+
+    (!verdicts.is_empty()).then(|| verdicts.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::SesVerdict;
+    use chrono::TimeZone;
+
+    fn verdict(status: &str) -> Option<SesVerdict> {
+        Some(SesVerdict {
+            status: status.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_received_header_value() {
+        let message_id = MessageId::try_from("abc123".to_string()).unwrap();
+        let at = Utc.with_ymd_and_hms(2026, 3, 14, 9, 30, 0).unwrap();
+
+        assert_eq!(
+            received_header_value("jimmillerdrums.com", &message_id, at),
+            "from amazonses.com by jimmillerdrums.com (email-processor) with SES id abc123; Sat, 14 Mar 2026 09:30:00 +0000"
+        );
+    }
+
+    #[test]
+    fn test_verdicts_header_value() {
+        let receipt = SesReceipt {
+            spam_verdict: verdict("PASS"),
+            virus_verdict: verdict("PASS"),
+            spf_verdict: verdict("PASS"),
+            dkim_verdict: verdict("FAIL"),
+            dmarc_verdict: verdict("GRAY"),
+            dmarc_policy: None,
+        };
+
+        assert_eq!(
+            verdicts_header_value(&receipt).unwrap(),
+            "spam=pass; virus=pass; spf=pass; dkim=fail; dmarc=gray"
+        );
+        assert_eq!(verdicts_header_value(&SesReceipt::default()), None);
+    }
+}
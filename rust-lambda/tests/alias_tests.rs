@@ -0,0 +1,128 @@
+use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
+use aws_smithy_mocks::{mock, mock_client, RuleMode};
+use chrono::{DateTime, TimeZone, Utc};
+use email_processor::{
+    conversation_alias, list_aliases, revoke_alias, AppContext, Config, MemoryStore,
+};
+use std::sync::Arc;
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 5, 1, 9, 0, 0).unwrap()
+}
+
+/// Aliases never send mail; any attempt fails to match the mock
+fn context() -> AppContext {
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|_| false)
+        .then_output(|| SendEmailOutput::builder().build());
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+
+    AppContext {
+        store: Arc::new(MemoryStore::new()),
+        ses_client,
+    }
+}
+
+fn config() -> Config {
+    Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "me@gmail.com".to_string(),
+    )
+}
+
+#[tokio::test]
+async fn test_conversation_keeps_one_alias_and_collects_references() {
+    let context = context();
+    let config = config();
+    let our_address = "booking@jimmillerdrums.com";
+
+    let first =
+        b"From: Fan <fan@example.com>\r\nMessage-ID: <1@example.com>\r\nSubject: Gig\r\n\r\nHi";
+    let second = b"From: Fan <fan@example.com>\r\nMessage-ID: <2@example.com>\r\nIn-Reply-To: <relayed@jimmillerdrums.com>\r\nReferences: <1@example.com> <relayed@jimmillerdrums.com>\r\nSubject: Re: Gig\r\n\r\nGreat";
+
+    let alias = conversation_alias(&context, &config, "secret", first, our_address, now())
+        .await
+        .unwrap()
+        .unwrap();
+    let again = conversation_alias(&context, &config, "secret", second, our_address, now())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(alias.alias.starts_with("reply+"));
+    assert!(alias.alias.ends_with("@jimmillerdrums.com"));
+    assert_eq!(alias.alias, again.alias);
+    assert_eq!(again.correspondent, "fan@example.com");
+    assert_eq!(again.our_address, our_address);
+    assert_eq!(again.references, vec!["<1@example.com>", "<2@example.com>"]);
+    assert_eq!(
+        again.thread_headers(),
+        vec![
+            ("In-Reply-To".to_string(), "<2@example.com>".to_string()),
+            (
+                "References".to_string(),
+                "<1@example.com> <2@example.com>".to_string()
+            ),
+        ]
+    );
+
+    // A new conversation with the same person gets its own alias
+    let other =
+        b"From: fan@example.com\r\nMessage-ID: <9@example.com>\r\nSubject: Lessons\r\n\r\nHi";
+    let other = conversation_alias(&context, &config, "secret", other, our_address, now())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_ne!(other.alias, alias.alias);
+}
+
+#[tokio::test]
+async fn test_revoked_alias_is_not_reused() {
+    let context = context();
+    let config = config();
+    let email = b"From: fan@example.com\r\nMessage-ID: <1@example.com>\r\n\r\nHi";
+    let our_address = "info@jimmillerdrums.com";
+
+    let alias = conversation_alias(&context, &config, "secret", email, our_address, now())
+        .await
+        .unwrap()
+        .unwrap();
+    let revoked = revoke_alias(&context, &config, &alias.alias, now())
+        .await
+        .unwrap();
+    assert!(revoked.is_revoked());
+
+    assert_eq!(
+        conversation_alias(&context, &config, "secret", email, our_address, now())
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        list_aliases(&context, &config).await.unwrap(),
+        vec![revoked]
+    );
+    assert!(revoke_alias(&context, &config, "0000000000000000", now())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_mail_from_the_forwarding_mailbox_gets_no_alias() {
+    let context = context();
+    let email = b"From: Me <me@gmail.com>\r\nMessage-ID: <1@gmail.com>\r\n\r\nHi";
+    assert_eq!(
+        conversation_alias(
+            &context,
+            &config(),
+            "secret",
+            email,
+            "info@jimmillerdrums.com",
+            now()
+        )
+        .await
+        .unwrap(),
+        None
+    );
+}
@@ -1,10 +1,13 @@
 use aws_sdk_s3::operation::get_object::GetObjectOutput;
 use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
 use aws_smithy_mocks::{mock, mock_client, Rule, RuleMode};
+use aws_smithy_runtime_api::http::{Response as HttpResponse, StatusCode};
 use aws_smithy_types::body::SdkBody;
 use email_processor::{
-    forward_email, AppContext, Config, EmailAddress, ForwardEmailRequest, MessageId,
+    forward_email, AppContext, Config, EmailAddress, ForwardEmailRequest, MailStore, MessageId,
+    S3Key, StoreError,
 };
+use std::sync::Arc;
 
 mod mocks {
     use super::*;
@@ -23,6 +26,19 @@ mod mocks {
             })
         }
 
+        /// Error response as S3 sends it, `status` with an XML error `code`
+        pub fn get_object_status(status: u16, code: &'static str) -> Rule {
+            mock!(aws_sdk_s3::Client::get_object).then_http_response(move || {
+                HttpResponse::new(
+                    StatusCode::try_from(status).unwrap(),
+                    SdkBody::from(format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code><Message>{}</Message></Error>",
+                        code, code
+                    )),
+                )
+            })
+        }
+
         pub fn get_object_not_found() -> Rule {
             mock!(aws_sdk_s3::Client::get_object).then_error(|| {
                 GetObjectError::unhandled("NoSuchKey: The specified key does not exist")
@@ -60,7 +76,7 @@ async fn test_forward_email_with_custom_incoming_path() {
     let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
 
     let context = AppContext {
-        s3_client,
+        store: Arc::new(s3_client),
         ses_client,
     };
 
@@ -93,7 +109,7 @@ async fn test_forward_email_with_updated_sender_identity() {
     let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
 
     let context = AppContext {
-        s3_client,
+        store: Arc::new(s3_client),
         ses_client,
     };
 
@@ -126,7 +142,7 @@ async fn test_s3_get_object_failure() {
     let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
 
     let context = AppContext {
-        s3_client,
+        store: Arc::new(s3_client),
         ses_client,
     };
 
@@ -157,7 +173,7 @@ async fn test_ses_send_email_failure() {
     let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
 
     let context = AppContext {
-        s3_client,
+        store: Arc::new(s3_client),
         ses_client,
     };
 
@@ -177,3 +193,24 @@ async fn test_ses_send_email_failure() {
     let result = forward_email(&context, request, &config).await;
     assert!(result.is_err());
 }
+
+#[tokio::test]
+async fn test_s3_missing_key_maps_to_not_found() {
+    let s3_mock = mocks::s3::get_object_status(404, "NoSuchKey");
+    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&s3_mock]);
+    let key = S3Key::try_from("ratelimit/new-sender.json".to_string()).unwrap();
+
+    let result = MailStore::get_object(&s3_client, "test-bucket", &key).await;
+    assert!(matches!(result, Err(StoreError::NotFound(k)) if k == key.as_str()));
+}
+
+#[tokio::test]
+async fn test_s3_access_denied_is_not_mistaken_for_missing_key() {
+    // Without s3:ListBucket, S3 answers a missing key with 403
+    let s3_mock = mocks::s3::get_object_status(403, "AccessDenied");
+    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&s3_mock]);
+    let key = S3Key::try_from("ratelimit/new-sender.json".to_string()).unwrap();
+
+    let result = MailStore::get_object(&s3_client, "test-bucket", &key).await;
+    assert!(matches!(result, Err(StoreError::Backend(_))));
+}
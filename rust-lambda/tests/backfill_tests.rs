@@ -0,0 +1,247 @@
+use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
+use aws_smithy_mocks::{mock, mock_client, RuleMode};
+use email_processor::{
+    encode_relay_address, run_backfill, AppContext, BackfillOptions, Config, MemoryStore,
+    ObjectTags, TimeWindow, FORWARDED_MESSAGE_ID_TAG, ROUTE_TAG, STATUS_TAG,
+};
+use std::sync::Arc;
+
+const RAW_EMAIL: &str = "From: sender@example.com\r\nSubject: Gig inquiry\r\n\r\nAre you free?";
+
+fn at(value: &str) -> chrono::DateTime<chrono::Utc> {
+    TimeWindow::parse_bound(value).unwrap()
+}
+
+fn outage_window() -> TimeWindow {
+    TimeWindow {
+        since: Some(at("2026-01-14T14:00:00Z")),
+        until: Some(at("2026-01-14T17:30:00Z")),
+    }
+}
+
+fn seeded_store() -> Arc<MemoryStore> {
+    let store = Arc::new(MemoryStore::new());
+    store.insert_at(
+        "bucket",
+        "incoming/missed-1",
+        RAW_EMAIL,
+        at("2026-01-14T14:02:00Z"),
+    );
+    store.insert_at(
+        "bucket",
+        "incoming/missed-2",
+        RAW_EMAIL,
+        at("2026-01-14T17:14:00Z"),
+    );
+    store.insert_at(
+        "bucket",
+        "incoming/delivered",
+        RAW_EMAIL,
+        at("2026-01-14T15:00:00Z"),
+    );
+    store.insert_at(
+        "bucket",
+        "incoming/before-outage",
+        RAW_EMAIL,
+        at("2026-01-13T09:00:00Z"),
+    );
+    store.insert_at(
+        "bucket",
+        "incoming/AMAZON_SES_SETUP_NOTIFICATION",
+        "setup",
+        at("2026-01-14T15:00:00Z"),
+    );
+
+    let mut forwarded = ObjectTags::new();
+    forwarded.insert(STATUS_TAG.to_string(), "forwarded".to_string());
+    store.set_tags("bucket", "incoming/delivered", forwarded);
+
+    store
+}
+
+fn context(store: Arc<MemoryStore>) -> AppContext {
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("backfilled-id")
+            .build()
+    });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+
+    AppContext { store, ses_client }
+}
+
+fn config() -> Config {
+    Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "recipient@example.com".to_string(),
+    )
+}
+
+#[tokio::test]
+async fn test_backfill_forwards_missed_messages_in_window() {
+    let store = seeded_store();
+    let context = context(store.clone());
+
+    let options = BackfillOptions {
+        window: outage_window(),
+        concurrency: 2,
+        dry_run: false,
+    };
+    let report = run_backfill(&context, &config(), &options).await.unwrap();
+
+    assert_eq!(report.scanned, 5);
+    assert_eq!(report.outside_window, 1);
+    assert_eq!(report.ignored, 1);
+    assert_eq!(report.already_forwarded, 1);
+    assert_eq!(report.forwarded, vec!["missed-1", "missed-2"]);
+    assert!(report.failed.is_empty());
+
+    let tags = store.tags("bucket", "incoming/missed-1").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    assert_eq!(tags.get(FORWARDED_MESSAGE_ID_TAG).unwrap(), "backfilled-id");
+}
+
+#[tokio::test]
+async fn test_backfill_dry_run_sends_nothing() {
+    let store = seeded_store();
+    let context = context(store.clone());
+
+    let options = BackfillOptions {
+        window: outage_window(),
+        concurrency: 2,
+        dry_run: true,
+    };
+    let report = run_backfill(&context, &config(), &options).await.unwrap();
+
+    assert_eq!(report.would_forward, vec!["missed-1", "missed-2"]);
+    assert!(report.forwarded.is_empty());
+    assert!(store
+        .tags("bucket", "incoming/missed-1")
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_backfill_reports_failures() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert_at(
+        "bucket",
+        "incoming/no-from-header",
+        "Subject: Broken\r\n\r\nNo sender",
+        at("2026-01-14T15:00:00Z"),
+    );
+    let context = context(store.clone());
+
+    let options = BackfillOptions {
+        window: outage_window(),
+        ..Default::default()
+    };
+    let report = run_backfill(&context, &config(), &options).await.unwrap();
+
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].message_id, "no-from-header");
+    let tags = store.tags("bucket", "incoming/no-from-header").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "failed");
+}
+
+#[tokio::test]
+async fn test_backfill_leaves_reports_and_skipped_mail_alone() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert_at(
+        "bucket",
+        "incoming/dmarc-report",
+        "From: noreply-dmarc@google.com\r\nTo: dmarc@jimmillerdrums.com\r\nSubject: Report\r\n\r\nzip",
+        at("2026-01-14T14:30:00Z"),
+    );
+    store.insert_at(
+        "bucket",
+        "incoming/skipped",
+        RAW_EMAIL,
+        at("2026-01-14T15:30:00Z"),
+    );
+    store.insert_at(
+        "bucket",
+        "incoming/retry",
+        RAW_EMAIL,
+        at("2026-01-14T16:00:00Z"),
+    );
+    let mut skipped = ObjectTags::new();
+    skipped.insert(STATUS_TAG.to_string(), "skipped".to_string());
+    skipped.insert(ROUTE_TAG.to_string(), "manual".to_string());
+    store.set_tags("bucket", "incoming/skipped", skipped);
+    let mut failed = ObjectTags::new();
+    failed.insert(STATUS_TAG.to_string(), "failed".to_string());
+    store.set_tags("bucket", "incoming/retry", failed);
+
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("backfilled-id")
+            .build()
+    });
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]),
+    };
+
+    let options = BackfillOptions {
+        window: outage_window(),
+        ..Default::default()
+    };
+    let report = run_backfill(&context, &config(), &options).await.unwrap();
+
+    assert_eq!(report.already_skipped, 1);
+    assert_eq!(report.suppressed, vec!["dmarc-report"]);
+    assert_eq!(report.forwarded, vec!["retry"]);
+    assert_eq!(ses_mock.num_calls(), 1);
+
+    let tags = store.tags("bucket", "incoming/dmarc-report").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "skipped");
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "reports");
+    let tags = store.tags("bucket", "incoming/skipped").unwrap();
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "manual");
+}
+
+#[tokio::test]
+async fn test_backfill_leaves_failed_relay_replies_for_manual_action() {
+    let relay_address =
+        encode_relay_address("fan@example.com", "jimmillerdrums.com", "secret").unwrap();
+    let store = Arc::new(MemoryStore::new());
+    store.insert_at(
+        "bucket",
+        "incoming/reply",
+        format!(
+            "From: Me <recipient@example.com>\r\nTo: {}\r\nSubject: Re: Hi\r\n\r\nSee you there",
+            relay_address
+        ),
+        at("2026-01-14T15:00:00Z"),
+    );
+    let mut failed = ObjectTags::new();
+    failed.insert(STATUS_TAG.to_string(), "failed".to_string());
+    failed.insert(ROUTE_TAG.to_string(), "relay".to_string());
+    store.set_tags("bucket", "incoming/reply", failed.clone());
+
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("backfilled-id")
+            .build()
+    });
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]),
+    };
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+
+    let options = BackfillOptions {
+        window: outage_window(),
+        ..Default::default()
+    };
+    let report = run_backfill(&context, &config, &options).await.unwrap();
+    assert_eq!(report.needs_manual, vec!["reply"]);
+    assert!(report.suppressed.is_empty());
+    assert_eq!(ses_mock.num_calls(), 0);
+
+    // Still failed, so a later run or a manual relay can pick it up
+    assert_eq!(store.tags("bucket", "incoming/reply").unwrap(), failed);
+}
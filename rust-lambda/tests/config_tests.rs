@@ -1,9 +1,14 @@
 use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_sdk_s3::operation::put_object_tagging::PutObjectTaggingOutput;
 use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
 use aws_smithy_mocks::{mock, mock_client, RuleMode};
 use aws_smithy_types::body::SdkBody;
 use email_processor::config::Config;
-use email_processor::{process_ses_event, AppContext, SesEvent, SesMail, SesMessage, SesRecord};
+use email_processor::{
+    process_ses_event, AppContext, MemoryStore, SesEvent, SesMail, SesMessage, SesRecord,
+    FORWARDED_MESSAGE_ID_TAG, ROUTE_TAG, STATUS_TAG,
+};
+use std::sync::Arc;
 
 #[tokio::test]
 async fn test_config_based_processing() {
@@ -29,11 +34,14 @@ async fn test_config_based_processing() {
             .build()
     });
 
-    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&s3_mock]);
+    let tagging_mock = mock!(aws_sdk_s3::Client::put_object_tagging)
+        .then_output(|| PutObjectTaggingOutput::builder().build());
+
+    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&s3_mock, &tagging_mock]);
     let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
 
     let context = AppContext {
-        s3_client,
+        store: Arc::new(s3_client),
         ses_client,
     };
 
@@ -46,6 +54,7 @@ async fn test_config_based_processing() {
                     source: "sender@example.com".to_string(),
                     destination: vec!["recipient@jimmillerdrums.com".to_string()],
                 },
+                receipt: None,
             },
         }],
     };
@@ -82,11 +91,14 @@ async fn test_custom_incoming_prefix_in_config() {
             .build()
     });
 
-    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&s3_mock]);
+    let tagging_mock = mock!(aws_sdk_s3::Client::put_object_tagging)
+        .then_output(|| PutObjectTaggingOutput::builder().build());
+
+    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&s3_mock, &tagging_mock]);
     let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
 
     let context = AppContext {
-        s3_client,
+        store: Arc::new(s3_client),
         ses_client,
     };
 
@@ -98,6 +110,7 @@ async fn test_custom_incoming_prefix_in_config() {
                     source: "noreply-dmarc-support@google.com".to_string(),
                     destination: vec!["dmarc@jimmillerdrums.com".to_string()],
                 },
+                receipt: None,
             },
         }],
     };
@@ -105,3 +118,59 @@ async fn test_custom_incoming_prefix_in_config() {
     let result = process_ses_event(ses_event, &context, &config).await;
     assert!(result.is_ok());
 }
+
+#[tokio::test]
+async fn test_processed_message_is_tagged_and_copied() {
+    let mut config = Config::new(
+        "test-bucket".to_string(),
+        "incoming".to_string(),
+        "test@example.com".to_string(),
+    );
+    config.processed_prefix = Some("processed".to_string());
+
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "test-bucket",
+        "incoming/tagged-message-1",
+        "From: sender@example.com\r\nSubject: Test\r\n\r\nTest body",
+    );
+
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("forwarded-id-1")
+            .build()
+    });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let ses_event = SesEvent {
+        records: vec![SesRecord {
+            ses: SesMessage {
+                mail: SesMail {
+                    message_id: "tagged-message-1".to_string(),
+                    source: "sender@example.com".to_string(),
+                    destination: vec!["info@jimmillerdrums.com".to_string()],
+                },
+                receipt: None,
+            },
+        }],
+    };
+
+    let result = process_ses_event(ses_event, &context, &config).await;
+    assert!(result.is_ok());
+
+    let tags = store
+        .tags("test-bucket", "incoming/tagged-message-1")
+        .unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    assert_eq!(
+        tags.get(FORWARDED_MESSAGE_ID_TAG).unwrap(),
+        "forwarded-id-1"
+    );
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "default");
+    assert!(store.contains("test-bucket", "processed/tagged-message-1"));
+}
@@ -0,0 +1,209 @@
+use aws_sdk_sesv2::operation::send_email::{SendEmailError, SendEmailOutput};
+use aws_smithy_mocks::{mock, mock_client, RuleMode};
+use email_processor::{
+    encode_relay_address, list_dead_letters, process_ses_event, replay_dead_letters, AppContext,
+    Config, MemoryStore, SesEvent, SesMail, SesMessage, SesRecord, FAILED_ATTEMPTS_TAG, ROUTE_TAG,
+};
+use std::sync::Arc;
+
+fn config() -> Config {
+    Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "recipient@example.com".to_string(),
+    )
+}
+
+fn context(store: Arc<MemoryStore>) -> AppContext {
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email)
+        .then_output(|| SendEmailOutput::builder().message_id("replayed-id").build());
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+
+    AppContext { store, ses_client }
+}
+
+fn event(message_id: &str) -> SesEvent {
+    SesEvent {
+        records: vec![SesRecord {
+            ses: SesMessage {
+                mail: SesMail {
+                    message_id: message_id.to_string(),
+                    source: "sender@example.com".to_string(),
+                    destination: vec!["info@jimmillerdrums.com".to_string()],
+                },
+                receipt: None,
+            },
+        }],
+    }
+}
+
+#[tokio::test]
+async fn test_unparseable_message_is_dead_lettered() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/broken-1",
+        "Subject: No sender\r\n\r\nBody",
+    );
+    let context = context(store.clone());
+
+    let result = process_ses_event(event("broken-1"), &context, &config()).await;
+    assert!(result.is_err());
+
+    let records = list_dead_letters(&context, &config()).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].message_id, "broken-1");
+    assert_eq!(records[0].s3_key, "incoming/broken-1");
+    assert_eq!(records[0].error_class, "email_parse");
+    assert_eq!(records[0].attempts, 1);
+
+    let tags = store.tags("bucket", "incoming/broken-1").unwrap();
+    assert_eq!(tags.get(FAILED_ATTEMPTS_TAG).unwrap(), "1");
+}
+
+#[tokio::test]
+async fn test_replay_removes_record_once_forwarded() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/broken-2",
+        "Subject: No sender\r\n\r\nBody",
+    );
+    let context = context(store.clone());
+
+    let _ = process_ses_event(event("broken-2"), &context, &config()).await;
+    assert!(store.contains("bucket", "dead-letter/broken-2.json"));
+
+    // Still broken: replay fails and the record is re-captured with another attempt
+    let report = replay_dead_letters(&context, &config(), &[], false)
+        .await
+        .unwrap();
+    assert_eq!(report.failed.len(), 1);
+    let records = list_dead_letters(&context, &config()).await.unwrap();
+    assert_eq!(records[0].attempts, 2);
+
+    // Repaired by hand: replay forwards it and clears the record
+    store.insert(
+        "bucket",
+        "incoming/broken-2",
+        "From: sender@example.com\r\nSubject: Fixed\r\n\r\nBody",
+    );
+    let report = replay_dead_letters(&context, &config(), &["broken-2".to_string()], false)
+        .await
+        .unwrap();
+    assert_eq!(report.replayed, vec!["broken-2"]);
+    assert!(!store.contains("bucket", "dead-letter/broken-2.json"));
+}
+
+#[tokio::test]
+async fn test_failed_relay_is_counted_and_dead_lettered() {
+    let relay_address =
+        encode_relay_address("fan@example.com", "jimmillerdrums.com", "secret").unwrap();
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/reply",
+        format!(
+            "From: Me <recipient@example.com>\r\nTo: {}\r\nSubject: Re: Hi\r\n\r\nSee you there",
+            relay_address
+        ),
+    );
+    let throttled = mock!(aws_sdk_sesv2::Client::send_email)
+        .then_error(|| SendEmailError::unhandled("ThrottlingException: Rate limit exceeded"));
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&throttled]),
+    };
+
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+    config.max_attempts = 2;
+    let event = || -> SesEvent {
+        serde_json::from_value(serde_json::json!({
+        "Records": [{
+            "ses": {
+                "mail": {
+                    "messageId": "reply",
+                    "source": "recipient@example.com",
+                    "destination": [relay_address]
+                },
+                "receipt": {
+                    "spfVerdict": { "status": "PASS" },
+                    "dkimVerdict": { "status": "PASS" },
+                    "dmarcVerdict": { "status": "PASS" }
+                }
+            }
+        }]
+        }))
+        .unwrap()
+    };
+
+    // A throttled send is retried by SES until attempts run out
+    assert!(process_ses_event(event(), &context, &config).await.is_err());
+    assert!(!store.contains("bucket", "dead-letter/reply.json"));
+    assert!(process_ses_event(event(), &context, &config).await.is_err());
+
+    let tags = store.tags("bucket", "incoming/reply").unwrap();
+    assert_eq!(tags.get(FAILED_ATTEMPTS_TAG).unwrap(), "2");
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "relay");
+    let records = list_dead_letters(&context, &config).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].message_id, "reply");
+    assert_eq!(records[0].attempts, 2);
+}
+
+#[tokio::test]
+async fn test_replay_leaves_relay_records_alone() {
+    let relay_address =
+        encode_relay_address("fan@example.com", "jimmillerdrums.com", "secret").unwrap();
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/reply",
+        format!(
+            "From: Me <recipient@example.com>\r\nTo: {}\r\nSubject: Re: Hi\r\n\r\nSee you there",
+            relay_address
+        ),
+    );
+    let rejected = mock!(aws_sdk_sesv2::Client::send_email)
+        .then_error(|| SendEmailError::unhandled("MessageRejected: Email address is not verified"));
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&rejected]),
+    };
+
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+    let event: SesEvent = serde_json::from_value(serde_json::json!({
+        "Records": [{
+            "ses": {
+                "mail": {
+                    "messageId": "reply",
+                    "source": "recipient@example.com",
+                    "destination": [relay_address]
+                },
+                "receipt": {
+                    "spfVerdict": { "status": "PASS" },
+                    "dkimVerdict": { "status": "PASS" },
+                    "dmarcVerdict": { "status": "PASS" }
+                }
+            }
+        }]
+    }))
+    .unwrap();
+    assert!(process_ses_event(event, &context, &config).await.is_err());
+    assert!(store.contains("bucket", "dead-letter/reply.json"));
+    let tags_before = store.tags("bucket", "incoming/reply").unwrap();
+
+    // Without the SES verdicts the reply cannot be authorized again, so it
+    // is neither refused nor dropped from the dead letters
+    let report = replay_dead_letters(&context, &config, &[], false)
+        .await
+        .unwrap();
+    assert_eq!(report.not_replayable, vec!["reply".to_string()]);
+    assert!(report.replayed.is_empty());
+    assert!(report.suppressed.is_empty());
+    assert!(store.contains("bucket", "dead-letter/reply.json"));
+    assert_eq!(store.tags("bucket", "incoming/reply").unwrap(), tags_before);
+    assert_eq!(rejected.num_calls(), 1);
+}
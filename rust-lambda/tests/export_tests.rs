@@ -0,0 +1,149 @@
+use chrono::{DateTime, TimeZone, Utc};
+use email_processor::{
+    export_mail, Config, ExportFormat, ExportOptions, FsStore, MemoryStore, TimeWindow,
+};
+use std::fs;
+use std::path::PathBuf;
+
+fn config() -> Config {
+    Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "me@gmail.com".to_string(),
+    )
+}
+
+fn at(day: u32, hour: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap()
+}
+
+/// A fresh directory for one test, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("export-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+const INVOICE: &str = "Return-Path: <accounts@venue.example>\r\n\
+From: Accounts <accounts@venue.example>\r\n\
+Date: Mon, 02 Mar 2026 09:00:00 +0000\r\n\
+Subject: Invoice\r\n\
+\r\n\
+From March onwards the rate is $400.\r\n\
+>From the old contract: $350.\r\n";
+
+const RECEIPT: &str = "From: bank@example.com\r\n\
+Date: Tue, 10 Mar 2026 09:00:00 +0000\r\n\
+Subject: Receipt\r\n\
+\r\n\
+Paid\r\n";
+
+fn seeded_store() -> MemoryStore {
+    let store = MemoryStore::new();
+    store.insert_at("bucket", "incoming/invoice", INVOICE, at(2, 9));
+    store.insert_at("bucket", "incoming/receipt", RECEIPT, at(10, 9));
+    store.insert_at(
+        "bucket",
+        "incoming/AMAZON_SES_SETUP_NOTIFICATION",
+        "setup",
+        at(2, 8),
+    );
+    store.insert_at("bucket", "incoming/april", RECEIPT, at(31, 23));
+    store.insert_at("bucket", "processed/invoice", INVOICE, at(2, 9));
+    store
+}
+
+#[tokio::test]
+async fn test_export_date_range_to_mboxrd() {
+    let dir = TempDir::new("mbox");
+    let output = dir.0.join("march.mbox");
+    let options = ExportOptions {
+        window: TimeWindow {
+            since: Some(at(1, 0)),
+            until: Some(at(31, 0)),
+        },
+        format: ExportFormat::Mbox,
+    };
+
+    let report = export_mail(&seeded_store(), &config(), &options, &output)
+        .await
+        .unwrap();
+    assert_eq!(report.scanned, 4);
+    assert_eq!(report.outside_window, 1);
+    assert_eq!(report.ignored, 1);
+    assert_eq!(report.exported, 2);
+
+    let mbox = fs::read_to_string(&output).unwrap();
+    let separators: Vec<&str> = mbox
+        .lines()
+        .filter(|line| line.starts_with("From "))
+        .collect();
+    assert_eq!(
+        separators,
+        vec![
+            "From accounts@venue.example Mon Mar  2 09:00:00 2026",
+            "From bank@example.com Tue Mar 10 09:00:00 2026",
+        ]
+    );
+    assert!(mbox.contains("\n>From March onwards the rate is $400.\n>>From the old contract"));
+    assert!(!mbox.contains('\r'));
+}
+
+#[tokio::test]
+async fn test_export_filesystem_store_to_maildir() {
+    let dir = TempDir::new("maildir");
+    let store_dir = dir.0.join("store");
+    for (name, body, stored_at) in [
+        ("invoice", INVOICE, at(2, 9)),
+        ("receipt", RECEIPT, at(10, 9)),
+    ] {
+        let path = store_dir.join("bucket/incoming").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, body).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(stored_at.into())
+            .unwrap();
+    }
+
+    let output = dir.0.join("Maildir");
+    let options = ExportOptions {
+        window: TimeWindow {
+            since: Some(at(5, 0)),
+            until: None,
+        },
+        format: ExportFormat::Maildir,
+    };
+    let report = export_mail(&FsStore::new(&store_dir), &config(), &options, &output)
+        .await
+        .unwrap();
+    assert_eq!(report.exported, 1);
+    assert_eq!(report.outside_window, 1);
+
+    assert_eq!(fs::read_dir(output.join("new")).unwrap().count(), 0);
+    assert_eq!(fs::read_dir(output.join("tmp")).unwrap().count(), 0);
+    let files: Vec<PathBuf> = fs::read_dir(output.join("cur"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    let name = files[0].file_name().unwrap().to_string_lossy().into_owned();
+    assert_eq!(
+        name,
+        format!("{}.receipt.jimmillerdrums.com:2,", at(10, 9).timestamp())
+    );
+    assert_eq!(fs::read_to_string(&files[0]).unwrap(), RECEIPT);
+}
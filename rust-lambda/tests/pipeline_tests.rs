@@ -0,0 +1,1301 @@
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_sdk_s3::operation::put_object::PutObjectOutput;
+use aws_sdk_s3::operation::put_object_tagging::PutObjectTaggingOutput;
+use aws_sdk_sesv2::operation::send_email::{SendEmailError, SendEmailOutput};
+use aws_smithy_mocks::{mock, mock_client, RuleMode};
+use aws_smithy_runtime_api::http::{Response as HttpResponse, StatusCode};
+use aws_smithy_types::body::SdkBody;
+use chrono::{Duration, Utc};
+use email_processor::{
+    alias_id, encode_relay_address, encode_return_path, flush_held_notices, list_aliases,
+    process_invocation, process_ses_event, query_index, unknown_recipient_report, AppContext,
+    AttachmentManifest, AttachmentPolicy, AutoResponder, AutomatedMailPolicy, BounceRecord,
+    Classifier, Config, IndexQuery, IndexRecord, MemoryStore, RateLimit, RoutingTable, S3Key,
+    SenderRules, SesEvent, SesMail, SesMessage, SesRecord, TimeWindow, UnknownRecipientPolicy,
+    ROUTE_TAG, SKIP_REASON_TAG, STATUS_TAG,
+};
+use std::sync::Arc;
+
+fn config() -> Config {
+    Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "me@gmail.com".to_string(),
+    )
+}
+
+fn event(message_id: &str) -> SesEvent {
+    event_from(message_id, "sender@example.com")
+}
+
+fn event_from(message_id: &str, source: &str) -> SesEvent {
+    SesEvent {
+        records: vec![SesRecord {
+            ses: SesMessage {
+                mail: SesMail {
+                    message_id: message_id.to_string(),
+                    source: source.to_string(),
+                    destination: vec!["info@jimmillerdrums.com".to_string()],
+                },
+                receipt: None,
+            },
+        }],
+    }
+}
+
+/// Event as delivered by SES, with receipt verdicts
+fn event_with_receipt(message_id: &str) -> SesEvent {
+    serde_json::from_value(serde_json::json!({
+        "Records": [{
+            "ses": {
+                "mail": {
+                    "messageId": message_id,
+                    "source": "fan@example.com",
+                    "destination": ["info@jimmillerdrums.com"]
+                },
+                "receipt": {
+                    "recipients": ["info@jimmillerdrums.com"],
+                    "spamVerdict": { "status": "PASS" },
+                    "virusVerdict": { "status": "PASS" },
+                    "spfVerdict": { "status": "PASS" },
+                    "dkimVerdict": { "status": "GRAY" },
+                    "dmarcVerdict": { "status": "PASS" },
+                    "action": { "type": "Lambda" }
+                }
+            }
+        }]
+    }))
+    .unwrap()
+}
+
+/// Context whose SES mock only accepts raw messages containing `expected`
+fn context_expecting(store: Arc<MemoryStore>, expected: &'static str) -> AppContext {
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(move |req| {
+            req.content()
+                .and_then(|content| content.raw())
+                .map(|raw| String::from_utf8_lossy(raw.data().as_ref()).contains(expected))
+                .unwrap_or(false)
+        })
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+
+    AppContext { store, ses_client }
+}
+
+#[tokio::test]
+async fn test_forwarded_message_carries_trace_header() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/fresh",
+        "From: fan@example.com\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let context = context_expecting(
+        store.clone(),
+        "X-Forwarded-By-Processor: forwarder@jimmillerdrums.com; hops=1\r\n",
+    );
+
+    let result = process_ses_event(event("fresh"), &context, &config()).await;
+    assert!(result.is_ok());
+
+    let tags = store.tags("bucket", "incoming/fresh").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+}
+
+#[tokio::test]
+async fn test_looping_message_is_not_forwarded() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/looped",
+        "X-Forwarded-By-Processor: forwarder@jimmillerdrums.com; hops=3\r\nFrom: me@gmail.com\r\nSubject: Fwd: Hi\r\n\r\nBody",
+    );
+    // Any send attempt would fail to match and panic the mock
+    let context = context_expecting(store.clone(), "never sent");
+
+    let response = process_ses_event(event("looped"), &context, &config())
+        .await
+        .unwrap();
+    assert!(response["body"]
+        .as_str()
+        .unwrap()
+        .contains("mail-loop-hop-limit"));
+
+    let tags = store.tags("bucket", "incoming/looped").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "skipped");
+    assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "mail-loop-hop-limit");
+}
+
+#[tokio::test]
+async fn test_message_from_forwarder_is_not_forwarded() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/self",
+        "From: \"Fan\" (via jimmillerdrums.com) <forwarder@jimmillerdrums.com>\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let context = context_expecting(store.clone(), "never sent");
+
+    let result = process_ses_event(event("self"), &context, &config()).await;
+    assert!(result.is_ok());
+
+    let tags = store.tags("bucket", "incoming/self").unwrap();
+    assert_eq!(
+        tags.get(SKIP_REASON_TAG).unwrap(),
+        "mail-loop-from-forwarder"
+    );
+}
+
+#[tokio::test]
+async fn test_auto_reply_is_forwarded_with_tag_by_default() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/ooo",
+        "Auto-Submitted: auto-replied\r\nFrom: agent@example.com\r\nSubject: Out of office\r\n\r\nAway until Monday",
+    );
+    let context = context_expecting(store.clone(), "X-Processor-Automated: auto-submitted\r\n");
+
+    let result = process_ses_event(event("ooo"), &context, &config()).await;
+    assert!(result.is_ok());
+
+    let tags = store.tags("bucket", "incoming/ooo").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+}
+
+#[tokio::test]
+async fn test_bounce_is_stored_only_when_configured() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/bounce",
+        "From: MAILER-DAEMON@example.com\r\nSubject: Undeliverable\r\n\r\nUser unknown",
+    );
+    let context = context_expecting(store.clone(), "never sent");
+
+    let mut config = config();
+    config.bounce_policy = AutomatedMailPolicy::StoreOnly;
+
+    let result = process_ses_event(event_from("bounce", "<>"), &context, &config).await;
+    assert!(result.is_ok());
+
+    let tags = store.tags("bucket", "incoming/bounce").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "skipped");
+    assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "null-sender");
+}
+
+#[tokio::test]
+async fn test_forwarded_message_uses_srs_return_path() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/srs",
+        "Return-Path: <fan@example.com>\r\nFrom: fan@example.com\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            req.feedback_forwarding_email_address()
+                .is_some_and(|address| {
+                    address.starts_with("SRS0=")
+                        && address.ends_with("=example.com=fan@jimmillerdrums.com")
+                })
+        })
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = config();
+    config.srs_secret = Some("secret".to_string());
+
+    let result = process_ses_event(event("srs"), &context, &config).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_bounce_to_srs_address_is_recorded() {
+    let return_path = encode_return_path(
+        "fan@example.com",
+        "jimmillerdrums.com",
+        "secret",
+        Utc::now(),
+    )
+    .unwrap();
+
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/dsn",
+        "From: MAILER-DAEMON@amazonses.com\r\nSubject: Delivery Status Notification (Failure)\r\nContent-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: message/delivery-status\r\n\r\nReporting-MTA: dns; amazonses.com\r\n\r\nFinal-Recipient: rfc822; me@gmail.com\r\nStatus: 5.2.2\r\nDiagnostic-Code: smtp; 552 5.2.2 me@gmail.com mailbox full\r\n--b--",
+    );
+    let context = context_expecting(store.clone(), "never sent");
+
+    let mut config = config();
+    config.srs_secret = Some("secret".to_string());
+
+    let mut event = event_from("dsn", "<>");
+    event.records[0].ses.mail.destination = vec![return_path];
+
+    let response = process_ses_event(event, &context, &config).await.unwrap();
+    assert!(response["body"]
+        .as_str()
+        .unwrap()
+        .contains("fan@example.com"));
+
+    let key = S3Key::try_from("bounces/dsn.json".to_string()).unwrap();
+    let record = context.store.get_object("bucket", &key).await.unwrap();
+    let record: BounceRecord = serde_json::from_slice(&record).unwrap();
+    assert_eq!(record.original_sender.as_deref(), Some("fan@example.com"));
+    assert_eq!(record.status.as_deref(), Some("5.2.2"));
+
+    let tags = store.tags("bucket", "incoming/dsn").unwrap();
+    assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "srs-bounce");
+}
+
+#[tokio::test]
+async fn test_failed_bounce_notice_still_records_the_bounce() {
+    let return_path = encode_return_path(
+        "fan@example.com",
+        "jimmillerdrums.com",
+        "secret",
+        Utc::now(),
+    )
+    .unwrap();
+
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/dsn",
+        "From: MAILER-DAEMON@amazonses.com\r\nSubject: Delivery Status Notification (Failure)\r\n\r\nUser unknown",
+    );
+    let notice = mock!(aws_sdk_sesv2::Client::send_email)
+        .then_error(|| SendEmailError::unhandled("ThrottlingException: Rate limit exceeded"));
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&notice]),
+    };
+
+    let mut config = config();
+    config.srs_secret = Some("secret".to_string());
+    config.notify_bounced_senders = true;
+
+    let mut event = event_from("dsn", "<>");
+    event.records[0].ses.mail.destination = vec![return_path];
+    let response = process_ses_event(event, &context, &config).await.unwrap();
+    assert!(response["body"]
+        .as_str()
+        .unwrap()
+        .contains("\"notified\":false"));
+    assert_eq!(notice.num_calls(), 1);
+
+    let key = S3Key::try_from("bounces/dsn.json".to_string()).unwrap();
+    let record = context.store.get_object("bucket", &key).await.unwrap();
+    let record: BounceRecord = serde_json::from_slice(&record).unwrap();
+    assert_eq!(record.original_sender.as_deref(), Some("fan@example.com"));
+    assert!(record.notice_message_id.is_none());
+}
+
+#[tokio::test]
+async fn test_srs_address_is_forwarded_when_srs_is_disabled() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/lookalike",
+        "From: fan@example.com\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let context = context_expecting(store.clone(), "Subject: Hi");
+
+    let mut event = event("lookalike");
+    event.records[0].ses.mail.destination =
+        vec!["SRS0=abcd=TT=example.com=fan@jimmillerdrums.com".to_string()];
+    process_ses_event(event, &context, &config()).await.unwrap();
+
+    let tags = store.tags("bucket", "incoming/lookalike").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    assert!(!store.contains("bucket", "bounces/lookalike.json"));
+}
+
+#[tokio::test]
+async fn test_forwarded_message_is_stamped_with_receipt_verdicts() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/stamped",
+        "From: fan@example.com\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let context = context_expecting(
+        store.clone(),
+        "X-Processor-Verdicts: spam=pass; virus=pass; spf=pass; dkim=gray; dmarc=pass\r\n",
+    );
+
+    let result = process_ses_event(event_with_receipt("stamped"), &context, &config()).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_forwarded_message_carries_authentication_results() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/authenticated",
+        "Authentication-Results: jimmillerdrums.com; dmarc=pass\r\nFrom: Fan <fan@example.com>\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let context = context_expecting(
+        store.clone(),
+        "Authentication-Results: jimmillerdrums.com; spf=pass smtp.mailfrom=fan@example.com; dkim=none; dmarc=pass header.from=example.com\r\n",
+    );
+
+    let result = process_ses_event(event_with_receipt("authenticated"), &context, &config()).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_forwarded_message_starts_with_received_stamp() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/received",
+        "Received: from mx.example.com\r\nFrom: fan@example.com\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            req.content()
+                .and_then(|content| content.raw())
+                .map(|raw| {
+                    String::from_utf8_lossy(raw.data().as_ref()).starts_with(
+                        "Received: from amazonses.com by jimmillerdrums.com (email-processor) with SES id received; ",
+                    )
+                })
+                .unwrap_or(false)
+        })
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let result = process_ses_event(event("received"), &context, &config()).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_routed_message_is_tagged_with_route() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/booking",
+        "From: planner@example.com\r\nSubject: Wedding on June 6\r\n\r\nAre you available?",
+    );
+    let context = context_expecting(store.clone(), "Subject: [booking] Wedding on June 6\r\n");
+
+    let mut config = config();
+    config.routes = RoutingTable::from_json(
+        r#"[{"name": "booking", "addresses": ["booking"], "subject_tag": "[booking]"}]"#,
+    )
+    .unwrap();
+
+    let mut event = event("booking");
+    event.records[0].ses.mail.destination = vec!["booking@jimmillerdrums.com".to_string()];
+
+    let result = process_ses_event(event, &context, &config).await;
+    assert!(result.is_ok());
+
+    let tags = store.tags("bucket", "incoming/booking").unwrap();
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "booking");
+}
+
+#[tokio::test]
+async fn test_plus_addressed_message_keeps_its_subaddress() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/lead",
+        "From: leads@gigsalad.example\r\nX-Subaddress: spoofed\r\nSubject: New lead\r\n\r\nA client wants a drummer",
+    );
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            let raw = req
+                .content()
+                .and_then(|content| content.raw())
+                .map(|raw| String::from_utf8_lossy(raw.data().as_ref()).to_string())
+                .unwrap_or_default();
+            raw.contains("Subject: [lead:gigsalad] New lead\r\n")
+                && raw.contains("X-Subaddress: gigsalad\r\n")
+                && !raw.contains("spoofed")
+        })
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = config();
+    config.routes = RoutingTable::from_json(
+        r#"[
+            {"name": "info", "addresses": ["info"]},
+            {"name": "leads", "addresses": ["info+*"], "subject_tag": "[lead:{{subaddress}}]"}
+        ]"#,
+    )
+    .unwrap();
+
+    let mut event = event("lead");
+    event.records[0].ses.mail.destination = vec!["info+gigsalad@jimmillerdrums.com".to_string()];
+
+    let result = process_ses_event(event, &context, &config).await;
+    assert!(result.is_ok());
+    assert_eq!(ses_mock.num_calls(), 1);
+
+    let tags = store.tags("bucket", "incoming/lead").unwrap();
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "leads");
+}
+
+#[tokio::test]
+async fn test_unknown_recipient_goes_to_catch_all() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/guess",
+        "From: fan@example.com\r\nSubject: Is this the right address?\r\n\r\nHi",
+    );
+    let context = context_expecting(store.clone(), "Subject: [catch-all] Is this");
+
+    let mut config = config();
+    config.known_recipients = vec!["info".to_string()];
+    config.routes = RoutingTable::from_json(
+        r#"[{"name": "catch-all", "addresses": [], "subject_tag": "[catch-all]"}]"#,
+    )
+    .unwrap();
+
+    let mut event = event("guess");
+    event.records[0].ses.mail.destination = vec!["jim.miller@jimmillerdrums.com".to_string()];
+
+    assert!(process_ses_event(event, &context, &config).await.is_ok());
+
+    let tags = store.tags("bucket", "incoming/guess").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "catch-all");
+
+    let report = unknown_recipient_report(&context, &config, 7, 10, Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(report.total, 1);
+    assert_eq!(report.addresses[0].address, "jim.miller@jimmillerdrums.com");
+}
+
+#[tokio::test]
+async fn test_unknown_recipient_is_dropped_by_policy() {
+    let store = Arc::new(MemoryStore::new());
+    for id in ["spam1", "spam2"] {
+        store.insert(
+            "bucket",
+            &format!("incoming/{}", id),
+            "From: bulk@example.com\r\nSubject: Offer\r\n\r\nBuy now",
+        );
+    }
+    store.insert(
+        "bucket",
+        "incoming/lead",
+        "From: planner@example.com\r\nSubject: Wedding\r\n\r\nAre you free?",
+    );
+    let context = context_expecting(store.clone(), "Subject: Wedding");
+
+    let mut config = config();
+    config.processed_prefix = Some("processed".to_string());
+    config.known_recipients = vec!["info".to_string()];
+    config.unknown_recipient_policy = UnknownRecipientPolicy::Drop;
+
+    for (id, to) in [
+        ("spam1", "admin@jimmillerdrums.com"),
+        ("spam2", "Admin+x@jimmillerdrums.com"),
+        ("lead", "info+weddings@jimmillerdrums.com"),
+    ] {
+        let mut event = event(id);
+        event.records[0].ses.mail.destination = vec![to.to_string()];
+        assert!(process_ses_event(event, &context, &config).await.is_ok());
+    }
+
+    let tags = store.tags("bucket", "incoming/spam1").unwrap();
+    assert_eq!(
+        tags.get(SKIP_REASON_TAG).unwrap(),
+        "unknown-recipient-dropped"
+    );
+    assert!(!store.contains("bucket", "processed/spam1"));
+    let tags = store.tags("bucket", "incoming/lead").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+
+    // Subaddresses count towards the address as written
+    let report = unknown_recipient_report(&context, &config, 1, 10, Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(report.total, 2);
+    assert_eq!(report.addresses.len(), 2);
+}
+
+#[tokio::test]
+async fn test_attachments_are_archived_with_a_manifest() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/rider",
+        "From: venue@example.com\r\n\
+Subject: Rider\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Rider attached\r\n\
+--b\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename*=UTF-8''B%C3%BChne.pdf\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQ=\r\n\
+--b--\r\n",
+    );
+    let context = context_expecting(store.clone(), "Subject: Rider");
+
+    let mut config = config();
+    config.attachment_prefix = Some("attachments".to_string());
+
+    assert!(process_ses_event(event("rider"), &context, &config)
+        .await
+        .is_ok());
+
+    let key = "attachments/rider/1-B_hne.pdf";
+    assert_eq!(
+        store.content_type("bucket", key).as_deref(),
+        Some("application/pdf")
+    );
+    let metadata = store.metadata("bucket", key).unwrap();
+    assert_eq!(metadata.get("filename").unwrap(), "B%C3%BChne.pdf");
+    assert_eq!(metadata.get("sha256").unwrap().len(), 64);
+
+    let manifest = context
+        .store
+        .get_object(
+            "bucket",
+            &S3Key::try_from("attachments/rider/manifest.json".to_string()).unwrap(),
+        )
+        .await
+        .unwrap();
+    let manifest: AttachmentManifest = serde_json::from_slice(&manifest).unwrap();
+    assert_eq!(manifest.attachments.len(), 1);
+    assert_eq!(
+        manifest.attachments[0].filename.as_deref(),
+        Some("Bühne.pdf")
+    );
+    assert_eq!(manifest.attachments[0].size, 8);
+
+    let tags = store.tags("bucket", "incoming/rider").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+}
+
+const PHOTOS_WITH_EXECUTABLE: &str = "From: fan@example.com\r\n\
+Subject: Photos\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Photos from the show\r\n\
+--b\r\n\
+Content-Type: image/jpeg\r\n\
+Content-Disposition: attachment; filename=\"photo.jpg\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+TVqQAAMAAAAEAAAA\r\n\
+--b--\r\n";
+
+#[tokio::test]
+async fn test_dangerous_attachment_is_stripped() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert("bucket", "incoming/photos", PHOTOS_WITH_EXECUTABLE);
+    let context = context_expecting(
+        store.clone(),
+        "X-Processor-Stripped-Attachments: photo.jpg\r\n",
+    );
+
+    let mut config = config();
+    config.attachment_policy = AttachmentPolicy::Strip;
+    config.attachment_prefix = Some("attachments".to_string());
+
+    assert!(process_ses_event(event("photos"), &context, &config)
+        .await
+        .is_ok());
+
+    let tags = store.tags("bucket", "incoming/photos").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    // Only what was forwarded is archived
+    assert!(!store.contains("bucket", "attachments/photos/1-photo.jpg"));
+}
+
+#[tokio::test]
+async fn test_dangerous_attachment_quarantines_message() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert("bucket", "incoming/photos", PHOTOS_WITH_EXECUTABLE);
+    let context = context_expecting(store.clone(), "never sent");
+
+    let mut config = config();
+    config.attachment_policy = AttachmentPolicy::Quarantine;
+
+    assert!(process_ses_event(event("photos"), &context, &config)
+        .await
+        .is_ok());
+
+    assert!(store.contains("bucket", "quarantine/photos"));
+    let tags = store.tags("bucket", "incoming/photos").unwrap();
+    assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "dangerous-attachment");
+}
+
+#[tokio::test]
+async fn test_classified_message_is_routed_by_category() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/lesson",
+        "From: parent@example.com\r\nSubject: Drum lessons for my son\r\n\r\nDo you teach beginners?",
+    );
+    let context = context_expecting(
+        store.clone(),
+        "Subject: [lessons] Drum lessons for my son\r\n",
+    );
+
+    let mut config = config();
+    config.classifier = Some(
+        Classifier::from_json(
+            r#"[{"category": "lessons", "keywords": ["lessons", "teach"], "subject_tag": "[lessons]"}]"#,
+            0.5,
+        )
+        .unwrap(),
+    );
+
+    let result = process_ses_event(event("lesson"), &context, &config).await;
+    assert!(result.is_ok());
+
+    let tags = store.tags("bucket", "incoming/lesson").unwrap();
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "lessons");
+}
+
+#[tokio::test]
+async fn test_blocked_sender_is_quarantined() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/spam",
+        "From: Rank <rank@mail.seo-experts.example>\r\nSubject: Page one on Google\r\n\r\nGuaranteed",
+    );
+    let context = context_expecting(store.clone(), "never sent");
+
+    let mut config = config();
+    config.sender_rules = SenderRules::from_json(
+        r#"[{"action": "quarantine", "field": "domain", "pattern": "*.seo-experts.example"}]"#,
+    )
+    .unwrap();
+
+    let response = process_ses_event(event("spam"), &context, &config)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+    assert_eq!(body["reason"], "sender-quarantine");
+    assert_eq!(body["senderRule"]["field"], "domain");
+    assert_eq!(body["senderRule"]["value"], "mail.seo-experts.example");
+
+    assert!(store.contains("bucket", "quarantine/spam"));
+    let tags = store.tags("bucket", "incoming/spam").unwrap();
+    assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "sender-quarantine");
+}
+
+#[tokio::test]
+async fn test_allowed_sender_bypasses_store_only_policy() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/venue",
+        "Auto-Submitted: auto-generated\r\nFrom: bookings@venue.example\r\nSubject: Your set times\r\n\r\nDoors at 8",
+    );
+    let context = context_expecting(store.clone(), "X-Processor-Automated: auto-submitted\r\n");
+
+    let mut config = config();
+    config.auto_reply_policy = AutomatedMailPolicy::StoreOnly;
+    config.sender_rules = SenderRules::from_json(
+        r#"[{"action": "always-forward", "field": "from", "pattern": "*@venue.example"}]"#,
+    )
+    .unwrap();
+
+    let response = process_ses_event(event("venue"), &context, &config)
+        .await
+        .unwrap();
+    assert!(response["body"]
+        .as_str()
+        .unwrap()
+        .contains("always-forward"));
+
+    let tags = store.tags("bucket", "incoming/venue").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+}
+
+#[tokio::test]
+async fn test_sender_over_rate_limit_is_held_with_one_notice() {
+    let store = Arc::new(MemoryStore::new());
+    for id in ["form1", "form2", "form3", "form4"] {
+        store.insert(
+            "bucket",
+            &format!("incoming/{}", id),
+            "From: Contact Form <form@venue.example>\r\nSubject: New enquiry\r\n\r\nHello",
+        );
+    }
+
+    let forward = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| req.content().and_then(|c| c.raw()).is_some())
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let notice = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            req.content()
+                .and_then(|c| c.simple())
+                .and_then(|m| m.subject())
+                .is_some_and(|s| s.data() == "2 messages held from form@venue.example")
+        })
+        .then_output(|| SendEmailOutput::builder().message_id("notice-id").build());
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&forward, &notice]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = config();
+    config.rate_limit = Some(RateLimit {
+        per_address: Some(2),
+        per_domain: None,
+        window: Duration::minutes(60),
+    });
+
+    for id in ["form1", "form2", "form3", "form4"] {
+        process_ses_event(event(id), &context, &config)
+            .await
+            .unwrap();
+    }
+
+    let tags = store.tags("bucket", "incoming/form2").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    for id in ["form3", "form4"] {
+        let tags = store.tags("bucket", &format!("incoming/{}", id)).unwrap();
+        assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "rate-limited");
+        assert!(store.contains("bucket", &format!("held/{}", id)));
+    }
+
+    // Still inside the burst: nothing to report yet
+    assert!(flush_held_notices(&context, &config, false, Utc::now())
+        .await
+        .unwrap()
+        .is_empty());
+
+    let later = Utc::now() + Duration::minutes(61);
+    let notices = flush_held_notices(&context, &config, false, later)
+        .await
+        .unwrap();
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].held, 2);
+    assert_eq!(notice.num_calls(), 1);
+
+    // The held messages were reported once
+    assert!(flush_held_notices(&context, &config, true, later)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_unreadable_rate_limit_window_does_not_fail_delivery() {
+    let window = mock!(aws_sdk_s3::Client::get_object)
+        .match_requests(|req| req.key().unwrap().starts_with("rate-limit/"))
+        .then_http_response(|| {
+            HttpResponse::new(
+                StatusCode::try_from(403).unwrap(),
+                SdkBody::from("<Error><Code>AccessDenied</Code></Error>"),
+            )
+        });
+    let message = mock!(aws_sdk_s3::Client::get_object)
+        .match_requests(|req| req.key() == Some("incoming/form1"))
+        .then_output(|| {
+            GetObjectOutput::builder()
+                .body(
+                    SdkBody::from(
+                        "From: Contact Form <form@venue.example>\r\nSubject: New enquiry\r\n\r\nHello",
+                    )
+                    .into(),
+                )
+                .build()
+        });
+    let put =
+        mock!(aws_sdk_s3::Client::put_object).then_output(|| PutObjectOutput::builder().build());
+    let tagging = mock!(aws_sdk_s3::Client::put_object_tagging)
+        .then_output(|| PutObjectTaggingOutput::builder().build());
+    let s3_client = mock_client!(
+        aws_sdk_s3,
+        RuleMode::MatchAny,
+        [&window, &message, &put, &tagging]
+    );
+    let forward = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("forwarded-id")
+            .build()
+    });
+    let context = AppContext {
+        store: Arc::new(s3_client),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&forward]),
+    };
+
+    let mut config = config();
+    config.rate_limit = Some(RateLimit {
+        per_address: Some(2),
+        per_domain: None,
+        window: Duration::minutes(60),
+    });
+
+    process_ses_event(event("form1"), &context, &config)
+        .await
+        .unwrap();
+    assert!(window.num_calls() > 0);
+    assert_eq!(forward.num_calls(), 1);
+}
+
+#[tokio::test]
+async fn test_schedule_reports_a_burst_that_has_ended() {
+    let store = Arc::new(MemoryStore::new());
+    let held_at = Utc::now() - Duration::minutes(90);
+    store.insert(
+        "bucket",
+        "rate-limit/address/d57a5be8651375f99019e78cfc8876027e39592e0b09b6c1e5f11dfb231cc606.json",
+        serde_json::to_vec(&serde_json::json!({
+            "scope": "address",
+            "sender": "form@venue.example",
+            "arrivals": [held_at],
+            "held": ["form3"],
+            "last_held_at": held_at
+        }))
+        .unwrap(),
+    );
+    let notice = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            req.content()
+                .and_then(|c| c.simple())
+                .and_then(|m| m.subject())
+                .is_some_and(|s| s.data() == "1 message held from form@venue.example")
+        })
+        .then_output(|| SendEmailOutput::builder().message_id("notice-id").build());
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&notice]),
+    };
+    let mut config = config();
+    config.rate_limit = Some(RateLimit {
+        per_address: Some(2),
+        per_domain: None,
+        window: Duration::minutes(60),
+    });
+
+    let scheduled = serde_json::from_value(serde_json::json!({
+        "version": "0",
+        "detail-type": "Scheduled Event",
+        "source": "aws.events",
+        "detail": {}
+    }))
+    .unwrap();
+    let response = process_invocation(scheduled, &context, &config)
+        .await
+        .unwrap();
+    assert!(response["body"].as_str().unwrap().contains("notice-id"));
+    assert_eq!(notice.num_calls(), 1);
+}
+
+#[tokio::test]
+async fn test_auto_responder_replies_once_per_sender() {
+    let store = Arc::new(MemoryStore::new());
+    for id in ["gig1", "gig2"] {
+        store.insert(
+            "bucket",
+            &format!("incoming/{}", id),
+            "From: Pat <pat@example.com>\r\nMessage-ID: <gig@example.com>\r\nSubject: Wedding gig\r\n\r\nAre you free?",
+        );
+    }
+
+    let forward = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| req.content().and_then(|c| c.raw()).is_some())
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let reply = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            let Some(message) = req.content().and_then(|c| c.simple()) else {
+                return false;
+            };
+            req.from_email_address() == Some("booking@jimmillerdrums.com")
+                && req
+                    .destination()
+                    .is_some_and(|d| d.to_addresses() == ["pat@example.com"])
+                && message
+                    .subject()
+                    .is_some_and(|s| s.data() == "Auto: Wedding gig")
+                && message
+                    .headers()
+                    .iter()
+                    .any(|h| h.name() == "Auto-Submitted" && h.value() == "auto-replied")
+                && message
+                    .headers()
+                    .iter()
+                    .any(|h| h.name() == "In-Reply-To" && h.value() == "<gig@example.com>")
+        })
+        .then_output(|| SendEmailOutput::builder().message_id("reply-id").build());
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&forward, &reply]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = config();
+    config.auto_responder = Some(
+        AutoResponder::from_json(
+            r#"{"body": "Hi {{sender_name}}, I'm on tour and will reply soon."}"#,
+        )
+        .unwrap(),
+    );
+
+    let mut responses = Vec::new();
+    for id in ["gig1", "gig2"] {
+        let mut event = event_from(id, "pat@example.com");
+        event.records[0].ses.mail.destination = vec!["booking@jimmillerdrums.com".to_string()];
+        let response = process_ses_event(event, &context, &config).await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        responses.push(body);
+    }
+
+    assert_eq!(responses[0]["autoReplyMessageId"], "reply-id");
+    assert_eq!(responses[1]["autoReplySkipped"], "recently-replied");
+    assert_eq!(reply.num_calls(), 1);
+    assert_eq!(forward.num_calls(), 2);
+    assert!(store.contains("bucket", "auto-responder/pat@example.com.json"));
+}
+
+#[tokio::test]
+async fn test_forwarded_message_replies_through_relay_address() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/relayed",
+        "From: Fan <fan@example.com>\r\nSubject: Hi\r\n\r\nBody",
+    );
+    let context = context_expecting(store.clone(), "Reply-To: \"Fan\" <reply+");
+
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+
+    let result = process_ses_event(event("relayed"), &context, &config).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_reply_from_forwarding_mailbox_is_relayed() {
+    let relay_address =
+        encode_relay_address("fan@example.com", "jimmillerdrums.com", "secret").unwrap();
+
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/reply",
+        format!(
+            "Received: from mail-ed1.google.com by inbound-smtp.us-east-1.amazonaws.com\r\nReceived-SPF: pass (envelope-from=me@gmail.com)\r\nFrom: Me <me@gmail.com>\r\nTo: \"Fan\" <{}>\r\nCc: friend@example.org\r\nIn-Reply-To: <gig@example.com>\r\nSubject: Re: Hi\r\n\r\nSee you there",
+            relay_address
+        ),
+    );
+    let relay = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            let raw = req
+                .content()
+                .and_then(|content| content.raw())
+                .map(|raw| String::from_utf8_lossy(raw.data().as_ref()).to_string())
+                .unwrap_or_default();
+            req.from_email_address() == Some("booking@jimmillerdrums.com")
+                && raw.contains("From: booking@jimmillerdrums.com\r\n")
+                && raw.contains("To: fan@example.com\r\n")
+                && raw.contains("In-Reply-To: <gig@example.com>\r\n")
+                && !raw.contains("gmail.com")
+                && !raw.contains("friend@example.org")
+        })
+        .then_output(|| SendEmailOutput::builder().message_id("relayed-id").build());
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&relay]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+    config.relay_from_email = Some("booking@jimmillerdrums.com".to_string());
+
+    let mut event = event_with_receipt("reply");
+    event.records[0].ses.mail.source = "me@gmail.com".to_string();
+    event.records[0].ses.mail.destination = vec![relay_address.clone()];
+    let response = process_ses_event(event, &context, &config).await.unwrap();
+    let body: serde_json::Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+    assert_eq!(body["relayedMessageId"], "relayed-id");
+    assert_eq!(body["correspondent"], "fan@example.com");
+
+    let tags = store.tags("bucket", "incoming/reply").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+    assert_eq!(tags.get(ROUTE_TAG).unwrap(), "relay");
+
+    // Anyone else writing to the relay address is refused
+    store.insert(
+        "bucket",
+        "incoming/spoof",
+        format!(
+            "From: someone@example.net\r\nTo: {}\r\nSubject: Hi\r\n\r\nBody",
+            relay_address
+        ),
+    );
+    let mut event = event_with_receipt("spoof");
+    event.records[0].ses.mail.destination = vec![relay_address];
+    let response = process_ses_event(event, &context, &config).await.unwrap();
+    assert!(response["body"]
+        .as_str()
+        .unwrap()
+        .contains("relay-unauthorized"));
+    assert_eq!(relay.num_calls(), 1);
+}
+
+#[tokio::test]
+async fn test_signed_reply_address_stands_in_when_alias_cannot_be_stored() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/gig",
+        "From: Fan <fan@example.com>\r\nMessage-ID: <gig@example.com>\r\nSubject: Gig\r\n\r\nAre you free?",
+    );
+    let id = alias_id(
+        "secret",
+        "fan@example.com",
+        "booking@jimmillerdrums.com",
+        Some("<gig@example.com>"),
+    );
+    store.insert("bucket", &format!("aliases/{}.json", id), "not json");
+
+    let signed = encode_relay_address("fan@example.com", "jimmillerdrums.com", "secret").unwrap();
+    let forward = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(move |req| {
+            let raw = req
+                .content()
+                .and_then(|content| content.raw())
+                .map(|raw| String::from_utf8_lossy(raw.data().as_ref()).to_string())
+                .unwrap_or_default();
+            raw.contains(&format!("Reply-To: \"Fan\" <{}>\r\n", signed))
+                && !raw.contains("Reply-To: fan@example.com")
+        })
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let context = AppContext {
+        store: store.clone(),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&forward]),
+    };
+
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+
+    let mut event = event_from("gig", "fan@example.com");
+    event.records[0].ses.mail.destination = vec!["booking@jimmillerdrums.com".to_string()];
+    process_ses_event(event, &context, &config).await.unwrap();
+    assert_eq!(forward.num_calls(), 1);
+}
+
+#[tokio::test]
+async fn test_reply_through_conversation_alias_threads_with_original() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/gig",
+        "From: Fan <fan@example.com>\r\nMessage-ID: <gig@example.com>\r\nSubject: Gig\r\n\r\nAre you free?",
+    );
+    let forward = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            req.content()
+                .and_then(|content| content.raw())
+                .map(|raw| {
+                    String::from_utf8_lossy(raw.data().as_ref()).contains("To: me@gmail.com\r\n")
+                })
+                .unwrap_or(false)
+        })
+        .then_output(|| {
+            SendEmailOutput::builder()
+                .message_id("forwarded-id")
+                .build()
+        });
+    let relay = mock!(aws_sdk_sesv2::Client::send_email)
+        .match_requests(|req| {
+            let raw = req
+                .content()
+                .and_then(|content| content.raw())
+                .map(|raw| String::from_utf8_lossy(raw.data().as_ref()).to_string())
+                .unwrap_or_default();
+            req.from_email_address() == Some("booking@jimmillerdrums.com")
+                && raw.contains("To: fan@example.com\r\n")
+                && raw.contains("In-Reply-To: <gig@example.com>\r\n")
+                && raw.contains("References: <gig@example.com>\r\n")
+                && !raw.contains("amazonses.com")
+        })
+        .then_output(|| SendEmailOutput::builder().message_id("relayed-id").build());
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&forward, &relay]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = config();
+    config.relay_secret = Some("secret".to_string());
+
+    let mut event = event_from("gig", "fan@example.com");
+    event.records[0].ses.mail.destination = vec!["booking@jimmillerdrums.com".to_string()];
+    process_ses_event(event, &context, &config).await.unwrap();
+
+    let aliases = list_aliases(&context, &config).await.unwrap();
+    assert_eq!(aliases.len(), 1);
+    let alias = aliases[0].alias.clone();
+
+    // The mailbox replies to the alias, threading on our forwarded copy
+    store.insert(
+        "bucket",
+        "incoming/reply",
+        format!(
+            "From: me@gmail.com\r\nTo: \"Fan\" <{}>\r\nIn-Reply-To: <forwarded-id@email.amazonses.com>\r\nReferences: <forwarded-id@email.amazonses.com>\r\nSubject: Re: Gig\r\n\r\nYes!",
+            alias
+        ),
+    );
+    let mut event = event_with_receipt("reply");
+    event.records[0].ses.mail.source = "me@gmail.com".to_string();
+    event.records[0].ses.mail.destination = vec![alias.clone()];
+    let response = process_ses_event(event, &context, &config).await.unwrap();
+    assert!(response["body"].as_str().unwrap().contains("relayed-id"));
+    assert_eq!(relay.num_calls(), 1);
+
+    // Once revoked the alias no longer relays
+    email_processor::revoke_alias(&context, &config, &alias, Utc::now())
+        .await
+        .unwrap();
+    let mut event = event_with_receipt("reply");
+    event.records[0].ses.mail.source = "me@gmail.com".to_string();
+    event.records[0].ses.mail.destination = vec![alias];
+    let response = process_ses_event(event, &context, &config).await.unwrap();
+    assert!(response["body"].as_str().unwrap().contains("relay-revoked"));
+    assert_eq!(relay.num_calls(), 1);
+}
+
+#[tokio::test]
+async fn test_processed_mail_is_indexed() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/gig",
+        "From: \"Pat\" <pat@venue.example>\r\nSubject: Friday gig\r\n\r\nSee you at 8",
+    );
+    store.insert(
+        "bucket",
+        "incoming/pitch",
+        "From: growth@seo-experts.example\r\nSubject: Rank #1 today\r\n\r\nBuy now",
+    );
+    let context = context_expecting(store.clone(), "Subject: Friday gig");
+
+    let mut config = config();
+    config.index_prefix = Some("index".to_string());
+    config.sender_rules = SenderRules::from_json(
+        r#"[{"action": "drop", "field": "domain", "pattern": "seo-experts.example"}]"#,
+    )
+    .unwrap();
+
+    assert!(
+        process_ses_event(event_with_receipt("gig"), &context, &config)
+            .await
+            .is_ok()
+    );
+    assert!(process_ses_event(event("pitch"), &context, &config)
+        .await
+        .is_ok());
+
+    let today = Utc::now().date_naive().format("%Y-%m-%d");
+    let body = context
+        .store
+        .get_object(
+            "bucket",
+            &S3Key::try_from(format!("index/date={}/gig.jsonl", today)).unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&body).lines().count(), 1);
+    let record: IndexRecord = serde_json::from_slice(&body).unwrap();
+    assert_eq!(record.from.as_deref(), Some("pat@venue.example"));
+    assert_eq!(record.subject.as_deref(), Some("Friday gig"));
+    assert_eq!(record.outcome, "forwarded");
+    assert_eq!(
+        record.verdicts.as_deref(),
+        Some("spam=pass; virus=pass; spf=pass; dkim=gray; dmarc=pass")
+    );
+
+    let now = Utc::now();
+    let all = query_index(&context, &config, &IndexQuery::default(), now)
+        .await
+        .unwrap();
+    assert_eq!(all.len(), 2);
+
+    let dropped = IndexQuery {
+        outcome: Some("sender-drop".to_string()),
+        window: TimeWindow {
+            since: Some(now - Duration::days(1)),
+            until: None,
+        },
+        ..Default::default()
+    };
+    let records = query_index(&context, &config, &dropped, now).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].message_id, "pitch");
+    assert_eq!(records[0].outcome, "skipped");
+
+    let by_sender = IndexQuery {
+        sender: Some("@VENUE.example".to_string()),
+        subject: Some("gig".to_string()),
+        ..Default::default()
+    };
+    let records = query_index(&context, &config, &by_sender, now)
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].message_id, "gig");
+}
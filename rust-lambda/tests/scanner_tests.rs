@@ -0,0 +1,230 @@
+use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
+use aws_smithy_mocks::{mock, mock_client, RuleMode};
+use email_processor::{
+    process_ses_event, AppContext, ClamdScanner, Config, MemoryStore, ScanTarget, ScanVerdict,
+    ScannerAddress, ScannerError, SesEvent, SesMail, SesMessage, SesRecord, SKIP_REASON_TAG,
+    STATUS_TAG,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// The EICAR test string, split so scanners do not flag this file
+const EICAR: &str = concat!(
+    "X5O!P%@AP[4\\PZX54(P^)7CC)7}$",
+    "EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*"
+);
+
+/// Answer one INSTREAM session the way clamd does, finding the EICAR test
+/// string and refusing streams over `max_len` bytes
+async fn serve_instream<S>(mut stream: S, max_len: usize)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut command = [0u8; 10];
+    stream.read_exact(&mut command).await.unwrap();
+    assert_eq!(&command, b"zINSTREAM\0");
+
+    let mut data = Vec::new();
+    loop {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length).await.unwrap();
+        let length = u32::from_be_bytes(length) as usize;
+        if length == 0 {
+            break;
+        }
+        let mut chunk = vec![0u8; length];
+        stream.read_exact(&mut chunk).await.unwrap();
+        data.extend_from_slice(&chunk);
+    }
+
+    let reply: &[u8] = if data.len() > max_len {
+        b"INSTREAM size limit exceeded. ERROR\0"
+    } else if String::from_utf8_lossy(&data).contains("EICAR-STANDARD-ANTIVIRUS-TEST-FILE") {
+        b"stream: Win.Test.EICAR_HDB-1 FOUND\0"
+    } else {
+        b"stream: OK\0"
+    };
+    stream.write_all(reply).await.unwrap();
+}
+
+/// Stub clamd on a local TCP port, answering `sessions` scans
+async fn stub_clamd(sessions: usize, max_len: usize) -> ClamdScanner {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        for _ in 0..sessions {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_instream(stream, max_len).await;
+        }
+    });
+
+    ClamdScanner {
+        address: ScannerAddress::Tcp(address),
+        target: ScanTarget::Message,
+        timeout: Duration::from_secs(5),
+    }
+}
+
+fn message_with_attachment(body: &str) -> String {
+    format!(
+        "From: fan@example.com\r\n\
+Subject: Files\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Files attached\r\n\
+--b\r\n\
+Content-Type: application/octet-stream\r\n\
+Content-Disposition: attachment; filename=\"setlist.txt\"\r\n\
+\r\n\
+{}\r\n\
+--b--\r\n",
+        body
+    )
+}
+
+#[tokio::test]
+async fn test_scan_over_tcp() {
+    let scanner = stub_clamd(3, 1024 * 1024).await;
+
+    assert_eq!(
+        scanner.scan(b"Doors at 8").await.unwrap(),
+        ScanVerdict::Clean
+    );
+    assert_eq!(
+        scanner.scan(EICAR.as_bytes()).await.unwrap(),
+        ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string())
+    );
+
+    // Large streams go out in several chunks
+    let large = vec![b'a'; 200 * 1024];
+    assert_eq!(scanner.scan(&large).await.unwrap(), ScanVerdict::Clean);
+}
+
+#[tokio::test]
+async fn test_scan_errors() {
+    let scanner = stub_clamd(1, 16).await;
+    assert!(matches!(
+        scanner.scan(b"more than sixteen bytes").await,
+        Err(ScannerError::Scanner(message)) if message == "INSTREAM size limit exceeded."
+    ));
+
+    // Nothing listening
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    drop(listener);
+    let scanner = ClamdScanner {
+        address: ScannerAddress::Tcp(address),
+        target: ScanTarget::Message,
+        timeout: Duration::from_secs(5),
+    };
+    assert!(matches!(
+        scanner.scan(b"hello").await,
+        Err(ScannerError::Io(_))
+    ));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_scan_attachments_over_unix_socket() {
+    let dir = std::env::temp_dir().join(format!("clamd-stub-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("clamd.sock");
+    let _ = std::fs::remove_file(&path);
+
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_instream(stream, 1024 * 1024).await;
+        }
+    });
+
+    let scanner = ClamdScanner {
+        address: ScannerAddress::Unix(path.clone()),
+        target: ScanTarget::Attachments,
+        timeout: Duration::from_secs(5),
+    };
+    let finding = scanner
+        .scan_message(message_with_attachment(EICAR).as_bytes())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(finding.signature, "Win.Test.EICAR_HDB-1");
+    assert_eq!(finding.attachment.as_deref(), Some("setlist.txt"));
+
+    assert_eq!(
+        scanner
+            .scan_message(message_with_attachment("1. Superstition").as_bytes())
+            .await
+            .unwrap(),
+        None
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn event(message_id: &str) -> SesEvent {
+    SesEvent {
+        records: vec![SesRecord {
+            ses: SesMessage {
+                mail: SesMail {
+                    message_id: message_id.to_string(),
+                    source: "fan@example.com".to_string(),
+                    destination: vec!["info@jimmillerdrums.com".to_string()],
+                },
+                receipt: None,
+            },
+        }],
+    }
+}
+
+#[tokio::test]
+async fn test_infected_message_is_quarantined() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert(
+        "bucket",
+        "incoming/infected",
+        message_with_attachment(EICAR),
+    );
+    store.insert(
+        "bucket",
+        "incoming/clean",
+        message_with_attachment("1. Superstition"),
+    );
+
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("forwarded-id")
+            .build()
+    });
+    let ses_client = mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]);
+    let context = AppContext {
+        store: store.clone(),
+        ses_client,
+    };
+
+    let mut config = Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "me@gmail.com".to_string(),
+    );
+    config.scanner = Some(stub_clamd(2, 1024 * 1024).await);
+
+    for id in ["infected", "clean"] {
+        assert!(process_ses_event(event(id), &context, &config)
+            .await
+            .is_ok());
+    }
+    assert_eq!(ses_mock.num_calls(), 1);
+
+    assert!(store.contains("bucket", "quarantine/infected"));
+    let tags = store.tags("bucket", "incoming/infected").unwrap();
+    assert_eq!(tags.get(SKIP_REASON_TAG).unwrap(), "infected");
+    let tags = store.tags("bucket", "incoming/clean").unwrap();
+    assert_eq!(tags.get(STATUS_TAG).unwrap(), "forwarded");
+}
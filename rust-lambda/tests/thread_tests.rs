@@ -0,0 +1,209 @@
+use aws_sdk_s3::operation::put_object::PutObjectOutput;
+use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
+use aws_smithy_mocks::{mock, mock_client, RuleMode};
+use aws_smithy_runtime_api::http::{Response as HttpResponse, StatusCode};
+use aws_smithy_types::body::SdkBody;
+use chrono::Utc;
+use email_processor::{
+    conversation_mbox, load_thread, process_ses_event, record_thread, thread_id, AppContext,
+    Config, MemoryStore, MessageId, S3Key, SesEvent, SesMail, SesMessage, SesRecord,
+};
+use std::sync::Arc;
+
+fn event(message_id: &str) -> SesEvent {
+    SesEvent {
+        records: vec![SesRecord {
+            ses: SesMessage {
+                mail: SesMail {
+                    message_id: message_id.to_string(),
+                    source: "pat@venue.example".to_string(),
+                    destination: vec!["booking@jimmillerdrums.com".to_string()],
+                },
+                receipt: None,
+            },
+        }],
+    }
+}
+
+fn context(store: Arc<MemoryStore>) -> AppContext {
+    let ses_mock = mock!(aws_sdk_sesv2::Client::send_email).then_output(|| {
+        SendEmailOutput::builder()
+            .message_id("forwarded-id")
+            .build()
+    });
+    AppContext {
+        store,
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, [&ses_mock]),
+    }
+}
+
+fn config() -> Config {
+    let mut config = Config::new(
+        "bucket".to_string(),
+        "incoming".to_string(),
+        "me@gmail.com".to_string(),
+    );
+    config.thread_prefix = Some("threads".to_string());
+    config
+}
+
+const INQUIRY: &str = "Message-ID: <1@venue.example>\r\n\
+From: Pat <pat@venue.example>\r\n\
+Date: Mon, 01 Jun 2026 09:00:00 +0000\r\n\
+Subject: Friday\r\n\
+\r\n\
+Are you free on Friday?\r\n";
+
+const QUOTE: &str = "Message-ID: <2@jimmillerdrums.com>\r\n\
+In-Reply-To: <1@venue.example>\r\n\
+References: <1@venue.example>\r\n\
+From: Jim <jim@jimmillerdrums.com>\r\n\
+Date: Mon, 01 Jun 2026 12:00:00 +0000\r\n\
+Subject: Re: Friday\r\n\
+\r\n\
+Yes.\r\n\
+From the quote: $400.\r\n";
+
+/// Names only its direct parent, as some clients do
+const CONFIRMATION: &str = "Message-ID: <3@venue.example>\r\n\
+In-Reply-To: <2@jimmillerdrums.com>\r\n\
+From: Pat <pat@venue.example>\r\n\
+Date: Tue, 02 Jun 2026 08:00:00 +0000\r\n\
+Subject: Re: Re: Friday\r\n\
+\r\n\
+Booked!\r\n";
+
+const UNRELATED: &str = "Message-ID: <9@fan.example>\r\n\
+From: fan@fan.example\r\n\
+Date: Mon, 01 Jun 2026 10:00:00 +0000\r\n\
+Subject: Great show\r\n\
+\r\n\
+Loved it\r\n";
+
+#[tokio::test]
+async fn test_conversation_is_threaded_and_exported_in_order() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert("bucket", "incoming/inquiry", INQUIRY);
+    store.insert("bucket", "incoming/quote", QUOTE);
+    store.insert("bucket", "incoming/confirmation", CONFIRMATION);
+    store.insert("bucket", "incoming/unrelated", UNRELATED);
+    let context = context(store.clone());
+    let config = config();
+
+    // The reply is processed before the message it answers
+    for id in ["quote", "unrelated", "confirmation", "inquiry"] {
+        assert!(process_ses_event(event(id), &context, &config)
+            .await
+            .is_ok());
+    }
+
+    let thread = load_thread(&context, &config, "threads", "3@venue.example")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(thread.thread_id, thread_id("<1@venue.example>"));
+    assert_eq!(thread.root.as_deref(), Some("<1@venue.example>"));
+    let stored: Vec<&str> = thread
+        .messages
+        .iter()
+        .map(|message| message.stored_id.as_str())
+        .collect();
+    assert_eq!(stored, vec!["inquiry", "quote", "confirmation"]);
+
+    let by_id = load_thread(&context, &config, "threads", &thread.thread_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(by_id, thread);
+
+    let mbox =
+        String::from_utf8(conversation_mbox(&context, &config, &thread).await.unwrap()).unwrap();
+    let separators: Vec<&str> = mbox
+        .lines()
+        .filter(|line| line.starts_with("From "))
+        .collect();
+    assert_eq!(
+        separators,
+        vec![
+            "From pat@venue.example Mon Jun  1 09:00:00 2026",
+            "From jim@jimmillerdrums.com Mon Jun  1 12:00:00 2026",
+            "From pat@venue.example Tue Jun  2 08:00:00 2026",
+        ]
+    );
+    assert!(mbox.contains("\n>From the quote: $400.\n"));
+    assert!(!mbox.contains("Great show"));
+
+    assert!(
+        load_thread(&context, &config, "threads", "<missing@example.com>")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_expired_messages_are_left_out_of_the_mbox() {
+    let store = Arc::new(MemoryStore::new());
+    store.insert("bucket", "incoming/inquiry", INQUIRY);
+    store.insert("bucket", "incoming/quote", QUOTE);
+    let context = context(store.clone());
+    let config = config();
+
+    for id in ["inquiry", "quote"] {
+        assert!(process_ses_event(event(id), &context, &config)
+            .await
+            .is_ok());
+    }
+    context
+        .store
+        .delete_object(
+            "bucket",
+            &S3Key::try_from("incoming/inquiry".to_string()).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let thread = load_thread(&context, &config, "threads", "<1@venue.example>")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(thread.messages.len(), 2);
+    let mbox =
+        String::from_utf8(conversation_mbox(&context, &config, &thread).await.unwrap()).unwrap();
+    assert_eq!(mbox.matches("\nMessage-ID: ").count(), 1);
+    assert!(mbox.contains("Message-ID: <2@jimmillerdrums.com>"));
+}
+
+#[tokio::test]
+async fn test_first_message_is_threaded_against_s3() {
+    // A bucket the role may list answers lookups of unseen ids with 404
+    let missing = mock!(aws_sdk_s3::Client::get_object).then_http_response(|| {
+        HttpResponse::new(
+            StatusCode::try_from(404).unwrap(),
+            SdkBody::from("<Error><Code>NoSuchKey</Code></Error>"),
+        )
+    });
+    let put =
+        mock!(aws_sdk_s3::Client::put_object).then_output(|| PutObjectOutput::builder().build());
+    let s3_client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&missing, &put]);
+    let context = AppContext {
+        store: Arc::new(s3_client),
+        ses_client: mock_client!(aws_sdk_sesv2, RuleMode::MatchAny, []),
+    };
+
+    let stored_id = MessageId::try_from("inquiry".to_string()).unwrap();
+    let thread = record_thread(
+        &context,
+        &config(),
+        "threads",
+        &stored_id,
+        INQUIRY.as_bytes(),
+        Utc::now(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(thread.thread_id, thread_id("<1@venue.example>"));
+    assert_eq!(thread.messages.len(), 1);
+    // The thread record and the link from its Message-ID
+    assert_eq!(put.num_calls(), 2);
+}